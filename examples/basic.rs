@@ -1,8 +1,9 @@
 use dotenv::dotenv;
 use spotify_oauth::{
-    convert_callback_into_token, generate_random_string, SpotifyAuth, SpotifyCallback, SpotifyScope,
+    generate_random_string, AppClient, Endpoints, RandomStateGenerator, SpotifyAuth,
+    SpotifyCallback, SpotifyScope, SurfClient,
 };
-use std::{env, error::Error, io::stdin, str::FromStr};
+use std::{env, error::Error, io::stdin, str::FromStr, sync::Arc};
 use url::Url;
 
 #[async_std::main]
@@ -26,9 +27,12 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         client_secret,
         response_type,
         redirect_uri,
+        redirect_uris: Vec::new(),
         state,
+        state_generator: Arc::new(RandomStateGenerator),
         scope,
         show_dialog,
+        endpoints: Endpoints::default(),
     };
     let auth_url = auth.authorize_url()?;
 
@@ -41,13 +45,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
 
     let callback = SpotifyCallback::from_str(buffer.trim())?;
     // Convert the given callback URL into a token.
-    let token = convert_callback_into_token(
-        callback,
-        auth.client_id,
-        auth.client_secret,
-        auth.redirect_uri,
-    )
-    .await?;
+    let app = AppClient::from(&auth);
+    let token = app
+        .exchange_callback(&SurfClient::new(), &callback, &auth.redirect_uri)
+        .await?;
 
     println!("Token: {:#?}", token);
 
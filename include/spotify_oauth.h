@@ -0,0 +1,57 @@
+/*
+ * C API of spotify-oauth, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * All strings are UTF-8 and NUL terminated. Strings and objects returned by the
+ * library are owned by the caller and must be released with the matching
+ * spotify_oauth_*_free function. Failing functions return NULL, the reason can
+ * be read with spotify_oauth_last_error(). Network calls block.
+ */
+
+#ifndef SPOTIFY_OAUTH_H
+#define SPOTIFY_OAUTH_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SpotifyAuth SpotifyAuth;
+typedef struct SpotifyToken SpotifyToken;
+
+/* The message of the last error on the calling thread, or NULL. */
+char *spotify_oauth_last_error(void);
+
+/* `scopes` is a space separated list, e.g. "streaming user-read-email". */
+SpotifyAuth *spotify_oauth_auth_new(const char *client_id,
+                                    const char *client_secret,
+                                    const char *redirect_uri,
+                                    const char *scopes,
+                                    bool show_dialog);
+void spotify_oauth_auth_free(SpotifyAuth *auth);
+
+char *spotify_oauth_authorize_url(const SpotifyAuth *auth);
+
+/* Verifies the state of the callback and exchanges its code for a token. */
+SpotifyToken *spotify_oauth_exchange_callback(const SpotifyAuth *auth,
+                                              const char *callback_url);
+SpotifyToken *spotify_oauth_refresh(const SpotifyAuth *auth,
+                                    const char *refresh_token);
+
+char *spotify_oauth_token_access_token(const SpotifyToken *token);
+/* NULL if no refresh token was issued. */
+char *spotify_oauth_token_refresh_token(const SpotifyToken *token);
+/* 0 if unknown. */
+int64_t spotify_oauth_token_expires_at(const SpotifyToken *token);
+void spotify_oauth_token_free(SpotifyToken *token);
+
+void spotify_oauth_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SPOTIFY_OAUTH_H */
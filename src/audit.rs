@@ -0,0 +1,194 @@
+//! Audit trail of token refreshes.
+//!
+//! Every refresh done through [`refresh_access_token_audited`] is recorded as an [`AuditEvent`] in
+//! an [`AuditSink`], stating which account was refreshed, by which process, when and with which outcome.
+
+use crate::{
+    refresh_access_token_with, Clock, Endpoints, HttpClient, SpotifyError, SpotifyResult,
+    SpotifyToken, SystemClock,
+};
+use serde::Serialize;
+use std::{io::Write, sync::Mutex};
+
+/// The outcome of an audited token refresh.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// A new access token was issued.
+    Refreshed,
+    /// The refresh token was rejected, the account has to authorize the application again.
+    NeedsReauthorization,
+    /// The refresh failed for another reason.
+    Failed {
+        /// The error message, with credentials masked.
+        error: String,
+    },
+}
+
+/// A single entry of the audit trail.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// The account the token belongs to, as named by the application.
+    pub account: String,
+    /// The process which did the refresh, e.g. ``worker[4242]``.
+    pub process: String,
+    /// The unix timestamp of the refresh.
+    pub timestamp: i64,
+    /// The outcome of the refresh.
+    #[serde(flatten)]
+    pub outcome: AuditOutcome,
+}
+
+impl AuditEvent {
+    /// The name of the executable and the ID of the current process, e.g. ``worker[4242]``.
+    pub fn current_process() -> String {
+        let name = std::env::current_exe()
+            .ok()
+            .and_then(|x| x.file_stem().map(|x| x.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        format!("{}[{}]", name, std::process::id())
+    }
+}
+
+/// A destination for [`AuditEvent`]s, e.g. a database table or a compliance log.
+///
+/// Implemented for closures taking an ``&AuditEvent``.
+pub trait AuditSink: Send + Sync {
+    /// Record an event.
+    fn record(&self, event: &AuditEvent);
+}
+
+impl<F: Fn(&AuditEvent) + Send + Sync> AuditSink for F {
+    fn record(&self, event: &AuditEvent) {
+        self(event)
+    }
+}
+
+/// An [`AuditSink`] writing every event as a line of JSON.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::JsonLinesAuditSink;
+/// let file = std::fs::OpenOptions::new().create(true).append(true).open("refresh-audit.jsonl").unwrap();
+/// let sink = JsonLinesAuditSink::new(file);
+/// ```
+#[derive(Debug)]
+pub struct JsonLinesAuditSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesAuditSink<W> {
+    /// Write the events to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonLinesAuditSink<W> {
+    fn record(&self, event: &AuditEvent) {
+        let mut writer = self.writer.lock().unwrap();
+
+        // The audit trail must not break refreshing, write errors are dropped.
+        if let Ok(line) = serde_json::to_string(event) {
+            let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+        }
+    }
+}
+
+/// Refresh an access token like [`refresh_access_token_with`] and record the outcome in the audit sink.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{refresh_access_token_audited, AuditEvent, Endpoints, SurfClient};
+/// # #[async_std::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// let sink = |event: &AuditEvent| println!("{:?}", event);
+/// let token = refresh_access_token_audited(&SurfClient::new(), &Endpoints::default(), &sink, "alice", "NgAagAHfVxDkSvCUm_SHo".into(), "00000000000".into(), "secret".into()).await?;
+/// # Ok(()) }
+/// ```
+pub async fn refresh_access_token_audited<C: HttpClient + ?Sized>(
+    http: &C,
+    endpoints: &Endpoints,
+    sink: &dyn AuditSink,
+    account: &str,
+    refresh_token: String,
+    client_id: String,
+    client_secret: String,
+) -> SpotifyResult<SpotifyToken> {
+    let result =
+        refresh_access_token_with(http, endpoints, refresh_token, client_id, client_secret).await;
+
+    let outcome = match result {
+        Ok(_) => AuditOutcome::Refreshed,
+        Err(SpotifyError::NeedsReauthorization { .. }) => AuditOutcome::NeedsReauthorization,
+        Err(ref err) => AuditOutcome::Failed {
+            error: crate::util::redact(&err.to_string()),
+        },
+    };
+
+    sink.record(&AuditEvent {
+        account: account.to_string(),
+        process: AuditEvent::current_process(),
+        timestamp: SystemClock.now().timestamp(),
+        outcome,
+    });
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHttpClient, MockResponse};
+
+    #[async_std::test]
+    async fn test_audit_records_outcomes() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRK", None, "streaming"))
+            .with(MockResponse::oauth_error(
+                "invalid_grant",
+                "Refresh token revoked",
+            ))
+            .with(MockResponse::transport_error("dns failure"));
+        let sink = JsonLinesAuditSink::new(Vec::new());
+
+        for _ in 0..3 {
+            let _ = refresh_access_token_audited(
+                &http,
+                &Endpoints::default(),
+                &sink,
+                "alice",
+                "NgAagA".into(),
+                "id".into(),
+                "secret".into(),
+            )
+            .await;
+        }
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let events: Vec<serde_json::Value> = output
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["account"], "alice");
+        assert_eq!(events[0]["outcome"], "refreshed");
+        assert_eq!(events[1]["outcome"], "needs_reauthorization");
+        assert_eq!(events[2]["outcome"], "failed");
+        assert_eq!(events[2]["error"], "Transport failure: dns failure");
+        assert!(events[2]["process"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("[{}]", std::process::id())));
+    }
+}
@@ -1,7 +1,143 @@
-use crate::{generate_random_string, SpotifyResult, SpotifyScope, UrlError, SPOTIFY_AUTH_URL};
+#[cfg(feature = "rand")]
+use crate::Encryptor;
+#[cfg(feature = "rand")]
+use crate::UrlError;
+#[cfg(feature = "rand")]
+use crate::{generate_random_string, generate_random_string_with};
+use crate::{Endpoints, SpotifyError, SpotifyResult, SpotifyScope};
+#[cfg(feature = "rand")]
+use rand::Rng;
+#[cfg(feature = "rand")]
 use snafu::ResultExt;
-use std::string::ToString;
-use url::Url;
+use std::sync::Arc;
+use url::{form_urlencoded, Url};
+
+/// Generates the ``state`` of authorizations.
+///
+/// Implement it to embed e.g. a tenant ID into the state or to draw from a hardware RNG, the
+/// state must stay unguessable. Closures returning a ``String`` implement it as well.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{generate_random_string, SpotifyAuth, SpotifyScope};
+/// let auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false)
+///     .with_state_generator(|| format!("tenant-7.{}", generate_random_string(20)));
+/// # assert!(auth.state.starts_with("tenant-7."));
+/// ```
+pub trait StateGenerator: Send + Sync {
+    /// A new state.
+    fn generate_state(&self) -> String;
+}
+
+impl<F> StateGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn generate_state(&self) -> String {
+        self()
+    }
+}
+
+/// The default [`StateGenerator`], drawing random alphanumeric states of 20 characters.
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+#[cfg(feature = "rand")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomStateGenerator;
+
+#[cfg(feature = "rand")]
+impl StateGenerator for RandomStateGenerator {
+    fn generate_state(&self) -> String {
+        generate_random_string(20)
+    }
+}
+
+/// Seals a payload into the state with an [`Encryptor`], e.g. an internal user ID or the path to
+/// return to after the login.
+///
+/// The payload is encrypted together with a random nonce, so the state stays unguessable and
+/// differs on every call even for the same payload. Use an authenticated cipher such as
+/// XChaCha20-Poly1305 or AES-GCM, as PASETO and Branca do: the payload is then confidential, and
+/// [`StateSealer::verify`] rejects a state which was tampered with.
+///
+/// It is not a [`StateGenerator`], since the encryptor may fail, e.g. when it calls a KMS. Assign
+/// the state of [`StateSealer::seal`] to ``auth.state`` instead. The state of the callback still
+/// has to match the one of the pending authorization, ``verify`` only recovers the payload.
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{Encryptor, SpotifyResult, StateSealer};
+/// struct Aead;
+///
+/// impl Encryptor for Aead {
+///     fn encrypt(&self, plaintext: &[u8]) -> SpotifyResult<Vec<u8>> {
+///         // Encrypt with an authenticated cipher.
+/// #       Ok(plaintext.iter().rev().copied().collect())
+///     }
+///
+///     fn decrypt(&self, ciphertext: &[u8]) -> SpotifyResult<Vec<u8>> {
+/// #       Ok(ciphertext.iter().rev().copied().collect())
+///     }
+/// }
+///
+/// let sealer = StateSealer::new(Aead);
+/// let state = sealer.seal(b"/playlists/37i9dQZF1DXcBWIGoYBM5M").unwrap();
+///
+/// // On the callback, after comparing the state with the one of the session.
+/// let return_path = sealer.verify(&state).unwrap();
+/// # assert_eq!(return_path, b"/playlists/37i9dQZF1DXcBWIGoYBM5M");
+/// ```
+#[cfg(feature = "rand")]
+#[derive(Debug)]
+pub struct StateSealer<E> {
+    encryptor: E,
+}
+
+/// The length of the random nonce sealed in front of the payload.
+#[cfg(feature = "rand")]
+const STATE_NONCE_LEN: usize = 16;
+
+#[cfg(feature = "rand")]
+impl<E: Encryptor> StateSealer<E> {
+    /// Seal the states with ``encryptor``.
+    pub fn new(encryptor: E) -> Self {
+        Self { encryptor }
+    }
+
+    /// A state carrying ``payload``, failing with the error of the encryptor.
+    pub fn seal(&self, payload: &[u8]) -> SpotifyResult<String> {
+        let mut plaintext = rand::thread_rng().gen::<[u8; STATE_NONCE_LEN]>().to_vec();
+        plaintext.extend_from_slice(payload);
+
+        let ciphertext = self.encryptor.encrypt(&plaintext)?;
+        Ok(base64::encode_config(ciphertext, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// The payload sealed into ``state``, e.g. the state of a callback.
+    ///
+    /// Fails with [`SpotifyError::CallbackFailure`] if the state was not sealed by a sealer, and
+    /// with the error of the encryptor if it cannot decrypt the state.
+    pub fn verify(&self, state: &str) -> SpotifyResult<Vec<u8>> {
+        let ciphertext = base64::decode_config(state, base64::URL_SAFE_NO_PAD).map_err(|_| {
+            SpotifyError::CallbackFailure {
+                context: "The state is not an encrypted state.",
+            }
+        })?;
+
+        let mut plaintext = self.encryptor.decrypt(&ciphertext)?;
+        if plaintext.len() < STATE_NONCE_LEN {
+            return Err(SpotifyError::CallbackFailure {
+                context: "The state is not an encrypted state.",
+            });
+        }
+
+        Ok(plaintext.split_off(STATE_NONCE_LEN))
+    }
+}
 
 /// Spotify Authentication
 ///
@@ -15,13 +151,26 @@ pub struct SpotifyAuth {
     /// Required by the Spotify API.
     pub response_type: String,
     /// The URI to redirect to after the user grants or denies permission.
+    ///
+    /// Spotify compares it character by character with the registered redirect URIs, both in the
+    /// authorization URL and in the code exchange. Both send ``redirect_uri.as_str()`` unchanged,
+    /// including query parameters such as ``?env=dev``, so register the URI as serialized by
+    /// [`Url`], e.g. with a trailing slash for ``http://localhost:8888/``.
     pub redirect_uri: Url,
+    /// All redirect URIs registered for the application, e.g. one on ``localhost`` for development
+    /// and an ``https`` one for production. [`SpotifyAuth::select_redirect_uri`] picks one of them
+    /// as ``redirect_uri``, leave it empty to always use ``redirect_uri``.
+    pub redirect_uris: Vec<Url>,
     /// A random generated string that can be useful for correlating requests and responses.
     pub state: String,
+    /// Generates the state of new authorizations, e.g. ``Arc::new(RandomStateGenerator)``.
+    pub state_generator: Arc<dyn StateGenerator>,
     /// Vec of Spotify Scopes.
     pub scope: Vec<SpotifyScope>,
     /// Whether or not to force the user to approve the app again if they’ve already done so.
     pub show_dialog: bool,
+    /// The URLs of the Spotify Accounts service.
+    pub endpoints: Endpoints,
 }
 
 /// Conversion and helper functions for SpotifyAuth.
@@ -41,6 +190,10 @@ impl SpotifyAuth {
     /// let auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
     /// # assert_eq!(auth.scope_into_string(), "streaming");
     /// ```
+    ///
+    /// Only available with the ``rand`` feature, which is enabled by default. Without it, build the
+    /// structure from its fields with a state generated by the application.
+    #[cfg(feature = "rand")]
     pub fn new(
         client_id: String,
         client_secret: String,
@@ -48,18 +201,65 @@ impl SpotifyAuth {
         redirect_uri: String,
         scope: Vec<SpotifyScope>,
         show_dialog: bool,
+    ) -> Self {
+        Self::new_with_rng(
+            client_id,
+            client_secret,
+            response_type,
+            redirect_uri,
+            scope,
+            show_dialog,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// Generate a new SpotifyAuth structure, drawing the state value from the given random number generator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{SpotifyAuth, SpotifyScope};
+    /// use rand::{rngs::StdRng, SeedableRng};
+    ///
+    /// // Deterministic state for tests.
+    /// let mut rng = StdRng::seed_from_u64(42);
+    /// let auth = SpotifyAuth::new_with_rng("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false, &mut rng);
+    /// # assert_eq!(auth.state.len(), 20);
+    /// ```
+    ///
+    /// Only available with the ``rand`` feature, which is enabled by default.
+    #[cfg(feature = "rand")]
+    pub fn new_with_rng<R: Rng + ?Sized>(
+        client_id: String,
+        client_secret: String,
+        response_type: String,
+        redirect_uri: String,
+        scope: Vec<SpotifyScope>,
+        show_dialog: bool,
+        rng: &mut R,
     ) -> Self {
         Self {
             client_id,
             client_secret,
             response_type,
             redirect_uri: Url::parse(&redirect_uri).context(UrlError).unwrap(),
-            state: generate_random_string(20),
+            redirect_uris: Vec::new(),
+            state: generate_random_string_with(rng, 20),
+            state_generator: Arc::new(RandomStateGenerator),
             scope,
             show_dialog,
+            endpoints: Endpoints::default(),
         }
     }
 
+    /// Use ``generator`` for the states of this authorization, replacing the current state with a
+    /// new one.
+    pub fn with_state_generator(mut self, generator: impl StateGenerator + 'static) -> Self {
+        self.state = generator.generate_state();
+        self.state_generator = Arc::new(generator);
+        self
+    }
+
     /// Concatenate the scope vector into a string needed for the authorization URL.
     ///
     /// # Example
@@ -71,11 +271,17 @@ impl SpotifyAuth {
     /// # assert_eq!(auth.scope_into_string(), "streaming");
     /// ```
     pub fn scope_into_string(&self) -> String {
-        self.scope
-            .iter()
-            .map(|x| x.clone().to_string())
-            .collect::<Vec<String>>()
-            .join(" ")
+        let length = self.scope.iter().map(|x| x.as_str().len() + 1).sum();
+        let mut out = String::with_capacity(length);
+
+        for (i, scope) in self.scope.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(scope.as_str());
+        }
+
+        out
     }
 
     /// Convert the SpotifyAuth struct into the authorization URL.
@@ -91,16 +297,302 @@ impl SpotifyAuth {
     ///     .authorize_url().unwrap();
     /// ```
     pub fn authorize_url(&self) -> SpotifyResult<String> {
-        let mut url = Url::parse(SPOTIFY_AUTH_URL).context(UrlError)?;
+        Ok(self.authorize_url_with(&self.state, &[]).into())
+    }
 
-        url.query_pairs_mut()
+    /// The authorization URL with a fresh state from the ``state_generator``, and that state.
+    ///
+    /// Unlike [`SpotifyAuth::authorize_url`] every call gets its own state, so one configuration
+    /// can serve concurrent logins, e.g. of a multi-user web server. Keep the state with the
+    /// session of the user, or register it with ``SharedCallbackServer::expect``, and compare it
+    /// with the state of the callback.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{SpotifyAuth, SpotifyScope};
+    /// let auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
+    ///
+    /// let (url, state) = auth.authorize_url_with_state().unwrap();
+    /// # assert!(url.query_pairs().any(|x| x.0 == "state" && x.1 == state));
+    /// # assert_ne!(state, auth.authorize_url_with_state().unwrap().1);
+    /// ```
+    pub fn authorize_url_with_state(&self) -> SpotifyResult<(Url, String)> {
+        let state = self.state_generator.generate_state();
+        let url = self.authorize_url_with(&state, &[]);
+
+        Ok((url, state))
+    }
+
+    /// Use the first of the registered ``redirect_uris`` matching ``select`` as ``redirect_uri``.
+    ///
+    /// The code exchange has to send the redirect URI of the authorization, [`AuthFlow`](crate::AuthFlow)
+    /// and all functions taking ``auth.redirect_uri`` pick up the selection. Fails if no registered
+    /// URI matches, ``redirect_uri`` stays unchanged then.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{SpotifyAuth, SpotifyScope};
+    /// # use url::Url;
+    /// let mut auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
+    /// auth.redirect_uris = vec![
+    ///     Url::parse("http://localhost:8000/callback").unwrap(),
+    ///     Url::parse("https://app.example/callback").unwrap(),
+    /// ];
+    ///
+    /// auth.select_redirect_uri(|x| x.scheme() == "https").unwrap();
+    /// # assert_eq!(auth.redirect_uri.as_str(), "https://app.example/callback");
+    /// ```
+    pub fn select_redirect_uri(&mut self, select: impl Fn(&Url) -> bool) -> SpotifyResult<&Url> {
+        let selected = match self.redirect_uris.is_empty() {
+            true => Some(&self.redirect_uri).filter(|x| select(x)),
+            false => self.redirect_uris.iter().find(|x| select(x)),
+        };
+
+        match selected {
+            Some(x) => {
+                self.redirect_uri = x.clone();
+                Ok(&self.redirect_uri)
+            }
+            None => Err(SpotifyError::FlowFailure {
+                context: "None of the registered redirect URIs was selected.",
+            }),
+        }
+    }
+
+    /// Select the redirect URI with [`SpotifyAuth::select_redirect_uri`] and build the
+    /// authorization URL with it.
+    pub fn authorize_url_for(&mut self, select: impl Fn(&Url) -> bool) -> SpotifyResult<String> {
+        self.select_redirect_uri(select)?;
+        self.authorize_url()
+    }
+
+    /// The authorization URL of the PKCE flow, for public clients which cannot keep a secret.
+    ///
+    /// ``code_challenge`` is derived from the code verifier with
+    /// [`code_challenge_s256`](crate::code_challenge_s256), the verifier has to be kept until the
+    /// code is exchanged with [`protocol::pkce_exchange_request`](crate::protocol::pkce_exchange_request).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{code_challenge_s256, SpotifyAuth, SpotifyScope};
+    /// let auth = SpotifyAuth::new("00000000000".into(), String::new(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
+    /// let url = auth.authorize_url_with_pkce(&code_challenge_s256("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")).unwrap();
+    /// # assert!(url.ends_with("&code_challenge_method=S256&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"));
+    /// ```
+    pub fn authorize_url_with_pkce(&self, code_challenge: &str) -> SpotifyResult<String> {
+        let extra = [
+            ("code_challenge_method", "S256"),
+            ("code_challenge", code_challenge),
+        ];
+
+        Ok(self.authorize_url_with(&self.state, &extra).into())
+    }
+
+    /// The authorization URL with the given state and additional query parameters.
+    fn authorize_url_with(&self, state: &str, extra: &[(&str, &str)]) -> Url {
+        let mut url = self.endpoints.authorize_url.clone();
+        url.set_fragment(None);
+
+        // Serialize the query into one buffer, keeping a query the base URL may have.
+        let mut query = String::with_capacity(256 + self.redirect_uri.as_str().len());
+        query.push_str(url.query().unwrap_or_default());
+        form_urlencoded::Serializer::for_suffix(&mut query, 0)
             .append_pair("client_id", &self.client_id)
             .append_pair("response_type", &self.response_type)
             .append_pair("redirect_uri", self.redirect_uri.as_str())
-            .append_pair("state", &self.state)
-            .append_pair("scope", &self.scope_into_string())
-            .append_pair("show_dialog", &self.show_dialog.to_string());
+            .append_pair("state", state);
 
-        Ok(url.to_string())
+        // The scopes are separated by encoded spaces, written without joining them first.
+        query.push_str("&scope=");
+        for (i, scope) in self.scope.iter().enumerate() {
+            if i > 0 {
+                query.push('+');
+            }
+            query.extend(form_urlencoded::byte_serialize(scope.as_str().as_bytes()));
+        }
+
+        form_urlencoded::Serializer::for_suffix(&mut query, 0)
+            .append_pair(
+                "show_dialog",
+                if self.show_dialog { "true" } else { "false" },
+            )
+            .extend_pairs(extra);
+
+        url.set_query(Some(&query));
+        url
+    }
+
+    /// Build the authorization URL and open it in the default browser of the user.
+    ///
+    /// The URL is returned as well, so it can be printed in case the browser did not show up.
+    ///
+    /// Only available with the ``open`` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{SpotifyAuth, SpotifyScope};
+    /// let auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
+    /// let url = auth.open_in_browser().unwrap();
+    /// println!("If your browser did not open, visit: {}", url);
+    /// ```
+    #[cfg(feature = "open")]
+    pub fn open_in_browser(&self) -> SpotifyResult<String> {
+        let url = self.authorize_url()?;
+        crate::browser::open_url(&url)?;
+
+        Ok(url)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn auth(authorize_url: &str) -> SpotifyAuth {
+        let mut auth = SpotifyAuth::new_with_rng(
+            "id".into(),
+            "secret".into(),
+            "code".into(),
+            "http://localhost:8000/callback".into(),
+            vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail],
+            true,
+            &mut StdRng::seed_from_u64(1),
+        );
+        auth.endpoints.authorize_url = Url::parse(authorize_url).unwrap();
+        auth.state = "sN".into();
+        auth
+    }
+
+    #[test]
+    fn test_authorize_url() {
+        assert_eq!(
+            auth("https://accounts.spotify.com/authorize").authorize_url().unwrap(),
+            "https://accounts.spotify.com/authorize?client_id=id&response_type=code&redirect_uri=http%3A%2F%2Flocalhost%3A8000%2Fcallback&state=sN&scope=streaming+user-read-email&show_dialog=true"
+        );
+    }
+
+    #[test]
+    fn test_authorize_url_keeps_base_query() {
+        let url = auth("http://127.0.0.1:8080/authorize?tenant=a#top")
+            .authorize_url()
+            .unwrap();
+
+        assert!(url.starts_with("http://127.0.0.1:8080/authorize?tenant=a&client_id=id&"));
+        assert!(url.ends_with("&show_dialog=true"));
+    }
+
+    #[test]
+    fn test_authorize_url_with_state() {
+        let counter = AtomicUsize::new(0);
+        let auth = auth("https://accounts.spotify.com/authorize").with_state_generator(move || {
+            format!("tenant-7.{}", counter.fetch_add(1, Ordering::SeqCst))
+        });
+        assert_eq!(auth.state, "tenant-7.0");
+
+        for expected in ["tenant-7.1", "tenant-7.2"] {
+            let (url, state) = auth.authorize_url_with_state().unwrap();
+            assert_eq!(state, expected);
+            assert!(url.as_str().contains(&format!("&state={}&", expected)));
+        }
+        assert_eq!(auth.state, "tenant-7.0");
+        assert!(auth.authorize_url().unwrap().contains("&state=tenant-7.0&"));
+    }
+
+    #[test]
+    fn test_select_redirect_uri() {
+        let mut auth = auth("https://accounts.spotify.com/authorize");
+        assert!(auth.select_redirect_uri(|x| x.scheme() == "https").is_err());
+
+        auth.redirect_uris = vec![
+            Url::parse("http://localhost:8000/callback").unwrap(),
+            Url::parse("https://app.example/callback").unwrap(),
+        ];
+        let url = auth.authorize_url_for(|x| x.scheme() == "https").unwrap();
+        assert!(url.contains("&redirect_uri=https%3A%2F%2Fapp.example%2Fcallback&"));
+        assert_eq!(auth.redirect_uri.as_str(), "https://app.example/callback");
+
+        assert!(auth
+            .select_redirect_uri(|x| x.port() == Some(9000))
+            .is_err());
+        assert_eq!(auth.redirect_uri.as_str(), "https://app.example/callback");
+
+        auth.select_redirect_uri(|x| x.host_str() == Some("localhost"))
+            .unwrap();
+        assert_eq!(auth.redirect_uri.as_str(), "http://localhost:8000/callback");
+    }
+
+    #[test]
+    fn test_redirect_uri_query_is_kept() {
+        let registered = "http://localhost:8000/callback?env=dev&next=%2Fhome&tag=a+b&flag";
+        let mut auth = auth("https://accounts.spotify.com/authorize");
+        auth.redirect_uri = Url::parse(registered).unwrap();
+        assert_eq!(auth.redirect_uri.as_str(), registered);
+
+        let url = Url::parse(&auth.authorize_url().unwrap()).unwrap();
+        let sent = url.query_pairs().find(|x| x.0 == "redirect_uri").unwrap().1;
+        assert_eq!(sent, registered);
+
+        let request = crate::protocol::exchange_request(
+            &crate::AppClient::new("id", "secret"),
+            "AQD0yXvF",
+            &auth.redirect_uri,
+        );
+        assert_eq!(request.form_field("redirect_uri"), Some(registered));
+        let body = request.body();
+        let sent = form_urlencoded::parse(body.as_bytes())
+            .find(|x| x.0 == "redirect_uri")
+            .unwrap()
+            .1;
+        assert_eq!(sent, registered);
+    }
+
+    /// Reverses the plaintext behind a tag, rejecting ciphertexts without it.
+    struct Tagged;
+
+    impl Encryptor for Tagged {
+        fn encrypt(&self, plaintext: &[u8]) -> SpotifyResult<Vec<u8>> {
+            Ok(b"tag"
+                .iter()
+                .chain(plaintext.iter().rev())
+                .copied()
+                .collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> SpotifyResult<Vec<u8>> {
+            match ciphertext.strip_prefix(b"tag") {
+                Some(x) => Ok(x.iter().rev().copied().collect()),
+                None => Err(SpotifyError::CallbackFailure {
+                    context: "authentication failed",
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_sealer() {
+        let sealer = StateSealer::new(Tagged);
+        let mut auth = auth("https://accounts.spotify.com/authorize");
+        auth.state = sealer.seal(b"user-42").unwrap();
+
+        let url = auth.authorize_url().unwrap();
+        assert!(url.contains(&format!("&state={}&", auth.state)));
+        assert!(!auth.state.contains("user-42"));
+        assert_ne!(auth.state, sealer.seal(b"user-42").unwrap());
+        assert_eq!(sealer.verify(&auth.state).unwrap(), b"user-42");
+
+        assert_eq!(sealer.verify(&sealer.seal(b"").unwrap()).unwrap(), b"");
+
+        for forged in ["sN", "not base64!", "dGFn"] {
+            assert!(matches!(
+                sealer.verify(forged),
+                Err(SpotifyError::CallbackFailure { .. })
+            ));
+        }
     }
 }
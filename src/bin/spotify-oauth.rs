@@ -0,0 +1,412 @@
+//! Command line interface running the authorization code flow, only built with the ``cli`` feature.
+//!
+//! ```text
+//! spotify-oauth login --scopes streaming,user-read-email --cache token.json
+//! spotify-oauth refresh --cache token.json
+//! spotify-oauth --profile work login
+//! eval $(spotify-oauth refresh --cache token.json --output env)
+//! spotify-oauth login --no-browser --qr
+//! ```
+//!
+//! Profiles are read from ``spotify-oauth/profiles.toml`` in the configuration directory of the
+//! user, e.g. ``~/.config`` on Linux:
+//!
+//! ```toml
+//! [work]
+//! client_id = "00000000000"
+//! client_secret = "secret"
+//! scopes = ["streaming", "user-read-email"]
+//! ```
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use spotify_oauth::{
+    write_private_file, AppClient, CachedToken, CallbackServer, FileRefreshLock, RefreshLease,
+    SpotifyAuth, SpotifyCallback, SpotifyResult, SpotifyScope, SpotifyToken, SurfClient,
+};
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    io::{self, BufRead},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+};
+use url::Url;
+
+type CliResult<T> = Result<T, Box<dyn Error + Send + Sync + 'static>>;
+
+const DEFAULT_REDIRECT_URI: &str = "http://localhost:8888/callback";
+
+#[derive(Parser)]
+#[command(version, about = "Authorize against the Spotify Accounts service")]
+struct Cli {
+    /// Use the settings of a named profile, command line arguments take precedence.
+    #[arg(long, global = true, env = "SPOTIFY_OAUTH_PROFILE")]
+    profile: Option<String>,
+    /// The profiles file, defaults to "spotify-oauth/profiles.toml" in the configuration directory.
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the authorization code flow and print or cache the token.
+    Login(Login),
+    /// Refresh a cached token, write it back and print it.
+    Refresh(Refresh),
+}
+
+/// How a token is printed.
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    /// The token as JSON.
+    Json,
+    /// Shell export statements, e.g. for "eval $(spotify-oauth login --output env)".
+    Env,
+    /// Only the access token, e.g. for piping into curl.
+    Plain,
+}
+
+impl Output {
+    fn print(self, token: &SpotifyToken) -> CliResult<()> {
+        match self {
+            Output::Json => println!("{}", serde_json::to_string_pretty(token)?),
+            Output::Env => print!("{}", token.to_shell_exports()),
+            Output::Plain => println!("{}", token.access_token),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Args)]
+struct Credentials {
+    /// The client ID of the Spotify application [default: profile, then SPOTIFY_CLIENT_ID].
+    #[arg(long)]
+    client_id: Option<String>,
+    /// The client secret of the Spotify application [default: profile, then SPOTIFY_CLIENT_SECRET].
+    #[arg(long)]
+    client_secret: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct Login {
+    #[command(flatten)]
+    credentials: Credentials,
+    /// The redirect URI registered for the application, a local server listens on its port
+    /// [default: profile, then SPOTIFY_REDIRECT_URI, then http://localhost:8888/callback].
+    #[arg(long)]
+    redirect_uri: Option<Url>,
+    /// Comma separated list of scopes, e.g. "streaming,user-read-email".
+    #[arg(long, value_delimiter = ',', value_parser = parse_scope)]
+    scopes: Vec<SpotifyScope>,
+    /// Force the user to approve the application again.
+    #[arg(long)]
+    show_dialog: bool,
+    /// Print the authorization URL instead of opening the browser, e.g. over SSH. The redirect
+    /// URL can be pasted if the browser cannot reach the local callback server.
+    #[arg(long)]
+    no_browser: bool,
+    /// Also print the authorization URL as QR code, e.g. for logging in with a phone.
+    #[arg(long, requires = "no_browser")]
+    qr: bool,
+    /// Listen on this address instead of the host of the redirect URI, e.g. "0.0.0.0" to log in
+    /// from another device of the network with a redirect URI pointing at this machine.
+    #[arg(long, value_name = "ADDR")]
+    bind: Option<IpAddr>,
+    /// Advertise the callback server under this name via mDNS, e.g. for a companion app.
+    #[arg(long, value_name = "NAME", requires = "bind")]
+    advertise: Option<String>,
+    /// Write the token to this file, it is only printed if --output is given.
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+    /// How to print the token [default: json].
+    #[arg(long, value_enum)]
+    output: Option<Output>,
+}
+
+#[derive(clap::Args)]
+struct Refresh {
+    #[command(flatten)]
+    credentials: Credentials,
+    /// The token file written by "login --cache".
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+    /// How to print the refreshed token.
+    #[arg(long, value_enum, default_value = "plain")]
+    output: Output,
+}
+
+/// The settings of a named profile.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// Defaults to ``spotify-oauth/<profile>.json`` in the configuration directory.
+    cache: Option<PathBuf>,
+}
+
+/// Resolves settings from the command line, the selected profile and the environment, in this order.
+struct Settings {
+    profile: Profile,
+    default_cache: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Load the profile selected on the command line, if any.
+    fn load(cli: &Cli) -> CliResult<Self> {
+        let name = match &cli.profile {
+            None => {
+                return Ok(Self {
+                    profile: Profile::default(),
+                    default_cache: None,
+                })
+            }
+            Some(x) => x,
+        };
+
+        let config_dir = dirs::config_dir().map(|x| x.join("spotify-oauth"));
+        let path = match (&cli.config, &config_dir) {
+            (Some(x), _) => x.clone(),
+            (None, Some(x)) => x.join("profiles.toml"),
+            (None, None) => return Err("no configuration directory, pass --config".into()),
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let mut profiles: HashMap<String, Profile> = toml::from_str(&text)
+            .map_err(|err| format!("failed to parse {}: {}", path.display(), err))?;
+
+        let profile = profiles
+            .remove(name)
+            .ok_or_else(|| format!("no profile named {} in {}", name, path.display()))?;
+
+        Ok(Self {
+            profile,
+            default_cache: config_dir.map(|x| x.join(format!("{}.json", name))),
+        })
+    }
+
+    /// The client ID and secret to use.
+    fn credentials(&self, args: Credentials) -> CliResult<(String, String)> {
+        let client_id = args
+            .client_id
+            .or_else(|| self.profile.client_id.clone())
+            .or_else(|| env::var("SPOTIFY_CLIENT_ID").ok())
+            .ok_or("missing client ID, pass --client-id or set SPOTIFY_CLIENT_ID")?;
+        let client_secret = args
+            .client_secret
+            .or_else(|| self.profile.client_secret.clone())
+            .or_else(|| env::var("SPOTIFY_CLIENT_SECRET").ok())
+            .ok_or("missing client secret, pass --client-secret or set SPOTIFY_CLIENT_SECRET")?;
+
+        Ok((client_id, client_secret))
+    }
+
+    fn redirect_uri(&self, arg: Option<Url>) -> CliResult<Url> {
+        if let Some(x) = arg {
+            return Ok(x);
+        }
+
+        let value = match &self.profile.redirect_uri {
+            Some(x) => x.clone(),
+            None => {
+                env::var("SPOTIFY_REDIRECT_URI").unwrap_or_else(|_| DEFAULT_REDIRECT_URI.into())
+            }
+        };
+
+        Ok(Url::parse(&value)?)
+    }
+
+    fn scopes(&self, arg: Vec<SpotifyScope>) -> CliResult<Vec<SpotifyScope>> {
+        if !arg.is_empty() {
+            return Ok(arg);
+        }
+
+        Ok(self
+            .profile
+            .scopes
+            .iter()
+            .map(|x| parse_scope(x))
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn cache(&self, arg: Option<PathBuf>) -> Option<PathBuf> {
+        arg.or_else(|| self.profile.cache.clone())
+            .or_else(|| self.default_cache.clone())
+    }
+}
+
+fn parse_scope(value: &str) -> Result<SpotifyScope, String> {
+    match SpotifyScope::from_str(value.trim()) {
+        Ok(x) if x.is_known() => Ok(x),
+        _ => Err(format!("unknown scope: {}", value)),
+    }
+}
+
+/// Take the refresh lease of a token file, creating its directory if needed.
+///
+/// The lease is a [`FileRefreshLock`] on ``<path>.lock``, held until it is dropped, so concurrent
+/// invocations refreshing the same token file wait for each other instead of reading a refresh
+/// token which another invocation is about to rotate.
+fn lock_cache(path: &Path) -> CliResult<RefreshLease> {
+    let dir = path
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name().ok_or("the token file has no file name")?;
+
+    Ok(FileRefreshLock::new(dir).acquire(&name.to_string_lossy())?)
+}
+
+/// Write a token file in the versioned cache layout with a readable expiry date, holding the lock
+/// of [`lock_cache`].
+///
+/// The file holds the refresh token, so it is only readable by the user and replaced atomically.
+fn write_cache(path: &Path, token: &SpotifyToken) -> CliResult<()> {
+    let cached = CachedToken::from(token.clone());
+    write_private_file(
+        path,
+        serde_json::to_string_pretty(&cached.with_rfc3339_expiry())?,
+    )?;
+
+    Ok(())
+}
+
+/// Wait for the callback on the local server and, at the same time, for a redirect URL pasted to stdin.
+///
+/// The first callback wins. A local server which cannot be started is reported, pasting still works.
+fn wait_for_callback_or_paste(server: SpotifyResult<CallbackServer>) -> CliResult<SpotifyCallback> {
+    let (tx, rx) = mpsc::channel();
+
+    let sender = tx.clone();
+    thread::spawn(move || match server.and_then(|x| x.receive_blocking()) {
+        Ok(x) => drop(sender.send(x)),
+        Err(err) => eprintln!("Local callback server failed: {}", err),
+    });
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            match SpotifyCallback::from_str(line.trim()) {
+                Ok(x) => return drop(tx.send(x)),
+                Err(err) => eprintln!("Not a redirect URL ({}), try again:", err),
+            }
+        }
+    });
+
+    rx.recv()
+        .map_err(|_| "no callback received, stdin was closed".into())
+}
+
+/// Render a QR code for the terminal.
+fn qr_code(data: &str) -> CliResult<String> {
+    let code = qrcode::QrCode::new(data)?;
+
+    Ok(code
+        .render::<qrcode::render::unicode::Dense1x2>()
+        .quiet_zone(true)
+        .build())
+}
+
+async fn login(settings: &Settings, args: Login) -> CliResult<()> {
+    let (client_id, client_secret) = settings.credentials(args.credentials)?;
+    let auth = SpotifyAuth::new(
+        client_id,
+        client_secret,
+        "code".into(),
+        settings.redirect_uri(args.redirect_uri)?.to_string(),
+        settings.scopes(args.scopes)?,
+        args.show_dialog,
+    );
+
+    let server = match args.bind {
+        Some(address) => CallbackServer::bind_address(&auth.redirect_uri, address),
+        None => CallbackServer::bind(&auth.redirect_uri),
+    };
+    // Kept alive until the callback arrived.
+    let _advertisement = match (&server, &args.advertise) {
+        (Ok(server), Some(name)) => Some(server.advertise(name)?),
+        _ => None,
+    };
+
+    let callback = if args.no_browser {
+        let url = auth.authorize_url()?;
+        eprintln!("Open this URL in a browser: {}", url);
+        if args.qr {
+            eprintln!("{}", qr_code(&url)?);
+        }
+        eprintln!("Waiting for the callback, or paste the URL the browser was redirected to:");
+
+        wait_for_callback_or_paste(server)?
+    } else {
+        let server = server?;
+        let url = auth.open_in_browser()?;
+        eprintln!("If your browser did not open, visit: {}", url);
+
+        server.receive().await?
+    };
+    if callback.state() != auth.state {
+        return Err("state of the callback does not match, the login may have been forged".into());
+    }
+
+    let token = AppClient::from(&auth)
+        .exchange_callback(&SurfClient::new(), &callback, &auth.redirect_uri)
+        .await?;
+
+    match (settings.cache(args.cache), args.output) {
+        (Some(path), output) => {
+            let _lock = lock_cache(&path)?;
+            write_cache(&path, &token)?;
+            eprintln!("Token written to {}", path.display());
+            output.map_or(Ok(()), |x| x.print(&token))
+        }
+        (None, output) => output.unwrap_or(Output::Json).print(&token),
+    }
+}
+
+async fn refresh(settings: &Settings, args: Refresh) -> CliResult<()> {
+    let path = settings
+        .cache(args.cache)
+        .ok_or("missing token file, pass --cache or select a profile")?;
+    // Held until the refreshed token is written.
+    let _lock = lock_cache(&path)?;
+    // Token files of earlier releases are migrated when they are read.
+    let cached: CachedToken = match fs::read_to_string(&path) {
+        Ok(x) => serde_json::from_str(&x)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(format!("no token at {}, run login first", path.display()).into())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let refresh_token = cached
+        .refresh_token
+        .ok_or("the cached token has no refresh token, run login again")?;
+
+    let (client_id, client_secret) = settings.credentials(args.credentials)?;
+    let app = AppClient::new(client_id, client_secret);
+    let token = app.refresh(&SurfClient::new(), &refresh_token).await?;
+    write_cache(&path, &token)?;
+
+    args.output.print(&token)
+}
+
+#[async_std::main]
+async fn main() -> CliResult<()> {
+    let cli = Cli::parse();
+    let settings = Settings::load(&cli)?;
+
+    match cli.command {
+        Command::Login(args) => login(&settings, args).await,
+        Command::Refresh(args) => refresh(&settings, args).await,
+    }
+}
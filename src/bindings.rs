@@ -0,0 +1,212 @@
+//! uniffi bindings of the authorization code flow for Kotlin and Swift.
+//!
+//! Only available with the ``uniffi`` feature. The types of this module mirror the Rust API with
+//! plain strings, so the generated foreign bindings stay simple.
+
+use crate::{
+    convert_callback_into_token, refresh_access_token, SpotifyAuth, SpotifyCallback, SpotifyError,
+    SpotifyScope, SpotifyToken,
+};
+use std::{fmt, str::FromStr};
+use url::Url;
+
+/// The error raised by all exported functions.
+#[derive(uniffi::Error, Debug, Clone, PartialEq, Eq)]
+pub enum FfiError {
+    /// The operation failed, see [`SpotifyError`].
+    Failure {
+        /// The [`ErrorCategory`](crate::ErrorCategory) in lower case, e.g. ``transport``.
+        category: String,
+        /// The error message.
+        message: String,
+    },
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FfiError::Failure { category, message } => write!(f, "{}: {}", category, message),
+        }
+    }
+}
+
+impl From<SpotifyError> for FfiError {
+    fn from(err: SpotifyError) -> Self {
+        FfiError::Failure {
+            category: format!("{:?}", err.category()).to_lowercase(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// The authorization URL to open and the state to verify the callback with.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct FfiAuthorization {
+    /// The URL to open in a browser.
+    pub url: String,
+    /// The generated state, compare it with the state of the callback.
+    pub state: String,
+}
+
+/// A parsed callback URL.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct FfiCallback {
+    /// The authorization code, if the user granted access.
+    pub code: Option<String>,
+    /// The reason authorization failed.
+    pub error: Option<String>,
+    /// The state of the callback.
+    pub state: String,
+}
+
+/// A token, see [`SpotifyToken`].
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct FfiToken {
+    /// The access token.
+    pub access_token: String,
+    /// How the access token may be used.
+    pub token_type: String,
+    /// The granted scopes, e.g. ``streaming``.
+    pub scope: Vec<String>,
+    /// The lifetime of the access token in seconds.
+    pub expires_in: u32,
+    /// The unix timestamp at which the access token expires.
+    pub expires_at: Option<i64>,
+    /// The refresh token, if one was issued.
+    pub refresh_token: Option<String>,
+}
+
+impl From<SpotifyToken> for FfiToken {
+    fn from(token: SpotifyToken) -> Self {
+        Self {
+            scope: token.scope.iter().map(ToString::to_string).collect(),
+            access_token: token.access_token,
+            token_type: token.token_type.to_string(),
+            expires_in: token.expires_in,
+            expires_at: token.expires_at,
+            refresh_token: token.refresh_token,
+        }
+    }
+}
+
+/// Parse scopes given by their Spotify names.
+fn parse_scopes(scopes: &[String]) -> Result<Vec<SpotifyScope>, FfiError> {
+    scopes
+        .iter()
+        .map(|x| match SpotifyScope::from_str(x) {
+            Ok(x) if x.is_known() => Ok(x),
+            _ => Err(FfiError::Failure {
+                category: "usage".to_string(),
+                message: format!("Unknown scope: {}", x),
+            }),
+        })
+        .collect()
+}
+
+/// Build the authorization URL with a freshly generated state.
+#[uniffi::export]
+pub fn authorize_url(
+    client_id: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    show_dialog: bool,
+) -> Result<FfiAuthorization, FfiError> {
+    Url::parse(&redirect_uri).map_err(|err| FfiError::Failure {
+        category: "usage".to_string(),
+        message: err.to_string(),
+    })?;
+
+    let auth = SpotifyAuth::new(
+        client_id,
+        String::new(),
+        "code".to_string(),
+        redirect_uri,
+        parse_scopes(&scopes)?,
+        show_dialog,
+    );
+
+    Ok(FfiAuthorization {
+        url: auth.authorize_url()?,
+        state: auth.state,
+    })
+}
+
+/// Parse the URL Spotify redirected to.
+#[uniffi::export]
+pub fn parse_callback(url: String) -> Result<FfiCallback, FfiError> {
+    let callback = SpotifyCallback::from_str(&url)?;
+
+    Ok(FfiCallback {
+        code: callback.code,
+        error: callback.error,
+        state: callback.state,
+    })
+}
+
+/// Exchange the authorization code of a callback for a token.
+#[uniffi::export]
+pub async fn exchange_code(
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    code: String,
+) -> Result<FfiToken, FfiError> {
+    let redirect_uri = Url::parse(&redirect_uri).map_err(|err| FfiError::Failure {
+        category: "usage".to_string(),
+        message: err.to_string(),
+    })?;
+    let callback = SpotifyCallback::new(Some(code), None, String::new());
+
+    let token =
+        convert_callback_into_token(callback, client_id, client_secret, redirect_uri).await?;
+    Ok(token.into())
+}
+
+/// Request a new access token with a refresh token.
+#[uniffi::export]
+pub async fn refresh(
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+) -> Result<FfiToken, FfiError> {
+    let token = refresh_access_token(refresh_token, client_id, client_secret).await?;
+    Ok(token.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_authorize_url() {
+        let authorization = authorize_url(
+            "id".into(),
+            "http://localhost:8000/callback".into(),
+            vec!["streaming".into()],
+            false,
+        )
+        .unwrap();
+        assert!(authorization
+            .url
+            .contains(&format!("state={}", authorization.state)));
+
+        let err = authorize_url(
+            "id".into(),
+            "http://localhost:8000/callback".into(),
+            vec!["stream".into()],
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "usage: Unknown scope: stream");
+    }
+
+    #[test]
+    fn test_ffi_parse_callback() {
+        let callback =
+            parse_callback("http://localhost:8000/callback?error=access_denied&state=sN".into())
+                .unwrap();
+
+        assert_eq!(callback.error.as_deref(), Some("access_denied"));
+        assert_eq!(callback.state, "sN");
+    }
+}
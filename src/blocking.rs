@@ -0,0 +1,781 @@
+//! A blocking API for applications without an async runtime, e.g. GUI apps and simple tools.
+//!
+//! Only available with the ``blocking`` feature. The functions mirror the async API of
+//! [`AppClient`] and block the calling thread until Spotify responds. Requests are sent with
+//! [`UreqClient`] unless another [`HttpClient`] is given.
+//!
+//! # TLS
+//!
+//! The ``blocking`` feature does not enable a TLS backend of ureq, and the Spotify Accounts
+//! service is only reachable over ``https``. Enable one in your own manifest, e.g.
+//! ``ureq = { version = "3", features = ["rustls"] }``, otherwise every request of
+//! [`Client::new`] fails with a [`SpotifyError::Transport`] error saying so.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use spotify_oauth::{blocking::{Client, TokenManager}, AppClient, MemoryTokenStore, SpotifyCallback};
+//! # use std::str::FromStr;
+//! # use url::Url;
+//! # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+//! let client = Client::new(AppClient::new("00000000000", "secret"));
+//! let redirect_uri = Url::parse("http://localhost:8000/callback")?;
+//!
+//! let callback = SpotifyCallback::from_str("http://localhost:8000/callback?code=NApCCgBkWtQ&state=test")?;
+//! let token = client.exchange_callback(&callback, &redirect_uri)?;
+//!
+//! // Later: a valid access token, refreshed when it expired.
+//! let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice");
+//! manager.save(&token)?;
+//! let token = manager.token()?;
+//! # Ok(()) }
+//! ```
+
+pub use crate::manager::{PurgeTask, RefreshFailurePolicy, ValidToken};
+
+use crate::{
+    error::*,
+    manager::{Lifecycle, ManagerCore, Step},
+    scoped_key, AppClient, Clock, FileRefreshLock, HttpClient, SpotifyCallback, SpotifyScope,
+    SpotifyToken, TokenStore, UreqClient,
+};
+use futures_lite::{future::block_on, Stream};
+use std::{sync::Arc, time::Duration};
+use url::Url;
+
+/// The blocking counterpart of [`AppClient`], bundled with the HTTP client to use.
+#[derive(Debug, Clone)]
+pub struct Client<C = UreqClient> {
+    app: AppClient,
+    http: C,
+}
+
+impl Client {
+    /// Send the requests of ``app`` with a default [`UreqClient`].
+    ///
+    /// Needs a TLS backend of ureq, see the [module documentation](self#tls).
+    pub fn new(app: AppClient) -> Self {
+        Self::with_http(app, UreqClient::new())
+    }
+}
+
+impl<C: HttpClient> Client<C> {
+    /// Send the requests of ``app`` with the given HTTP client.
+    pub fn with_http(app: AppClient, http: C) -> Self {
+        Self { app, http }
+    }
+
+    /// The credentials and endpoints of the application.
+    pub fn app(&self) -> &AppClient {
+        &self.app
+    }
+
+    /// Exchange the authorization code of a callback for a token.
+    ///
+    /// See [`AppClient::exchange_callback`].
+    pub fn exchange_callback(
+        &self,
+        callback: &SpotifyCallback,
+        redirect_uri: &Url,
+    ) -> SpotifyResult<SpotifyToken> {
+        block_on(
+            self.app
+                .exchange_callback(&self.http, callback, redirect_uri),
+        )
+    }
+
+    /// Exchange an authorization code for a token.
+    ///
+    /// See [`AppClient::exchange_code`].
+    pub fn exchange_code(&self, code: &str, redirect_uri: &Url) -> SpotifyResult<SpotifyToken> {
+        block_on(self.app.exchange_code(&self.http, code, redirect_uri))
+    }
+
+    /// Request a new access token using the refresh token of a previous authorization.
+    ///
+    /// See [`AppClient::refresh`].
+    pub fn refresh(&self, refresh_token: &str) -> SpotifyResult<SpotifyToken> {
+        block_on(self.app.refresh(&self.http, refresh_token))
+    }
+
+    /// Refresh, retrying transient failures with the delays of a [`backoff`] policy.
+    ///
+    /// See [`AppClient::refresh_with_backoff`]. Only available with the ``backoff`` feature.
+    #[cfg(feature = "backoff")]
+    pub fn refresh_with_backoff(
+        &self,
+        refresh_token: &str,
+        policy: impl backoff::backoff::Backoff,
+    ) -> SpotifyResult<SpotifyToken> {
+        block_on(
+            self.app
+                .refresh_with_backoff(&self.http, refresh_token, policy),
+        )
+    }
+}
+
+/// Keeps the token of one account in a [`TokenStore`] and refreshes it when it expired.
+///
+/// The blocking counterpart of the async [`TokenManager`](crate::TokenManager), sharing its refresh
+/// logic.
+#[derive(Debug)]
+pub struct TokenManager<S, C = UreqClient> {
+    client: Client<C>,
+    core: ManagerCore<S>,
+}
+
+impl<S: TokenStore, C: HttpClient> TokenManager<S, C> {
+    /// Manage the token stored under ``key`` in ``store``.
+    pub fn new(client: Client<C>, store: S, key: impl Into<String>) -> Self {
+        Self {
+            client,
+            core: ManagerCore::new(store, key.into()),
+        }
+    }
+
+    /// Refresh only while holding the lease of the key in ``lock``, so one process at a time
+    /// refreshes the token of an account shared by several processes of the machine.
+    ///
+    /// The other processes wait for the lease and then use the token stored by the first one.
+    pub fn with_refresh_lock(mut self, lock: FileRefreshLock) -> Self {
+        self.core.lock = Some(lock);
+        self
+    }
+
+    /// Refresh the token once it expires within ``margin`` instead of once it expired, see
+    /// [`SpotifyToken::expires_within`].
+    pub fn with_refresh_margin(mut self, margin: Duration) -> Self {
+        self.core.margin = margin;
+        self
+    }
+
+    /// Handle failed refreshes according to ``policy`` instead of failing right away.
+    pub fn with_refresh_failure_policy(mut self, policy: RefreshFailurePolicy) -> Self {
+        self.core.policy = policy;
+        self
+    }
+
+    /// Read the time from ``clock`` instead of the system clock, e.g. a [`MockClock`](crate::MockClock)
+    /// in tests. Refreshed tokens are stamped with it as well, see [`AppClient::with_clock`].
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client.app.clock = clock.clone();
+        self.core.clock = clock;
+        self
+    }
+
+    /// Manage the token of ``account`` granting exactly ``scope``, stored under the
+    /// [`scoped_key`] of both.
+    ///
+    /// A manager per scope set keeps e.g. a minimal and a full token of the same user apart, so
+    /// the app can use the minimal one without asking for consent again.
+    pub fn for_scopes(client: Client<C>, store: S, account: &str, scope: &[SpotifyScope]) -> Self {
+        Self::new(client, store, scoped_key(account, scope))
+    }
+
+    /// Store the token of a new authorization.
+    pub fn save(&self, token: &SpotifyToken) -> SpotifyResult<()> {
+        self.core.save(token)
+    }
+
+    /// The stored token, refreshed and stored again if the access token expired or expires within
+    /// the refresh margin.
+    ///
+    /// The refreshed token is stored with [`TokenStore::compare_and_swap`]. If another process
+    /// refreshed the token in the meantime, its token is kept and returned instead, also when the
+    /// refresh failed because the other process rotated the refresh token.
+    ///
+    /// Stores keep only the wall clock ``expires_at``. The manager remembers the monotonic deadline
+    /// of the tokens it saved or refreshed itself and refreshes them once either clock says they
+    /// expired, so neither a system clock set back nor a suspended machine makes it use an expired
+    /// token.
+    ///
+    /// Yields ``None`` if no usable token is stored and the user has to log in again. Fails with
+    /// [`SpotifyError::TokenFailure`] once the manager was shut down.
+    pub fn token(&self) -> SpotifyResult<Option<SpotifyToken>> {
+        self.token_within(self.core.margin)
+    }
+
+    /// A token valid for at least ``min``, refreshed first if needed, e.g. before a batch of API
+    /// requests which must not run into the expiry of the token.
+    ///
+    /// Fails with [`SpotifyError::NeedsReauthorization`] if no usable token is stored, and with
+    /// [`SpotifyError::TokenFailure`] if even a refreshed token expires within ``min``.
+    pub fn get_valid_token(&self, min: Duration) -> SpotifyResult<ValidToken> {
+        let token = self.token_within(self.core.margin.max(min))?;
+        self.core.valid_token(token, min)
+    }
+
+    fn token_within(&self, margin: Duration) -> SpotifyResult<Option<SpotifyToken>> {
+        let mut token = match self.core.begin(margin)? {
+            Step::Done(x) => return Ok(x),
+            Step::Refresh(x) => x,
+        };
+
+        // Held until the refreshed token is stored.
+        let _lease = match &self.core.lock {
+            Some(lock) => {
+                let lease = lock.acquire(&self.core.key)?;
+                token = match self.core.check(margin)? {
+                    Step::Done(x) => return Ok(x),
+                    Step::Refresh(x) => x,
+                };
+                Some(lease)
+            }
+            None => None,
+        };
+
+        let refreshed = self.refresh(token.refresh_token.as_deref().unwrap_or_default());
+        self.core.finish(token, refreshed)
+    }
+
+    /// Refresh the token, retrying transient failures as configured.
+    fn refresh(&self, refresh_token: &str) -> SpotifyResult<SpotifyToken> {
+        match &self.core.policy {
+            #[cfg(feature = "backoff")]
+            RefreshFailurePolicy::Retry(policy) => {
+                self.client.refresh_with_backoff(refresh_token, policy())
+            }
+            _ => self.client.refresh(refresh_token),
+        }
+    }
+
+    /// The tokens saved or refreshed by the manager from now on, e.g. to hand new access tokens to
+    /// a Web Playback SDK session.
+    ///
+    /// The stream ends when the manager is dropped. Tokens are buffered until they are read.
+    pub fn updates(&self) -> impl Stream<Item = SpotifyToken> + Send + Unpin + 'static {
+        self.core.updates()
+    }
+
+    /// Resume refreshing tokens after [`TokenManager::pause_refresh`] or
+    /// [`TokenManager::shutdown`]. New managers are started already.
+    ///
+    /// Background tasks stopped by the shutdown have to be spawned again.
+    pub fn start(&self) {
+        self.core.set_lifecycle(Lifecycle::Running);
+    }
+
+    /// Stop refreshing tokens, e.g. while the device is suspended or offline.
+    ///
+    /// [`TokenManager::token`] then returns the stored token as is, even if it expired.
+    pub fn pause_refresh(&self) {
+        self.core.set_lifecycle(Lifecycle::Paused);
+    }
+
+    /// Stop the background tasks, end the [`TokenManager::updates`] streams and reject further
+    /// token requests, e.g. on a graceful shutdown of the application.
+    ///
+    /// Dropping the manager stops its background tasks as well.
+    pub fn shutdown(&self) {
+        self.core.shutdown();
+    }
+
+    /// Forget the token, e.g. when the user logs out.
+    pub fn remove(&self) -> SpotifyResult<()> {
+        self.core.remove()
+    }
+
+    /// Call [`TokenStore::purge_expired`] on the store every ``interval`` on a background thread,
+    /// until the returned task or the manager is dropped or the manager is shut down.
+    ///
+    /// The store is shared with the thread, e.g. as an ``Arc``, so the purge covers the tokens of
+    /// all managers over the same store. Failed purges are retried at the next interval.
+    pub fn spawn_purge(&self, interval: Duration) -> PurgeTask
+    where
+        S: Clone + 'static,
+    {
+        self.core.spawn_purge(interval)
+    }
+}
+
+/// A [`TokenStore`] over an [`AsyncTokenStore`], blocking the calling thread on its futures.
+///
+/// Lets a [`TokenManager`] keep its tokens in a store which only has an async API, e.g. a database
+/// client, without running an async runtime. The store must not depend on a runtime being
+/// entered, e.g. by spawning tasks.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{blocking::SyncTokenStore, MemoryTokenStore, SpotifyToken, TokenStore};
+/// // Any ``AsyncTokenStore``, e.g. over a database client.
+/// let store = SyncTokenStore::new(MemoryTokenStore::default());
+/// # let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "NgAagA"}"#).unwrap();
+///
+/// store.save("alice", &token).unwrap();
+/// # assert_eq!(store.load("alice").unwrap().unwrap().access_token, "NgCXRK");
+/// ```
+#[derive(Debug, Default)]
+pub struct SyncTokenStore<T> {
+    inner: T,
+}
+
+impl<T> SyncTokenStore<T> {
+    /// Block on the futures of ``inner``.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// The async store.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: crate::AsyncTokenStore + Send + Sync> TokenStore for SyncTokenStore<T> {
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        block_on(self.inner.load(key))
+    }
+
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        block_on(self.inner.save(key, token))
+    }
+
+    fn remove(&self, key: &str) -> SpotifyResult<()> {
+        block_on(self.inner.remove(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{MockHttpClient, MockResponse},
+        MemoryTokenStore, MockClock,
+    };
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use std::{sync::Mutex, thread};
+
+    #[test]
+    fn test_token_manager_refreshes_expired_token() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice");
+
+        assert_eq!(manager.token().unwrap(), None);
+
+        manager
+            .save(&SpotifyToken::fixture().expires_at(1_600_000_000).build())
+            .unwrap();
+        let token = manager.token().unwrap().unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(manager.token().unwrap(), Some(token));
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_token_manager_refreshes_within_margin() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice")
+            .with_refresh_margin(Duration::from_secs(60));
+
+        manager
+            .save(&SpotifyToken::fixture().expires_in(30).build())
+            .unwrap();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRL");
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    /// A store which another process writes ``other`` to right after the first load.
+    struct RacingStore {
+        inner: MemoryTokenStore,
+        other: Mutex<Option<SpotifyToken>>,
+    }
+
+    impl TokenStore for RacingStore {
+        fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+            let token = self.inner.load(key);
+            if let Some(other) = self.other.lock().unwrap().take() {
+                self.inner.save(key, &other)?;
+            }
+            token
+        }
+
+        fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+            self.inner.save(key, token)
+        }
+
+        fn remove(&self, key: &str) -> SpotifyResult<()> {
+            self.inner.remove(key)
+        }
+
+        fn compare_and_swap(
+            &self,
+            key: &str,
+            current: &SpotifyToken,
+            token: &SpotifyToken,
+        ) -> SpotifyResult<bool> {
+            self.inner.compare_and_swap(key, current, token)
+        }
+    }
+
+    #[test]
+    fn test_token_manager_refresh_failure_policy() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::status(503, "Service Unavailable"))
+            .with(MockResponse::status(503, "Service Unavailable"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let token = SpotifyToken::fixture()
+            .access_token("NgCXRK")
+            .expires_in(30);
+
+        let manager = TokenManager::new(client.clone(), MemoryTokenStore::default(), "alice")
+            .with_refresh_margin(Duration::from_secs(60))
+            .with_refresh_failure_policy(RefreshFailurePolicy::ServeStale);
+        manager.save(&token.clone().build()).unwrap();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRK");
+
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice")
+            .with_refresh_margin(Duration::from_secs(60));
+        manager.save(&token.build()).unwrap();
+        assert!(manager.token().is_err());
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[cfg(feature = "backoff")]
+    #[test]
+    fn test_token_manager_retries_with_backoff() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::transport_error("connection reset"))
+            .with(MockResponse::status(503, "Service Unavailable"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"))
+            .with(MockResponse::status(503, "Service Unavailable"));
+        let policy = backoff::ExponentialBackoffBuilder::new()
+            .with_initial_interval(Duration::from_millis(1))
+            .with_max_elapsed_time(Some(Duration::from_millis(50)))
+            .build();
+        let manager = TokenManager::new(
+            Client::with_http(AppClient::new("id", "secret"), &http),
+            MemoryTokenStore::default(),
+            "alice",
+        )
+        .with_refresh_failure_policy(RefreshFailurePolicy::retry(policy));
+
+        manager
+            .save(
+                &SpotifyToken::fixture()
+                    .access_token("NgCXRK")
+                    .expired()
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRL");
+        assert_eq!(http.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_token_manager_lifecycle() {
+        let store = std::sync::Arc::new(MemoryTokenStore::default());
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let manager = TokenManager::new(
+            Client::with_http(AppClient::new("id", "secret"), &http),
+            store.clone(),
+            "alice",
+        );
+        manager
+            .save(
+                &SpotifyToken::fixture()
+                    .access_token("NgCXRK")
+                    .expired()
+                    .build(),
+            )
+            .unwrap();
+
+        manager.pause_refresh();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRK");
+        assert!(http.requests().is_empty());
+
+        manager.start();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRL");
+
+        // Waits a second at most for the purge thread to stop.
+        let stopped = |task: &PurgeTask| {
+            let thread = task.thread.as_ref().unwrap();
+            (0..100).any(|_| {
+                thread.is_finished() || {
+                    thread::sleep(Duration::from_millis(10));
+                    false
+                }
+            })
+        };
+
+        let task = manager.spawn_purge(Duration::from_secs(3600));
+        manager.shutdown();
+        assert!(stopped(&task));
+        assert!(matches!(
+            manager.token(),
+            Err(SpotifyError::TokenFailure { .. })
+        ));
+
+        manager.start();
+        let task = manager.spawn_purge(Duration::from_secs(3600));
+        drop(manager);
+        assert!(stopped(&task));
+    }
+
+    #[test]
+    fn test_token_manager_streams_updates() {
+        use futures_lite::StreamExt;
+
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice");
+        let updates = manager.updates();
+        drop(manager.updates());
+
+        manager
+            .save(
+                &SpotifyToken::fixture()
+                    .access_token("NgCXRK")
+                    .expired()
+                    .build(),
+            )
+            .unwrap();
+        manager.token().unwrap();
+        manager.token().unwrap();
+        assert_eq!(manager.core.subscribers.lock().unwrap().len(), 1);
+
+        drop(manager);
+        let tokens: Vec<_> = block_on(updates.collect());
+        let access_tokens: Vec<_> = tokens.iter().map(|x| x.access_token.as_str()).collect();
+        assert_eq!(access_tokens, ["NgCXRK", "NgCXRL"]);
+    }
+
+    #[test]
+    fn test_token_manager_valid_token_guard() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRL", None, "streaming"))
+            .with(MockResponse::token("NgCXRM", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let clock = Arc::new(MockClock::default());
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice")
+            .with_clock(clock.clone());
+        let minutes = |x: u64| Duration::from_secs(60 * x);
+
+        let err = manager.get_valid_token(minutes(1)).unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+
+        manager
+            .save(&SpotifyToken::fixture().expires_in(600).build_with(&*clock))
+            .unwrap();
+        let token = manager.get_valid_token(minutes(5)).unwrap();
+        assert_eq!(token.remaining(), minutes(10));
+        assert!(http.requests().is_empty());
+
+        clock.advance(chrono::Duration::minutes(2));
+        assert_eq!(token.remaining(), minutes(8));
+
+        let token = manager.get_valid_token(minutes(15)).unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(token.remaining(), minutes(60));
+        assert_eq!(http.requests().len(), 1);
+
+        // Spotify issues tokens for an hour.
+        let err = manager.get_valid_token(minutes(90)).unwrap_err();
+        assert!(matches!(err, SpotifyError::TokenFailure { .. }));
+    }
+
+    #[test]
+    fn test_token_manager_ignores_system_clock_changes() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRL", None, "streaming"))
+            .with(MockResponse::token("NgCXRM", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let clock = Arc::new(MockClock::default());
+        let manager = TokenManager::new(client, MemoryTokenStore::default(), "alice")
+            .with_clock(clock.clone());
+        let issued = clock.now();
+
+        manager
+            .save(&SpotifyToken::fixture().build_with(&*clock))
+            .unwrap();
+
+        // An hour later the system clock is set back by an hour.
+        clock.advance(chrono::Duration::hours(1));
+        clock.set(issued);
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRL");
+        assert_eq!(http.requests().len(), 1);
+
+        // The monotonic clock stands still while the machine is suspended for two hours.
+        clock.set(issued + chrono::Duration::hours(2));
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRM");
+
+        // Tokens stored by other processes are checked against the wall clock.
+        let other = SpotifyToken::fixture()
+            .access_token("b3RoZXI")
+            .build_with(&*clock);
+        manager.core.store.save("alice", &other).unwrap();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "b3RoZXI");
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_token_manager_keeps_token_refreshed_elsewhere() {
+        let other = SpotifyToken::fixture()
+            .access_token("b3RoZXI")
+            .refresh_token("cm90YXRlZA")
+            .build();
+        let manager = |response| {
+            let store = RacingStore {
+                inner: MemoryTokenStore::default(),
+                other: Mutex::new(Some(other.clone())),
+            };
+            store
+                .inner
+                .save(
+                    "alice",
+                    &SpotifyToken::fixture().expires_at(1_600_000_000).build(),
+                )
+                .unwrap();
+            let http = MockHttpClient::new().with(response);
+            TokenManager::new(
+                Client::with_http(AppClient::new("id", "secret"), http),
+                store,
+                "alice",
+            )
+        };
+
+        // The refreshed token loses against the one stored by the other process.
+        let racing = manager(MockResponse::token("NgCXRL", Some("bG9zdA"), ""));
+        assert_eq!(racing.token().unwrap(), Some(other.clone()));
+        assert_eq!(
+            racing.core.store.load("alice").unwrap(),
+            Some(other.clone())
+        );
+
+        // The refresh fails since the other process rotated the refresh token.
+        let racing = manager(MockResponse::oauth_error(
+            "invalid_grant",
+            "Invalid refresh token",
+        ));
+        assert_eq!(racing.token().unwrap(), Some(other));
+    }
+
+    #[test]
+    fn test_token_manager_waits_for_refresh_lease() {
+        let dir = std::env::temp_dir().join(format!("spotify-oauth-lease-{}", std::process::id()));
+        let lock = FileRefreshLock::new(&dir);
+        let store = std::sync::Arc::new(MemoryTokenStore::default());
+        let http = MockHttpClient::new();
+        let manager = TokenManager::new(
+            Client::with_http(AppClient::new("id", "secret"), &http),
+            store.clone(),
+            "alice",
+        )
+        .with_refresh_lock(lock.clone());
+        store
+            .save(
+                "alice",
+                &SpotifyToken::fixture().expires_at(1_600_000_000).build(),
+            )
+            .unwrap();
+
+        // Another process is refreshing the token.
+        let lease = lock.acquire("alice").unwrap();
+        let token = thread::scope(|scope| {
+            let waiting = scope.spawn(|| manager.token());
+            thread::sleep(Duration::from_millis(50));
+            store
+                .save(
+                    "alice",
+                    &SpotifyToken::fixture().access_token("b3RoZXI").build(),
+                )
+                .unwrap();
+            drop(lease);
+            waiting.join().unwrap()
+        });
+
+        assert_eq!(token.unwrap().unwrap().access_token, "b3RoZXI");
+        assert!(http.requests().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_token_manager_purges_store_periodically() {
+        let store = std::sync::Arc::new(MemoryTokenStore::default());
+        let http = MockHttpClient::new();
+        let manager = TokenManager::new(
+            Client::with_http(AppClient::new("id", "secret"), &http),
+            store.clone(),
+            "alice",
+        );
+        let dead = SpotifyToken::fixture()
+            .expires_at(1_600_000_000)
+            .without_refresh_token()
+            .build();
+        store.save("bob", &dead).unwrap();
+
+        let task = manager.spawn_purge(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+        drop(task);
+
+        assert_eq!(store.purge_expired(Utc::now()).unwrap(), 0);
+    }
+
+    /// A store with only an async API, yielding once before every operation.
+    #[derive(Default)]
+    struct YieldingStore(MemoryTokenStore);
+
+    #[async_trait(?Send)]
+    impl crate::AsyncTokenStore for YieldingStore {
+        async fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+            futures_lite::future::yield_now().await;
+            TokenStore::load(&self.0, key)
+        }
+
+        async fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+            futures_lite::future::yield_now().await;
+            TokenStore::save(&self.0, key, token)
+        }
+
+        async fn remove(&self, key: &str) -> SpotifyResult<()> {
+            futures_lite::future::yield_now().await;
+            TokenStore::remove(&self.0, key)
+        }
+    }
+
+    #[test]
+    fn test_token_manager_over_async_store() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let manager =
+            TokenManager::new(client, SyncTokenStore::<YieldingStore>::default(), "alice");
+
+        manager
+            .save(&SpotifyToken::fixture().expires_at(1_600_000_000).build())
+            .unwrap();
+        assert_eq!(manager.token().unwrap().unwrap().access_token, "NgCXRL");
+
+        manager.remove().unwrap();
+        assert_eq!(manager.token().unwrap(), None);
+    }
+
+    #[test]
+    fn test_token_managers_by_scope_set() {
+        let store = std::sync::Arc::new(MemoryTokenStore::default());
+        let http = MockHttpClient::new();
+        let client = Client::with_http(AppClient::new("id", "secret"), &http);
+        let minimal = [SpotifyScope::UserReadEmail];
+        let full = [SpotifyScope::UserReadEmail, SpotifyScope::Streaming];
+        let manager = |scope: &[SpotifyScope]| {
+            TokenManager::for_scopes(client.clone(), store.clone(), "alice", scope)
+        };
+
+        manager(&minimal)
+            .save(&SpotifyToken::fixture().access_token("bWluaW1hbA").build())
+            .unwrap();
+        manager(&full)
+            .save(&SpotifyToken::fixture().access_token("ZnVsbA").build())
+            .unwrap();
+
+        let token = |scope: &[SpotifyScope]| manager(scope).token().unwrap().unwrap().access_token;
+        assert_eq!(token(&minimal), "bWluaW1hbA");
+        assert_eq!(
+            token(&[SpotifyScope::Streaming, SpotifyScope::UserReadEmail]),
+            "ZnVsbA"
+        );
+        assert_eq!(manager(&[SpotifyScope::Streaming]).token().unwrap(), None);
+    }
+}
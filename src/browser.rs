@@ -0,0 +1,33 @@
+//! Launching the system browser, only available with the ``open`` feature.
+
+use crate::error::*;
+use snafu::ResultExt;
+use std::process::Command;
+
+/// Open a URL in the default browser of the user.
+///
+/// Inside the Windows Subsystem for Linux the URL is handed to the Windows browser, since there
+/// usually is no Linux browser installed.
+pub fn open_url(url: &str) -> SpotifyResult<()> {
+    if is_wsl() {
+        return Command::new("rundll32.exe")
+            .args(["url.dll,FileProtocolHandler", url])
+            .status()
+            .map(|_| ())
+            .context(BrowserFailure);
+    }
+
+    open::that(url).context(BrowserFailure)
+}
+
+/// Whether the process runs inside the Windows Subsystem for Linux.
+pub fn is_wsl() -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .map(|x| x.to_lowercase().contains("microsoft"))
+            .unwrap_or(false)
+}
@@ -1,7 +1,6 @@
 use crate::{error, error::*};
-use snafu::ResultExt;
 use std::{str::FromStr, string::ToString};
-use url::Url;
+use url::{form_urlencoded, Url};
 
 /// The Spotify Callback URL
 ///
@@ -32,6 +31,12 @@ pub struct SpotifyCallback {
 
 /// Implementation of FromStr for Spotify Callback URLs.
 ///
+/// The parameters are read from the query, or from the fragment if the query contains none of
+/// them, as browser apps may receive them there.
+///
+/// Pasted input is normalized first: surrounding whitespace and line breaks as well as quotes,
+/// backticks and angle brackets added by terminals and chat clients are removed.
+///
 /// # Example
 ///
 /// ```
@@ -46,11 +51,23 @@ impl FromStr for SpotifyCallback {
     type Err = error::SpotifyError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let url = Url::parse(s).context(UrlError)?;
-        let parsed: Vec<(String, String)> = url
-            .query_pairs()
-            .map(|x| (x.0.into_owned(), x.1.into_owned()))
-            .collect();
+        let s = normalize_pasted(s);
+        if s.is_empty() {
+            return Err(SpotifyError::CallbackFailure {
+                context: "The input is empty, paste the URL the browser was redirected to.",
+            });
+        }
+
+        let url = Url::parse(s).map_err(|_| SpotifyError::CallbackFailure {
+            context: "Not a URL, paste the complete URL from the address bar of the browser, \
+                      starting with http:// or https://.",
+        })?;
+        let mut parsed = callback_pairs(url.query_pairs());
+        if parsed.is_empty() {
+            if let Some(fragment) = url.fragment() {
+                parsed = callback_pairs(form_urlencoded::parse(fragment.as_bytes()));
+            }
+        }
 
         let has_state = parsed.iter().any(|x| x.0 == "state");
         let has_response = parsed.iter().any(|x| x.0 == "error" || x.0 == "code");
@@ -99,6 +116,34 @@ impl FromStr for SpotifyCallback {
     }
 }
 
+/// Strip whitespace and the wrapping characters a pasted URL commonly picks up, e.g.
+/// ``"http://…"``, ``<http://…>`` or a trailing newline.
+fn normalize_pasted(mut s: &str) -> &str {
+    loop {
+        let trimmed = s.trim();
+        let unwrapped = [('"', '"'), ('\'', '\''), ('`', '`'), ('<', '>')]
+            .iter()
+            .find_map(|(open, close)| {
+                trimmed
+                    .strip_prefix(*open)
+                    .and_then(|x| x.strip_suffix(*close))
+            });
+
+        match unwrapped {
+            Some(inner) => s = inner,
+            None => return trimmed,
+        }
+    }
+}
+
+/// The callback parameters among the given pairs.
+fn callback_pairs(pairs: form_urlencoded::Parse) -> Vec<(String, String)> {
+    pairs
+        .filter(|x| matches!(x.0.as_ref(), "code" | "error" | "state"))
+        .map(|x| (x.0.into_owned(), x.1.into_owned()))
+        .collect()
+}
+
 /// Conversion and helper functions for SpotifyCallback.
 impl SpotifyCallback {
     /// Create a new Spotify Callback object with given values.
@@ -114,6 +159,68 @@ impl SpotifyCallback {
     pub fn new(code: Option<String>, error: Option<String>, state: String) -> Self {
         Self { code, error, state }
     }
+
+    /// The authorization code, if the user granted access.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The reason authorization failed, e.g. ``access_denied``.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// The value of the ``state`` parameter, compare it with the state of the authorization.
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Take the authorization code out of the callback, for [`AppClient::exchange`](crate::AppClient::exchange).
+    ///
+    /// A callback carrying an error, e.g. ``access_denied``, yields a [`SpotifyError::Protocol`] error.
+    pub fn into_code(self) -> SpotifyResult<AuthorizationCode> {
+        match (self.code, self.error) {
+            (Some(x), _) => Ok(AuthorizationCode(x)),
+            (None, Some(error)) => Err(SpotifyError::Protocol {
+                status: None,
+                error,
+                description: None,
+            }),
+            (None, None) => Err(SpotifyError::TokenFailure {
+                context: "Spotify callback code failed to parse.",
+            }),
+        }
+    }
+}
+
+/// An authorization code, consumed by the exchange for a token.
+///
+/// Codes are single use, Spotify rejects a second exchange with ``invalid_grant``. The code is
+/// neither ``Clone`` nor ``Copy`` and [`AppClient::exchange`](crate::AppClient::exchange) takes it
+/// by value, so exchanging it twice does not compile:
+///
+/// ```compile_fail
+/// # use spotify_oauth::{AppClient, AuthorizationCode, HttpClient};
+/// # use url::Url;
+/// # async fn run(app: AppClient, http: impl HttpClient, redirect_uri: Url) {
+/// let code = AuthorizationCode::new("NApCCgBkWtQ");
+/// app.exchange(&http, code, &redirect_uri).await;
+/// app.exchange(&http, code, &redirect_uri).await;
+/// # }
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuthorizationCode(String);
+
+impl AuthorizationCode {
+    /// Wrap a code received by other means than a [`SpotifyCallback`].
+    pub fn new(code: impl Into<String>) -> Self {
+        Self(code.into())
+    }
+
+    /// The code as sent to the token endpoint.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 #[cfg(test)]
@@ -130,6 +237,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_callback_into_code() {
+        let code =
+            SpotifyCallback::from_str("http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN")
+                .unwrap()
+                .into_code()
+                .unwrap();
+        assert_eq!(code.as_str(), "AQD0yXvFEOvw");
+
+        let err = SpotifyCallback::from_str(
+            "http://localhost:8888/callback?error=access_denied&state=sN",
+        )
+        .unwrap()
+        .into_code()
+        .unwrap_err();
+        assert!(
+            matches!(err, SpotifyError::Protocol { ref error, .. } if error == "access_denied")
+        );
+    }
+
     #[test]
     fn test_parse_callback_error() {
         let url = String::from("http://localhost:8888/callback?error=access_denied&state=sN");
@@ -140,6 +267,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_callback_fragment() {
+        let url = String::from("http://localhost:8888/app?page=1#code=AQD0yXvFEOvw&state=sN");
+
+        assert_eq!(
+            SpotifyCallback::from_str(&url).unwrap(),
+            SpotifyCallback::new(Some("AQD0yXvFEOvw".to_string()), None, "sN".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_pasted_callback() {
+        let expected =
+            SpotifyCallback::new(Some("AQD0yXvFEOvw".to_string()), None, "sN".to_string());
+
+        for input in [
+            "http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN\r\n",
+            "  \"http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN\"\n",
+            "'http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN'",
+            "<http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN>",
+            "`<http://localhost:8888/callback?code=AQD0yXvFEOvw&state=sN>`",
+        ] {
+            assert_eq!(
+                SpotifyCallback::from_str(input).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+
+        for input in ["", " \"\" \n", "code=AQD0yXvFEOvw&state=sN"] {
+            assert!(
+                matches!(
+                    SpotifyCallback::from_str(input),
+                    Err(SpotifyError::CallbackFailure { .. })
+                ),
+                "{:?}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_invalid_response_parse() {
         let url = String::from("http://localhost:8888/callback?state=sN");
@@ -0,0 +1,169 @@
+use crate::error::*;
+#[cfg(feature = "timeout")]
+use std::time::Duration;
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Poll, Waker},
+};
+
+/// A flag to abort running operations, e.g. when the user closes the login dialog.
+///
+/// Clones share the flag. Pass [`CancellationToken::cancelled`] to [`with_cancellation`] to
+/// abort an operation, or poll [`CancellationToken::is_cancelled`] in a loop.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{with_cancellation, CancellationToken, SpotifyError};
+/// # async_std::task::block_on(async {
+/// let token = CancellationToken::new();
+/// token.clone().cancel();
+///
+/// let result = with_cancellation(std::future::pending::<spotify_oauth::SpotifyResult<()>>(), token.cancelled()).await;
+/// assert!(matches!(result, Err(SpotifyError::Cancelled)));
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    /// A token which is not cancelled yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token and wake up everybody waiting for it.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        for waker in self.inner.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes when the token is cancelled.
+    pub async fn cancelled(&self) {
+        poll_fn(|cx| {
+            if self.is_cancelled() {
+                return Poll::Ready(());
+            }
+
+            let mut wakers = self.inner.wakers.lock().unwrap();
+            if !wakers.iter().any(|x| x.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            drop(wakers);
+
+            // Cancelled while the waker was registered.
+            match self.is_cancelled() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Run ``operation`` until it completes or ``cancel`` completes first.
+///
+/// A cancelled operation is dropped and yields [`SpotifyError::Cancelled`]. ``cancel`` can be any
+/// future, e.g. [`CancellationToken::cancelled`], a timer or a channel of the UI. Waiting for a
+/// callback is cancel safe, the server is stopped and a callback can be awaited again with a new
+/// login. A cancelled code exchange or refresh may have reached Spotify already, in which case
+/// the authorization code is used up and the login has to be restarted.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{with_cancellation, AppClient, CancellationToken, SurfClient};
+/// # async fn run(app: AppClient, refresh_token: &str, token: CancellationToken) -> spotify_oauth::SpotifyResult<()> {
+/// let http = SurfClient::new();
+/// let token = with_cancellation(app.refresh(&http, refresh_token), token.cancelled()).await?;
+/// # Ok(()) }
+/// ```
+pub async fn with_cancellation<T>(
+    operation: impl Future<Output = SpotifyResult<T>>,
+    cancel: impl Future<Output = ()>,
+) -> SpotifyResult<T> {
+    race(operation, cancel, || SpotifyError::Cancelled).await
+}
+
+/// Run ``operation`` until it completes or fail with [`SpotifyError::Timeout`] once ``deadline``
+/// passed.
+///
+/// The deadline covers the whole operation, independent of the socket timeouts of the HTTP
+/// client. The timer does not need a specific async runtime.
+///
+/// Only available with the ``timeout`` feature.
+#[cfg(feature = "timeout")]
+pub async fn with_deadline<T>(
+    operation: impl Future<Output = SpotifyResult<T>>,
+    deadline: Duration,
+) -> SpotifyResult<T> {
+    let timer = futures_timer::Delay::new(deadline);
+
+    race(operation, timer, || SpotifyError::Timeout {
+        after: deadline,
+    })
+    .await
+}
+
+/// Poll ``operation`` until it completes or fail with ``error`` once ``abort`` completed.
+async fn race<T>(
+    operation: impl Future<Output = SpotifyResult<T>>,
+    abort: impl Future<Output = ()>,
+    error: impl Fn() -> SpotifyError,
+) -> SpotifyResult<T> {
+    let mut operation = pin!(operation);
+    let mut abort = pin!(abort);
+
+    poll_fn(|cx| {
+        if abort.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(error()));
+        }
+
+        operation.as_mut().poll(cx)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{future::pending, thread, time::Duration};
+
+    #[async_std::test]
+    async fn test_cancel_pending_operation() {
+        let token = CancellationToken::new();
+        let result = with_cancellation(async { Ok(1) }, token.cancelled()).await;
+        assert_eq!(result.unwrap(), 1);
+
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            canceller.cancel();
+        });
+
+        let result = with_cancellation(pending::<SpotifyResult<()>>(), token.cancelled()).await;
+        assert!(matches!(result, Err(SpotifyError::Cancelled)));
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,612 @@
+use crate::{
+    error::*,
+    protocol::{self, ParseMode},
+    util::execute,
+    AuthorizationCode, Clock, Endpoints, Header, HttpClient, SpotifyAuth, SpotifyCallback,
+    SpotifyScope, SpotifyToken, SystemClock,
+};
+#[cfg(feature = "timeout")]
+use std::time::Duration;
+use std::{fmt, sync::Arc};
+use url::Url;
+
+/// The credentials of a Spotify application together with the endpoints to use them with.
+///
+/// All token requests borrow the client, so one instance can be shared by every login and refresh
+/// of an application.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{AppClient, SpotifyCallback, SurfClient};
+/// # use std::str::FromStr;
+/// # use url::Url;
+/// # #[async_std::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// let app = AppClient::new("00000000000", "secret");
+/// let redirect_uri = Url::parse("http://localhost:8000/callback")?;
+///
+/// let callback = SpotifyCallback::from_str("http://localhost:8000/callback?code=NApCCgBkWtQ&state=test")?;
+/// let token = app.exchange_callback(&SurfClient::new(), &callback, &redirect_uri).await?;
+/// if let Some(refresh_token) = &token.refresh_token {
+///     let token = app.refresh(&SurfClient::new(), refresh_token).await?;
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct AppClient {
+    /// The Spotify Application Client ID
+    pub client_id: String,
+    /// The Spotify Application Client Secret
+    pub client_secret: String,
+    /// The URLs of the Spotify Accounts service.
+    pub endpoints: Endpoints,
+    /// A header sent with every token request, so failures can be matched against the logs of
+    /// gateways and the application, see [`AppClient::with_correlation_id`].
+    pub correlation_id: Option<Header>,
+    /// How strictly token responses and callbacks are checked, see [`AppClient::strict`].
+    pub mode: ParseMode,
+    /// Scopes every exchanged or refreshed token must grant, see [`AppClient::require_scopes`].
+    pub required_scopes: Vec<SpotifyScope>,
+    /// The clock stamping the ``expires_at`` of exchanged and refreshed tokens, see
+    /// [`AppClient::with_clock`]. Clients are compared without it.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl PartialEq for AppClient {
+    fn eq(&self, other: &Self) -> bool {
+        self.client_id == other.client_id
+            && self.client_secret == other.client_secret
+            && self.endpoints == other.endpoints
+            && self.correlation_id == other.correlation_id
+            && self.mode == other.mode
+            && self.required_scopes == other.required_scopes
+    }
+}
+
+impl Eq for AppClient {}
+
+/// Masks the client secret, so logging a client or a token manager does not leak it.
+impl fmt::Debug for AppClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppClient")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"***")
+            .field("endpoints", &self.endpoints)
+            .field("correlation_id", &self.correlation_id)
+            .field("mode", &self.mode)
+            .field("required_scopes", &self.required_scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AppClient {
+    /// Create a client for the real Spotify Accounts service.
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            endpoints: Endpoints::default(),
+            correlation_id: None,
+            mode: ParseMode::Tolerant,
+            required_scopes: Vec::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use the given endpoints instead of the real Spotify Accounts service.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Stamp the ``expires_at`` of exchanged and refreshed tokens with ``clock`` instead of the
+    /// system clock, e.g. a [`MockClock`](crate::MockClock) in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Send the correlation ID in the ``X-Correlation-ID`` header of every token request.
+    ///
+    /// Set it once for the whole application or per call on a clone of the client. Gateways
+    /// expecting another header, e.g. ``X-Request-ID``, are served by setting
+    /// [`AppClient::correlation_id`] directly.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{AppClient, SurfClient};
+    /// # async fn run(app: AppClient, request_id: &str) -> spotify_oauth::SpotifyResult<()> {
+    /// let token = app
+    ///     .clone()
+    ///     .with_correlation_id(request_id)
+    ///     .refresh(&SurfClient::new(), "NgAagAHfVxDkSvCUm_SHo")
+    ///     .await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn with_correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+        self.correlation_id = Some(Header::new("X-Correlation-ID", correlation_id));
+        self
+    }
+
+    /// Fail fast on anything unexpected instead of tolerating it.
+    ///
+    /// Token responses with fields or scopes unknown to this library or a non-integer
+    /// ``expires_in`` and callbacks with an empty state are rejected. Compare the granted scopes
+    /// with [`SpotifyToken::ensure_scopes`], or let [`AuthFlow::strict`](crate::AuthFlow::strict)
+    /// do it.
+    pub fn strict(mut self) -> Self {
+        self.mode = ParseMode::Strict;
+        self
+    }
+
+    /// Fail with [`SpotifyError::MissingScopes`], listing the missing ones, if an exchanged or
+    /// refreshed token does not grant all of ``scopes``.
+    ///
+    /// For apps which cannot work with partial consent, e.g. when the user deselected scopes in
+    /// the consent dialog. Additional scopes are accepted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{AppClient, SpotifyScope};
+    /// let app = AppClient::new("00000000000", "secret")
+    ///     .require_scopes(vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail]);
+    /// ```
+    pub fn require_scopes(mut self, scopes: Vec<SpotifyScope>) -> Self {
+        self.required_scopes = scopes;
+        self
+    }
+
+    /// Exchange the authorization code of a callback for a token.
+    ///
+    /// A callback carrying an error, e.g. ``access_denied``, yields a [`SpotifyError::Protocol`] error.
+    pub async fn exchange_callback<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        callback: &SpotifyCallback,
+        redirect_uri: &Url,
+    ) -> SpotifyResult<SpotifyToken> {
+        if self.mode == ParseMode::Strict {
+            protocol::check_callback_strict(callback)?;
+        }
+
+        match (callback.code(), callback.error()) {
+            (Some(x), _) => self.exchange_code(http, x, redirect_uri).await,
+            (None, Some(error)) => Err(SpotifyError::Protocol {
+                status: None,
+                error: error.to_string(),
+                description: None,
+            }),
+            (None, None) => Err(SpotifyError::TokenFailure {
+                context: "Spotify callback code failed to parse.",
+            }),
+        }
+    }
+
+    /// Exchange an authorization code for a token.
+    ///
+    /// ``redirect_uri`` must be the redirect URI the authorization was requested with.
+    pub async fn exchange_code<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        code: &str,
+        redirect_uri: &Url,
+    ) -> SpotifyResult<SpotifyToken> {
+        let request = protocol::exchange_request(self, code, redirect_uri);
+
+        execute(http, request, |x| {
+            self.check(x)?;
+            protocol::finish_exchange(x, &*self.clock).and_then(|x| self.check_scopes(x))
+        })
+        .await
+    }
+
+    /// Exchange an authorization code for a token, consuming the code.
+    ///
+    /// Like [`AppClient::exchange_code`], but the single use of the code is checked at compile
+    /// time, see [`AuthorizationCode`].
+    pub async fn exchange<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        code: AuthorizationCode,
+        redirect_uri: &Url,
+    ) -> SpotifyResult<SpotifyToken> {
+        self.exchange_code(http, code.as_str(), redirect_uri).await
+    }
+
+    /// Request a new access token using the refresh token of a previous authorization.
+    ///
+    /// See [`refresh_access_token`](crate::refresh_access_token) for details.
+    pub async fn refresh<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        refresh_token: &str,
+    ) -> SpotifyResult<SpotifyToken> {
+        let request = protocol::refresh_request(self, refresh_token);
+
+        execute(http, request, |x| {
+            self.check(x)?;
+            protocol::finish_refresh(x, refresh_token, &*self.clock)
+                .and_then(|x| self.check_scopes(x))
+        })
+        .await
+    }
+
+    /// Check a response according to the parse mode.
+    fn check(&self, response: &crate::HttpResponse) -> SpotifyResult<()> {
+        match self.mode {
+            ParseMode::Tolerant => Ok(()),
+            ParseMode::Strict => protocol::check_response_strict(response),
+        }
+    }
+
+    /// Check that the token grants the required scopes.
+    fn check_scopes(&self, token: SpotifyToken) -> SpotifyResult<SpotifyToken> {
+        let granted = token.scope_set();
+        let mut missing: Vec<String> = Vec::new();
+        for scope in self.required_scopes.iter().map(ToString::to_string) {
+            if !granted.contains(&scope) && !missing.contains(&scope) {
+                missing.push(scope);
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(SpotifyError::MissingScopes { missing });
+        }
+
+        Ok(token)
+    }
+
+    /// Like [`AppClient::exchange_callback`], but fails with [`SpotifyError::Timeout`] if no
+    /// token arrived within ``deadline``.
+    ///
+    /// Only available with the ``timeout`` feature.
+    #[cfg(feature = "timeout")]
+    pub async fn exchange_with_deadline<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        callback: &SpotifyCallback,
+        redirect_uri: &Url,
+        deadline: Duration,
+    ) -> SpotifyResult<SpotifyToken> {
+        crate::with_deadline(
+            self.exchange_callback(http, callback, redirect_uri),
+            deadline,
+        )
+        .await
+    }
+
+    /// Like [`AppClient::refresh`], but fails with [`SpotifyError::Timeout`] if no token arrived
+    /// within ``deadline``.
+    ///
+    /// Only available with the ``timeout`` feature.
+    #[cfg(feature = "timeout")]
+    pub async fn refresh_with_deadline<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        refresh_token: &str,
+        deadline: Duration,
+    ) -> SpotifyResult<SpotifyToken> {
+        crate::with_deadline(self.refresh(http, refresh_token), deadline).await
+    }
+
+    /// Like [`AppClient::refresh`], but retries transport failures, rate limits and server errors
+    /// with the delays of a [`backoff`] policy until the policy gives up.
+    ///
+    /// Other failures, e.g. a revoked refresh token, are returned at once. The policy is reset
+    /// before the first attempt.
+    ///
+    /// Only available with the ``backoff`` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{AppClient, SurfClient};
+    /// # use std::time::Duration;
+    /// # async fn run(app: AppClient, refresh_token: &str) -> spotify_oauth::SpotifyResult<()> {
+    /// let policy = backoff::ExponentialBackoffBuilder::new()
+    ///     .with_multiplier(2.0)
+    ///     .with_max_elapsed_time(Some(Duration::from_secs(60)))
+    ///     .build();
+    /// let token = app.refresh_with_backoff(&SurfClient::new(), refresh_token, policy).await?;
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "backoff")]
+    pub async fn refresh_with_backoff<C: HttpClient + ?Sized>(
+        &self,
+        http: &C,
+        refresh_token: &str,
+        mut policy: impl backoff::backoff::Backoff,
+    ) -> SpotifyResult<SpotifyToken> {
+        policy.reset();
+
+        loop {
+            match self.refresh(http, refresh_token).await {
+                Err(err) if crate::util::is_retryable(&err) => match policy.next_backoff() {
+                    Some(delay) => futures_timer::Delay::new(delay).await,
+                    None => return Err(err),
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The credentials and endpoints of the authorization.
+impl From<&SpotifyAuth> for AppClient {
+    fn from(auth: &SpotifyAuth) -> Self {
+        Self {
+            client_id: auth.client_id.clone(),
+            client_secret: auth.client_secret.clone(),
+            endpoints: auth.endpoints.clone(),
+            correlation_id: None,
+            mode: ParseMode::Tolerant,
+            required_scopes: Vec::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl SpotifyToken {
+    /// Refresh the token in place if the access token expired, reporting whether it did.
+    ///
+    /// For apps keeping a single token around without a token manager. Fails with
+    /// [`SpotifyError::NeedsReauthorization`] if the token expired and has no refresh token.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{AppClient, SpotifyToken, SurfClient};
+    /// # async fn call(app: &AppClient, token: &mut SpotifyToken) -> spotify_oauth::SpotifyResult<()> {
+    /// if token.refresh_if_expired(&SurfClient::new(), app).await? {
+    ///     // Save the refreshed token.
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn refresh_if_expired<C: HttpClient + ?Sized>(
+        &mut self,
+        http: &C,
+        app: &AppClient,
+    ) -> SpotifyResult<bool> {
+        if !self.is_expired() {
+            return Ok(false);
+        }
+
+        let refresh_token = self
+            .refresh_token
+            .as_deref()
+            .ok_or(SpotifyError::NeedsReauthorization { description: None })?;
+        *self = app.refresh(http, refresh_token).await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHttpClient, MockResponse};
+    #[cfg(any(feature = "timeout", feature = "backoff"))]
+    use std::time::Duration;
+
+    #[test]
+    fn test_app_client_debug_masks_secret() {
+        let app = AppClient::new("id", "c2VjcmV0");
+        let debug = format!("{:?}", app);
+
+        assert!(debug.contains(r#"client_secret: "***""#));
+        assert!(!debug.contains("c2VjcmV0"));
+    }
+
+    #[async_std::test]
+    async fn test_app_client_borrows_configuration() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRK", Some("NgAagA"), "streaming"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+        let redirect_uri = Url::parse("http://localhost:8888/callback").unwrap();
+
+        let token = app
+            .exchange_code(&http, "AQD0yXvFEOvw", &redirect_uri)
+            .await
+            .unwrap();
+        let refresh_token = token.refresh_token.unwrap();
+        let token = app.refresh(&http, &refresh_token).await.unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+
+        let requests = http.requests();
+        assert_eq!(requests[0].form_field("code"), Some("AQD0yXvFEOvw"));
+        assert_eq!(requests[1].form_field("refresh_token"), Some("NgAagA"));
+        assert_eq!(app.client_id, "id");
+    }
+
+    #[async_std::test]
+    async fn test_app_client_stamps_tokens_with_its_clock() {
+        use chrono::{TimeZone, Utc};
+
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRK", Some("NgAagA"), "streaming"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let clock = crate::MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let app = AppClient::new("id", "secret").with_clock(Arc::new(clock));
+        let redirect_uri = Url::parse("http://localhost:8888/callback").unwrap();
+
+        let token = app
+            .exchange_code(&http, "AQD0yXvFEOvw", &redirect_uri)
+            .await
+            .unwrap();
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+        let token = app.refresh(&http, "NgAagA").await.unwrap();
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+        assert_eq!(app, AppClient::new("id", "secret"));
+    }
+
+    #[async_std::test]
+    async fn test_strict_mode() {
+        let extended = MockResponse::json(
+            200,
+            serde_json::json!({"access_token": "NgCXRK", "token_type": "Bearer", "expires_in": 3600, "id_token": "x"}),
+        );
+        let http = MockHttpClient::new()
+            .with(extended.clone())
+            .with(extended)
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+
+        assert!(app.refresh(&http, "NgAagA").await.is_ok());
+        let app = app.strict();
+        let err = app.refresh(&http, "NgAagA").await.unwrap_err();
+        assert!(matches!(err, SpotifyError::TokenFailure { .. }));
+        assert!(app.refresh(&http, "NgAagA").await.is_ok());
+
+        let callback = SpotifyCallback::new(Some("AQD0yXvFEOvw".into()), None, String::new());
+        let redirect_uri = Url::parse("http://localhost:8888/callback").unwrap();
+        let err = app
+            .exchange_callback(&http, &callback, &redirect_uri)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SpotifyError::CallbackFailure { .. }));
+        assert_eq!(http.requests().len(), 3);
+    }
+
+    #[async_std::test]
+    async fn test_required_scopes() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token(
+                "NgCXRK",
+                None,
+                "streaming user-read-email playlist-read-private",
+            ))
+            .with(MockResponse::token("NgCXRL", None, "user-read-email"));
+        let app = AppClient::new("id", "secret").require_scopes(vec![
+            SpotifyScope::Streaming,
+            SpotifyScope::UserReadEmail,
+            SpotifyScope::UserReadPrivate,
+            SpotifyScope::UserReadPrivate,
+        ]);
+
+        let err = app.refresh(&http, "NgAagA").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Requested scopes were not granted: user-read-private"
+        );
+
+        let app = app.require_scopes(vec![SpotifyScope::UserReadEmail]);
+        assert!(app.refresh(&http, "NgAagA").await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_refresh_if_expired() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+
+        let mut token = SpotifyToken::fixture().build();
+        assert!(!token.refresh_if_expired(&http, &app).await.unwrap());
+        assert!(http.requests().is_empty());
+
+        let mut token = SpotifyToken::fixture()
+            .refresh_token("NgAagA")
+            .expired()
+            .build();
+        assert!(token.refresh_if_expired(&http, &app).await.unwrap());
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+        assert!(!token.is_expired());
+
+        let mut token = SpotifyToken::fixture()
+            .without_refresh_token()
+            .expired()
+            .build();
+        let err = token.refresh_if_expired(&http, &app).await.unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+    }
+
+    #[async_std::test]
+    async fn test_correlation_id_header() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRK", None, "streaming"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret").with_correlation_id("b7f3c2e1");
+
+        app.refresh(&http, "NgAagA").await.unwrap();
+        app.clone()
+            .with_correlation_id("9d1e4a07")
+            .exchange_code(
+                &http,
+                "AQD0yXvFEOvw",
+                &Url::parse("http://localhost:8888/callback").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let requests = http.requests();
+        assert_eq!(requests[0].header("x-correlation-id"), Some("b7f3c2e1"));
+        assert_eq!(requests[1].header("x-correlation-id"), Some("9d1e4a07"));
+    }
+
+    #[cfg(feature = "backoff")]
+    #[async_std::test]
+    async fn test_refresh_with_backoff() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::transport_error("connection reset"))
+            .with(MockResponse::status(503, "Service Unavailable"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"))
+            .with(MockResponse::transport_error("connection reset"))
+            .with(MockResponse::oauth_error(
+                "invalid_grant",
+                "Refresh token revoked",
+            ));
+        let app = AppClient::new("id", "secret");
+        let policy = || backoff::backoff::Constant::new(Duration::from_millis(1));
+
+        let token = app
+            .refresh_with_backoff(&http, "NgAagA", policy())
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(http.requests().len(), 3);
+
+        let err = app
+            .refresh_with_backoff(&http, "NgAagA", policy())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+        assert_eq!(http.remaining(), 0);
+
+        let http = MockHttpClient::new()
+            .with(MockResponse::transport_error("connection reset"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let err = app
+            .refresh_with_backoff(&http, "NgAagA", backoff::backoff::Stop {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SpotifyError::Transport { .. }));
+        assert_eq!(http.remaining(), 1);
+    }
+
+    #[cfg(feature = "timeout")]
+    #[async_std::test]
+    async fn test_refresh_with_deadline() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRK", None, "streaming").delayed(Duration::from_secs(5)))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+        let deadline = Duration::from_millis(20);
+
+        let err = app
+            .refresh_with_deadline(&http, "NgAagA", deadline)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SpotifyError::Timeout { after } if after == deadline));
+        assert_eq!(err.kind_name(), "timeout");
+
+        let token = app
+            .refresh_with_deadline(&http, "NgAagA", deadline)
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+    }
+}
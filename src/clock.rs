@@ -0,0 +1,95 @@
+//! Time source abstraction for expiry calculations.
+
+use chrono::{DateTime, Duration, Utc};
+use std::{sync::Mutex, time::Instant};
+
+/// A source of the current time.
+///
+/// All expiry related logic of the library reads the time through this trait, so it can be
+/// replaced by a [`MockClock`] in tests.
+pub trait Clock: Send + Sync {
+    /// The current date and time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The current point on a monotonic timeline, unaffected by changes of the wall clock.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// The unix timestamp ``elapsed`` seconds from now.
+    fn timestamp_in(&self, elapsed: u32) -> i64 {
+        self.now().timestamp() + i64::from(elapsed)
+    }
+}
+
+/// The system wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A manually controlled clock for tests.
+///
+/// The wall clock and the monotonic clock can be moved independently, which allows simulating
+/// wall clock jumps (NTP adjustments, suspend/resume).
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{Clock, MockClock};
+/// # use chrono::{Duration, TimeZone, Utc};
+/// let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+/// clock.advance(Duration::seconds(60));
+/// # assert_eq!(clock.now().timestamp(), 1_600_000_060);
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    state: Mutex<(DateTime<Utc>, Instant)>,
+}
+
+impl MockClock {
+    /// Create a new clock frozen at the given time.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            state: Mutex::new((now, Instant::now())),
+        }
+    }
+
+    /// Move both clocks forward.
+    ///
+    /// Negative durations only move the wall clock backwards, since monotonic time cannot go back.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += duration;
+
+        if let Ok(x) = duration.to_std() {
+            state.1 += x;
+        }
+    }
+
+    /// Set the wall clock to the given time, leaving the monotonic clock untouched.
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.state.lock().unwrap().0 = now;
+    }
+}
+
+impl Default for MockClock {
+    /// A clock frozen at the current system time.
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().0
+    }
+
+    fn instant(&self) -> Instant {
+        self.state.lock().unwrap().1
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{SpotifyResult, UrlError};
+use snafu::ResultExt;
+use url::Url;
+
+const SPOTIFY_AUTH_URL: &str = "https://accounts.spotify.com/authorize";
+const SPOTIFY_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+
+/// The URLs of the Spotify Accounts service.
+///
+/// Defaults to the real Spotify endpoints. Tests, mocks and gateways can redirect traffic by
+/// passing different endpoints to [`SpotifyAuth`](crate::SpotifyAuth) and the token functions.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::Endpoints;
+/// // Point the library at a local mock of the accounts service.
+/// let endpoints = Endpoints::from_base_url("http://127.0.0.1:8080").unwrap();
+/// # assert_eq!(endpoints.token_url.as_str(), "http://127.0.0.1:8080/api/token");
+///
+/// // Or at a gateway serving it below a path.
+/// let endpoints = Endpoints::from_base_url("https://gw.example/spotify").unwrap();
+/// # assert_eq!(endpoints.authorize_url.as_str(), "https://gw.example/spotify/authorize");
+/// # assert_eq!(endpoints.token_url.as_str(), "https://gw.example/spotify/api/token");
+/// # assert_eq!(Endpoints::from_base_url("https://gw.example/spotify/").unwrap(), endpoints);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// The URL the user is sent to for granting permissions.
+    pub authorize_url: Url,
+    /// The URL codes and refresh tokens are exchanged at.
+    pub token_url: Url,
+}
+
+impl Endpoints {
+    /// Create endpoints with the paths of the Spotify Accounts service below the given base URL.
+    ///
+    /// The path of the base URL is kept, with or without a trailing slash.
+    pub fn from_base_url(base_url: &str) -> SpotifyResult<Self> {
+        let mut base_url = Url::parse(base_url).context(UrlError)?;
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+
+        Ok(Self {
+            authorize_url: base_url.join("authorize").context(UrlError)?,
+            token_url: base_url.join("api/token").context(UrlError)?,
+        })
+    }
+}
+
+impl Default for Endpoints {
+    /// The endpoints of accounts.spotify.com.
+    fn default() -> Self {
+        Self {
+            authorize_url: Url::parse(SPOTIFY_AUTH_URL).unwrap(),
+            token_url: Url::parse(SPOTIFY_TOKEN_URL).unwrap(),
+        }
+    }
+}
@@ -1,15 +1,68 @@
 //! Error Type for the API.
 
+#[cfg(feature = "serde-errors")]
+use serde::{ser::SerializeMap, Serialize, Serializer};
 use snafu::Snafu;
+#[cfg(feature = "miette")]
+use std::fmt::Display;
+use std::time::Duration;
 
 /// Generic Result for the Library
 pub type SpotifyResult<T, E = SpotifyError> = Result<T, E>;
 
+/// The category of a [`SpotifyError`], useful for routing alerts.
+///
+/// Infrastructure problems ([`ErrorCategory::Transport`]) usually resolve on their own, while
+/// [`ErrorCategory::Protocol`] errors point at configuration issues or revoked consent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The request never produced a response (DNS, TLS, connection failures), or a store such as
+    /// Redis was unreachable.
+    Transport,
+    /// The Spotify Accounts service answered with an OAuth error.
+    Protocol,
+    /// A response was received but could not be deserialized.
+    Deserialization,
+    /// The input given to the library was invalid (URLs, callbacks, missing codes).
+    Usage,
+}
+
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub(crate)")]
 pub enum SpotifyError {
+    #[snafu(display("Transport failure: {}", context))]
+    Transport {
+        // NOTE:
+        // 'source: Box<dyn error::Error + Send + Sync>'
+        // does not work with surf v2.x anymore.
+        context: String,
+    },
+
+    #[snafu(display(
+        "Spotify authorization failure: {}{}",
+        error,
+        description.as_ref().map(|x| format!(" ({})", x)).unwrap_or_default()
+    ))]
+    Protocol {
+        /// The HTTP status returned by the accounts service, if the error came from it.
+        status: Option<u16>,
+        /// The OAuth error code, e.g. ``invalid_client`` or ``access_denied``.
+        error: String,
+        /// The human readable ``error_description``, if Spotify sent one.
+        description: Option<String>,
+    },
+
+    #[snafu(display(
+        "Refresh token rejected, the user has to authorize the application again{}",
+        description.as_ref().map(|x| format!(": {}", x)).unwrap_or_default()
+    ))]
+    NeedsReauthorization {
+        /// The ``error_description`` sent by Spotify, e.g. ``Refresh token revoked``.
+        description: Option<String>,
+    },
+
     #[snafu(display("Unable to parse JSON: {}", source))]
-    SerdeError { source: serde_json::Error },
+    Deserialization { source: serde_json::Error },
 
     #[snafu(display("Unable to parse URL: {}", source))]
     UrlError { source: url::ParseError },
@@ -20,11 +73,295 @@ pub enum SpotifyError {
     #[snafu(display("Callback URL parsing failure: {}", context))]
     CallbackFailure { context: &'static str },
 
-    #[snafu(display("Surf http failure: {}", context))]
-    SurfError {
-        // NOTE:
-        // 'source: Box<dyn error::Error + Send + Sync>'
-        // does not work with surf v2.x anymore.
+    #[snafu(display("Unable to open the browser: {}", source))]
+    BrowserFailure { source: std::io::Error },
+
+    #[snafu(display("Callback server failure: {}", source))]
+    ServerFailure { source: std::io::Error },
+
+    #[snafu(display("Authorization flow failure: {}", context))]
+    FlowFailure { context: &'static str },
+
+    #[snafu(display("The operation was cancelled"))]
+    Cancelled,
+
+    #[snafu(display(
+        "No response within the deadline of {}",
+        humantime::format_duration(*after)
+    ))]
+    Timeout {
+        /// The deadline which passed.
+        after: Duration,
+    },
+
+    #[snafu(display("Store failure: {}", context))]
+    StoreFailure {
+        /// The error of the store, e.g. of the Redis connection.
         context: String,
     },
+
+    #[snafu(display("Requested scopes were not granted: {}", missing.join(" ")))]
+    MissingScopes {
+        /// The Spotify names of the requested scopes the user did not grant, e.g. ``streaming``.
+        missing: Vec<String>,
+    },
+
+    #[snafu(display("Unexpected response (HTTP {}): {}", status, snippet))]
+    UnexpectedResponse {
+        /// The HTTP status of the response.
+        status: u16,
+        /// The start of the body, e.g. of the HTML page of a proxy.
+        snippet: String,
+    },
+}
+
+impl SpotifyError {
+    /// The category of the error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{ErrorCategory, SpotifyCallback};
+    /// # use std::str::FromStr;
+    /// let err = SpotifyCallback::from_str("http://localhost:8888/callback").unwrap_err();
+    /// # assert_eq!(err.category(), ErrorCategory::Usage);
+    /// ```
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            SpotifyError::Transport { .. }
+            | SpotifyError::Timeout { .. }
+            | SpotifyError::StoreFailure { .. } => ErrorCategory::Transport,
+            SpotifyError::Protocol { .. }
+            | SpotifyError::NeedsReauthorization { .. }
+            | SpotifyError::MissingScopes { .. } => ErrorCategory::Protocol,
+            SpotifyError::Deserialization { .. } | SpotifyError::UnexpectedResponse { .. } => {
+                ErrorCategory::Deserialization
+            }
+            SpotifyError::UrlError { .. }
+            | SpotifyError::TokenFailure { .. }
+            | SpotifyError::CallbackFailure { .. }
+            | SpotifyError::BrowserFailure { .. }
+            | SpotifyError::ServerFailure { .. }
+            | SpotifyError::FlowFailure { .. }
+            | SpotifyError::Cancelled => ErrorCategory::Usage,
+        }
+    }
+
+    /// A stable, machine readable name of the error variant.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            SpotifyError::Transport { .. } => "transport",
+            SpotifyError::Protocol { .. } => "protocol",
+            SpotifyError::NeedsReauthorization { .. } => "needs_reauthorization",
+            SpotifyError::Deserialization { .. } => "deserialization",
+            SpotifyError::UrlError { .. } => "url",
+            SpotifyError::TokenFailure { .. } => "token",
+            SpotifyError::CallbackFailure { .. } => "callback",
+            SpotifyError::BrowserFailure { .. } => "browser",
+            SpotifyError::ServerFailure { .. } => "server",
+            SpotifyError::FlowFailure { .. } => "flow",
+            SpotifyError::Cancelled => "cancelled",
+            SpotifyError::Timeout { .. } => "timeout",
+            SpotifyError::StoreFailure { .. } => "store",
+            SpotifyError::MissingScopes { .. } => "missing_scopes",
+            SpotifyError::UnexpectedResponse { .. } => "unexpected_response",
+        }
+    }
+}
+
+/// Structured representation of the error for JSON logging.
+///
+/// The error is serialized as a map with a ``kind``, a ``category`` and a ``message`` plus the
+/// OAuth fields of protocol errors. Credentials contained in messages are masked.
+///
+/// Only available with the ``serde-errors`` feature.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::SpotifyCallback;
+/// # use std::str::FromStr;
+/// let err = SpotifyCallback::from_str("http://localhost:8888/callback").unwrap_err();
+/// let json = serde_json::to_value(&err).unwrap();
+/// # assert_eq!(json["kind"], "callback");
+/// ```
+#[cfg(feature = "serde-errors")]
+impl Serialize for SpotifyError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind_name())?;
+        map.serialize_entry("category", &format!("{:?}", self.category()).to_lowercase())?;
+        map.serialize_entry("message", &crate::util::redact(&self.to_string()))?;
+
+        if let SpotifyError::Protocol {
+            status,
+            error,
+            description,
+        } = self
+        {
+            map.serialize_entry("status", status)?;
+            map.serialize_entry("error", error)?;
+            map.serialize_entry(
+                "description",
+                &description.as_deref().map(crate::util::redact),
+            )?;
+        }
+
+        map.end()
+    }
+}
+
+/// Diagnostic codes and help texts for the most common misconfigurations.
+///
+/// Only available with the ``miette`` feature.
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for SpotifyError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            SpotifyError::Protocol { error, .. } => error,
+            _ => self.kind_name(),
+        };
+
+        Some(Box::new(format!("spotify_oauth::{}", code)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match self {
+            SpotifyError::UrlError { .. } => {
+                "Redirect URIs must be absolute URLs, e.g. http://localhost:8888/callback"
+            }
+            SpotifyError::CallbackFailure { .. } => {
+                "Paste the complete URL the browser was redirected to, including the query string"
+            }
+            SpotifyError::BrowserFailure { .. } => {
+                "Open the authorization URL manually in a browser"
+            }
+            SpotifyError::ServerFailure { .. } => {
+                "Check that no other program listens on the port of the redirect URI"
+            }
+            SpotifyError::Transport { .. } | SpotifyError::Timeout { .. } => {
+                "Check the network connection to accounts.spotify.com"
+            }
+            SpotifyError::UnexpectedResponse { .. } => {
+                "The accounts service is in maintenance or a proxy intercepted the request, retry later"
+            }
+            SpotifyError::NeedsReauthorization { .. } => {
+                "Delete the cached token and send the user through the authorization flow again"
+            }
+            SpotifyError::MissingScopes { .. } => {
+                "The user deselected scopes in the consent dialog, ask for them again"
+            }
+            SpotifyError::Protocol {
+                error, description, ..
+            } => authorization_help(error, description.as_deref().unwrap_or_default())?,
+            _ => return None,
+        };
+
+        Some(Box::new(help))
+    }
+}
+
+/// Map an OAuth error code and description to an actionable hint.
+#[cfg(feature = "miette")]
+fn authorization_help(error: &str, description: &str) -> Option<&'static str> {
+    let description = description.to_lowercase();
+
+    match error {
+        "invalid_client" if description.contains("secret") => {
+            Some("INVALID_CLIENT: check SPOTIFY_CLIENT_SECRET")
+        }
+        "invalid_client" => Some("INVALID_CLIENT: check SPOTIFY_CLIENT_ID"),
+        "invalid_grant" if description.contains("redirect") => {
+            Some("redirect_uri mismatch: must exactly match the dashboard entry")
+        }
+        "invalid_grant" if description.contains("refresh") => {
+            Some("The refresh token is no longer valid, the user has to authorize the app again")
+        }
+        "invalid_grant" => Some(
+            "Authorization codes are single use and expire quickly, restart the authorization flow",
+        ),
+        "access_denied" => Some("The user declined the authorization request"),
+        "unsupported_response_type" => Some("The response type of SpotifyAuth must be \"code\""),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, any(feature = "miette", feature = "serde-errors")))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "miette")]
+    use miette::Diagnostic;
+
+    fn failure(error: &str, description: &str) -> SpotifyError {
+        SpotifyError::Protocol {
+            status: Some(400),
+            error: error.to_string(),
+            description: Some(description.to_string()),
+        }
+    }
+
+    #[cfg(feature = "miette")]
+    #[test]
+    fn test_invalid_client_help() {
+        let err = failure("invalid_client", "Invalid client secret");
+
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "spotify_oauth::invalid_client"
+        );
+        assert_eq!(
+            err.help().unwrap().to_string(),
+            "INVALID_CLIENT: check SPOTIFY_CLIENT_SECRET"
+        );
+    }
+
+    #[cfg(feature = "miette")]
+    #[test]
+    fn test_redirect_mismatch_help() {
+        let err = failure("invalid_grant", "Invalid redirect URI");
+
+        assert_eq!(
+            err.help().unwrap().to_string(),
+            "redirect_uri mismatch: must exactly match the dashboard entry"
+        );
+    }
+
+    #[cfg(feature = "serde-errors")]
+    #[test]
+    fn test_serialize_strips_secrets() {
+        let err = SpotifyError::Transport {
+            context: "POST failed: code=AQD0yXvF&grant_type=authorization_code".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "transport",
+                "category": "transport",
+                "message": "Transport failure: POST failed: code=***&grant_type=authorization_code"
+            })
+        );
+    }
+
+    #[cfg(feature = "serde-errors")]
+    #[test]
+    fn test_serialize_protocol_error() {
+        let json = serde_json::to_value(failure("invalid_client", "Invalid client")).unwrap();
+
+        assert_eq!(json["kind"], "protocol");
+        assert_eq!(json["status"], 400);
+        assert_eq!(json["error"], "invalid_client");
+    }
+
+    #[cfg(feature = "serde-errors")]
+    #[test]
+    fn test_serialize_redacts_protocol_description() {
+        let err = failure("invalid_grant", "Invalid authorization code: code=AQD0yXvF");
+        let json = serde_json::to_value(err).unwrap();
+
+        assert_eq!(json["description"], "Invalid authorization code: code=***");
+    }
 }
@@ -0,0 +1,384 @@
+//! Export of obtained tokens into the formats of other tools, and import from them.
+
+use crate::{
+    error::*, token::deserialize_scope_field, write_private_file, CachedToken, SpotifyScope,
+    SpotifyToken, TokenType,
+};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::{fs, io, path::Path};
+
+/// The ``AUTHENTICATION_SPOTIFY_TOKEN`` value of librespot's ``AuthenticationType``.
+const LIBRESPOT_SPOTIFY_TOKEN: i32 = 3;
+
+/// Credentials in the format of librespot's ``credentials.json`` cache file.
+///
+/// librespot logs in with an access token as ``Credentials::with_access_token`` does: no username,
+/// the ``AUTHENTICATION_SPOTIFY_TOKEN`` type and the token as base64 encoded auth data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LibrespotCredentials {
+    /// The Spotify username, librespot resolves it during the login.
+    pub username: Option<String>,
+    /// The numeric librespot ``AuthenticationType``.
+    pub auth_type: i32,
+    /// The base64 encoded access token.
+    pub auth_data: String,
+}
+
+impl LibrespotCredentials {
+    /// The credentials as JSON, ready to be written to librespot's ``credentials.json``.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("credentials always serialize")
+    }
+}
+
+impl SpotifyToken {
+    /// Convert the token into credentials for librespot's OAuth login.
+    ///
+    /// librespot needs the ``streaming`` scope for playback, tokens without it or which have
+    /// already expired are rejected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::SpotifyToken;
+    /// let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "streaming", "expires_in": 3600, "expires_at": 99999999999, "refresh_token": "r"}"#).unwrap();
+    /// let credentials = token.librespot_credentials().unwrap();
+    /// # assert_eq!(credentials.auth_data, "TmdDWFJL");
+    /// ```
+    pub fn librespot_credentials(&self) -> SpotifyResult<LibrespotCredentials> {
+        if !self.scope.contains(&SpotifyScope::Streaming) {
+            return Err(SpotifyError::TokenFailure {
+                context: "librespot requires a token with the streaming scope.",
+            });
+        }
+
+        if self.is_expired() {
+            return Err(SpotifyError::TokenFailure {
+                context: "The access token has expired.",
+            });
+        }
+
+        Ok(LibrespotCredentials {
+            username: None,
+            auth_type: LIBRESPOT_SPOTIFY_TOKEN,
+            auth_data: base64::encode(&self.access_token),
+        })
+    }
+}
+
+/// A token in rspotify's ``.spotify_token_cache.json`` layout.
+///
+/// rspotify 0.11 and later write ``expires_at`` as RFC 3339 date, earlier releases as unix
+/// timestamp. The scopes are space separated, lists are accepted as well.
+#[derive(Deserialize)]
+struct RspotifyToken {
+    access_token: String,
+    #[serde(default, deserialize_with = "crate::rfc3339::deserialize")]
+    expires_at: Option<i64>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_scope_field")]
+    scope: Vec<SpotifyScope>,
+}
+
+impl SpotifyToken {
+    /// Read the token cache file of rspotify, by default ``.spotify_token_cache.json``, so users
+    /// switching to this crate keep their refresh token and need not consent again.
+    ///
+    /// The scopes and the expiry are kept, the access token is usable until it expires and is then
+    /// refreshed as usual.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::SpotifyToken;
+    /// let token = SpotifyToken::from_rspotify_cache(".spotify_token_cache.json").unwrap();
+    /// ```
+    pub fn from_rspotify_cache(path: impl AsRef<Path>) -> SpotifyResult<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| SpotifyError::StoreFailure {
+            context: format!("unable to read {}: {}", path.display(), err),
+        })?;
+
+        Self::from_rspotify_json(&text)
+    }
+
+    /// Parse a token in rspotify's cache layout, see [`SpotifyToken::from_rspotify_cache`].
+    fn from_rspotify_json(json: &str) -> SpotifyResult<Self> {
+        let token: RspotifyToken = serde_json::from_str(json).context(Deserialization)?;
+
+        Ok(CachedToken {
+            access_token: token.access_token,
+            token_type: TokenType::Bearer,
+            scope: token.scope,
+            expires_at: token.expires_at,
+            refresh_token: token.refresh_token,
+            extra: Default::default(),
+        }
+        .into())
+    }
+}
+
+/// Hands the token to rspotify, e.g. ``AuthCodeSpotify::from_token(token.into())``.
+///
+/// The token type is dropped, rspotify only handles bearer tokens. Only available with the
+/// ``rspotify`` feature.
+#[cfg(feature = "rspotify")]
+impl From<SpotifyToken> for rspotify_model::Token {
+    fn from(token: SpotifyToken) -> Self {
+        Self {
+            expires_in: chrono::Duration::seconds(token.expires_in.into()),
+            expires_at: token.expires_at_datetime(),
+            scopes: token.scope_set(),
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+        }
+    }
+}
+
+/// Takes over a token obtained with rspotify, e.g. from ``spotify.token``.
+///
+/// Scopes unknown to this library are kept as [`SpotifyScope::Other`]. Fails if the lifetime of
+/// the token is negative or does not fit the ``expires_in`` seconds. Only available with the
+/// ``rspotify`` feature.
+#[cfg(feature = "rspotify")]
+impl TryFrom<rspotify_model::Token> for SpotifyToken {
+    type Error = SpotifyError;
+
+    fn try_from(token: rspotify_model::Token) -> SpotifyResult<Self> {
+        let expires_in = u32::try_from(token.expires_in.num_seconds()).map_err(|_| {
+            SpotifyError::TokenFailure {
+                context: "The lifetime of the rspotify token is out of range.",
+            }
+        })?;
+
+        let mut scope: Vec<_> = token
+            .scopes
+            .iter()
+            .filter_map(|x| x.parse::<SpotifyScope>().ok())
+            .collect();
+        scope.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        Ok(SpotifyToken {
+            access_token: token.access_token,
+            token_type: TokenType::Bearer,
+            scope,
+            expires_in,
+            expires_at: token.expires_at.map(|x| x.timestamp()),
+            refresh_token: token.refresh_token,
+            extra: Default::default(),
+        })
+    }
+}
+
+impl SpotifyToken {
+    /// The ``KEY=value`` pairs exported by [`SpotifyToken::to_env`] and [`SpotifyToken::to_shell_exports`].
+    fn env_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![("SPOTIFY_ACCESS_TOKEN", self.access_token.clone())];
+
+        if let Some(ref x) = self.refresh_token {
+            pairs.push(("SPOTIFY_REFRESH_TOKEN", x.clone()));
+        }
+
+        if let Some(x) = self.expires_at {
+            pairs.push(("SPOTIFY_TOKEN_EXPIRES_AT", x.to_string()));
+        }
+
+        pairs
+    }
+
+    /// The token as ``.env`` style ``KEY=value`` lines, e.g. for docker-compose.
+    ///
+    /// ``SPOTIFY_ACCESS_TOKEN`` and, if known, ``SPOTIFY_REFRESH_TOKEN`` and ``SPOTIFY_TOKEN_EXPIRES_AT`` are exported.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::SpotifyToken;
+    /// let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "NgAagA"}"#).unwrap();
+    /// assert_eq!(token.to_env(), "SPOTIFY_ACCESS_TOKEN=NgCXRK\nSPOTIFY_REFRESH_TOKEN=NgAagA\n");
+    /// ```
+    pub fn to_env(&self) -> String {
+        self.env_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect()
+    }
+
+    /// The token as shell ``export`` statements, to be used with ``eval``.
+    pub fn to_shell_exports(&self) -> String {
+        self.env_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("export {}='{}'\n", key, value.replace('\'', "'\\''")))
+            .collect()
+    }
+
+    /// Write the token into a ``.env`` file.
+    ///
+    /// Existing lines of the exported keys are replaced, all other lines are kept.
+    /// The file is created if it does not exist, and written with [`write_private_file`].
+    pub fn write_env_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let existing = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let pairs = self.env_pairs();
+        let mut out: String = existing
+            .lines()
+            .filter(|line| {
+                let key = line.trim_start().trim_start_matches("export ");
+                !pairs
+                    .iter()
+                    .any(|(x, _)| key.strip_prefix(x).is_some_and(|x| x.starts_with('=')))
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        out.push_str(&self.to_env());
+
+        write_private_file(path, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_librespot_credentials() {
+        let token = SpotifyToken::fixture()
+            .access_token("NgCXRK")
+            .scope(vec![SpotifyScope::Streaming])
+            .build();
+
+        assert_eq!(
+            token.librespot_credentials().unwrap().to_json(),
+            r#"{"username":null,"auth_type":3,"auth_data":"TmdDWFJL"}"#
+        );
+    }
+
+    #[test]
+    fn test_shell_exports_quote_values() {
+        let token = SpotifyToken::fixture()
+            .access_token("it's")
+            .refresh_token("NgAagA")
+            .expires_at(1_600_003_600)
+            .build();
+
+        assert_eq!(
+            token.to_shell_exports(),
+            "export SPOTIFY_ACCESS_TOKEN='it'\\''s'\nexport SPOTIFY_REFRESH_TOKEN='NgAagA'\nexport SPOTIFY_TOKEN_EXPIRES_AT='1600003600'\n"
+        );
+    }
+
+    #[test]
+    fn test_write_env_file_replaces_keys() {
+        let path = std::env::temp_dir().join(format!("spotify-oauth-{}.env", std::process::id()));
+        fs::write(
+            &path,
+            "APP=1\nSPOTIFY_ACCESS_TOKEN=old\nSPOTIFY_ACCESS_TOKEN_URL=x\n",
+        )
+        .unwrap();
+
+        let token = SpotifyToken::fixture()
+            .access_token("NgCXRK")
+            .refresh_token("NgAagA")
+            .expires_at(1_600_003_600)
+            .build();
+        token.write_env_file(&path).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "APP=1\nSPOTIFY_ACCESS_TOKEN_URL=x\nSPOTIFY_ACCESS_TOKEN=NgCXRK\nSPOTIFY_REFRESH_TOKEN=NgAagA\nSPOTIFY_TOKEN_EXPIRES_AT=1600003600\n"
+        );
+    }
+
+    #[test]
+    fn test_from_rspotify_json() {
+        // rspotify 0.11 and later.
+        let token = SpotifyToken::from_rspotify_json(
+            r#"{"access_token": "NgCXRK", "expires_in": 3600, "expires_at": "2020-09-13T13:26:40.123456Z", "refresh_token": "NgAagA", "scope": "user-read-email streaming"}"#,
+        )
+        .unwrap();
+        assert_eq!(token.access_token, "NgCXRK");
+        assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+        assert_eq!(
+            token.scope,
+            vec![SpotifyScope::UserReadEmail, SpotifyScope::Streaming]
+        );
+
+        // rspotify 0.10.
+        let token = SpotifyToken::from_rspotify_json(
+            r#"{"access_token": "NgCXRK", "token_type": "Bearer", "expires_in": 3600, "expires_at": 1600003600, "refresh_token": "NgAagA", "scope": "streaming"}"#,
+        )
+        .unwrap();
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+        assert_eq!(token.scope, vec![SpotifyScope::Streaming]);
+
+        assert!(SpotifyToken::from_rspotify_json(
+            r#"{"access_token": "NgCXRK", "expires_at": "tomorrow"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_librespot_credentials_require_streaming() {
+        let token = SpotifyToken::fixture()
+            .scope(vec![SpotifyScope::UserReadEmail])
+            .build();
+
+        assert!(token.librespot_credentials().is_err());
+    }
+
+    #[cfg(feature = "rspotify")]
+    #[test]
+    fn test_rspotify_token_conversions() {
+        let token = SpotifyToken::fixture()
+            .access_token("NgCXRK")
+            .refresh_token("NgAagA")
+            .expires_at(1_600_003_600)
+            .scope(vec![
+                SpotifyScope::Streaming,
+                SpotifyScope::UserReadEmail,
+                SpotifyScope::Other("app-remote-future".into()),
+            ])
+            .build();
+
+        let converted = rspotify_model::Token::from(token.clone());
+        assert_eq!(converted.access_token, "NgCXRK");
+        assert_eq!(converted.refresh_token.as_deref(), Some("NgAagA"));
+        assert_eq!(converted.expires_in, chrono::Duration::seconds(3600));
+        assert_eq!(converted.expires_at.unwrap().timestamp(), 1_600_003_600);
+        assert!(converted.scopes.contains("app-remote-future"));
+
+        let back = SpotifyToken::try_from(converted.clone()).unwrap();
+        assert_eq!(back.access_token, token.access_token);
+        assert_eq!(back.refresh_token, token.refresh_token);
+        assert_eq!(
+            (back.expires_in, back.expires_at),
+            (3600, Some(1_600_003_600))
+        );
+        assert!(crate::scopes_eq(&back.scope, &token.scope));
+
+        let expired = rspotify_model::Token {
+            expires_in: chrono::Duration::seconds(-1),
+            ..converted
+        };
+        assert!(matches!(
+            SpotifyToken::try_from(expired),
+            Err(SpotifyError::TokenFailure { .. })
+        ));
+    }
+}
@@ -0,0 +1,963 @@
+//! HTTP abstraction used to talk to the Spotify Accounts service.
+//!
+//! The library only ever sends form encoded ``POST`` requests to the token endpoint, so a backend
+//! has to implement a single method of the [`HttpClient`] trait. ``SurfClient`` is used by default,
+//! disable the ``surf`` feature to build without it.
+
+use crate::{token::parse_scopes, util::redact, Endpoints, SpotifyToken, TokenType};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    borrow::Cow,
+    error, fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A single HTTP header of a [`TokenRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    name: Cow<'static, str>,
+    value: String,
+}
+
+impl Header {
+    /// Create a new header.
+    pub fn new(name: impl Into<Cow<'static, str>>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    /// The name of the header.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the header.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// The fields of a form encoded request body, in order.
+pub type FormData = Vec<(String, String)>;
+
+/// A form encoded ``POST`` request to the Spotify token endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenRequest {
+    /// The URL of the token endpoint.
+    pub url: String,
+    /// The headers to send, including the ``Authorization`` header.
+    pub headers: Vec<Header>,
+    form: FormData,
+    body: String,
+}
+
+impl TokenRequest {
+    /// Create a request to the token endpoint authenticated with the given client credentials.
+    pub fn new(
+        endpoints: &Endpoints,
+        client_id: &str,
+        client_secret: &str,
+        form: FormData,
+    ) -> Self {
+        // Form authorisation header.
+        let auth_value = base64::encode(format!("{}:{}", client_id, client_secret));
+
+        let headers = vec![Header::new(
+            "Authorization",
+            format!("Basic {}", auth_value),
+        )];
+
+        Self::with_form(endpoints.token_url.to_string(), headers, form)
+    }
+
+    /// Create a request with the given headers, encoding the form fields into the body once.
+    pub(crate) fn with_form(url: String, headers: Vec<Header>, form: FormData) -> Self {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&form)
+            .finish();
+
+        Self {
+            url,
+            headers,
+            form,
+            body,
+        }
+    }
+
+    /// The value of the first header with the given name (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|x| x.name().eq_ignore_ascii_case(name))
+            .map(Header::value)
+    }
+
+    /// The ``Content-Type`` of the body returned by [`TokenRequest::body`].
+    pub const CONTENT_TYPE: &'static str = "application/x-www-form-urlencoded";
+
+    /// The form fields of the body, in order.
+    pub fn form(&self) -> &[(String, String)] {
+        &self.form
+    }
+
+    /// The value of the first form field with the given name.
+    pub fn form_field(&self, name: &str) -> Option<&str> {
+        self.form.iter().find(|x| x.0 == name).map(|x| x.1.as_str())
+    }
+
+    /// The form fields encoded as ``application/x-www-form-urlencoded`` body, ready to be sent.
+    ///
+    /// The body is encoded once when the request is created.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{Endpoints, TokenRequest};
+    /// let request = TokenRequest::new(&Endpoints::default(), "id", "secret", vec![("grant_type".into(), "refresh_token".into())]);
+    /// # assert_eq!(request.body(), "grant_type=refresh_token");
+    /// ```
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+/// Build an ``http-types`` request, e.g. to send it with a tide or async-h1 based connector.
+///
+/// Only available with the ``http-types`` feature.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{Endpoints, TokenRequest};
+/// use std::convert::TryFrom;
+///
+/// let request = TokenRequest::new(&Endpoints::default(), "id", "secret", vec![("grant_type".into(), "refresh_token".into())]);
+/// let request = http_types::Request::try_from(request).unwrap();
+/// # assert_eq!(request.method(), http_types::Method::Post);
+/// ```
+#[cfg(feature = "http-types")]
+impl std::convert::TryFrom<TokenRequest> for http_types::Request {
+    type Error = crate::SpotifyError;
+
+    fn try_from(request: TokenRequest) -> Result<Self, Self::Error> {
+        use snafu::ResultExt;
+
+        let url = url::Url::parse(&request.url).context(crate::UrlError)?;
+        let mut out = http_types::Request::new(http_types::Method::Post, url);
+
+        for header in &request.headers {
+            out.append_header(header.name(), header.value());
+        }
+
+        out.set_body(request.body());
+        out.set_content_type(http_types::mime::FORM);
+
+        Ok(out)
+    }
+}
+
+/// The raw response of the Spotify token endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The response body.
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Whether the status code is in the ``2xx`` range.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Read the status and body of an ``http-types`` response.
+    ///
+    /// Only available with the ``http-types`` feature.
+    #[cfg(feature = "http-types")]
+    pub async fn from_http_types(
+        mut response: http_types::Response,
+    ) -> Result<Self, HttpClientError> {
+        let body = response
+            .body_string()
+            .await
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+
+        Ok(Self {
+            status: response.status().into(),
+            body,
+        })
+    }
+}
+
+/// The body of a successful response of the Spotify token endpoint.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::TokenResponse;
+/// let response: TokenResponse = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "streaming", "expires_in": 3600, "id_token": "x"}"#).unwrap();
+/// # assert_eq!(response.extra["id_token"], "x");
+/// let token = response.into_token(Some("NgAagA"));
+/// # assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenResponse {
+    /// The access token.
+    pub access_token: String,
+    /// How the access token may be used.
+    pub token_type: TokenType,
+    /// The granted scopes, space separated.
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// The lifetime of the access token in seconds, also accepted as a string or a float.
+    #[serde(deserialize_with = "crate::token::deserialize_expires_in")]
+    pub expires_in: u32,
+    /// The refresh token, refresh responses may omit it.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Fields not known to this library.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TokenResponse {
+    /// Convert the response into a token, using ``refresh_token`` if the response contains none.
+    ///
+    /// Scopes unknown to this library are kept as [`crate::SpotifyScope::Other`], fields unknown to
+    /// it are kept in [`SpotifyToken::extra`]. ``expires_at`` is not set, see
+    /// [`SpotifyToken::update_expires_at`].
+    pub fn into_token(self, refresh_token: Option<&str>) -> SpotifyToken {
+        SpotifyToken {
+            access_token: self.access_token,
+            token_type: self.token_type,
+            scope: parse_scopes(self.scope.as_deref().unwrap_or_default()),
+            expires_in: self.expires_in,
+            expires_at: None,
+            refresh_token: self
+                .refresh_token
+                .or_else(|| refresh_token.map(str::to_string)),
+            extra: self.extra,
+        }
+    }
+}
+
+/// A failure of the HTTP backend, e.g. a DNS or connection error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpClientError {
+    message: String,
+}
+
+impl HttpClientError {
+    /// Create a new error with the given message.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    /// The error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for HttpClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for HttpClientError {}
+
+/// Serialized as a map with the ``message``, credentials contained in it are masked.
+///
+/// Only available with the ``serde-errors`` feature.
+#[cfg(feature = "serde-errors")]
+impl serde::Serialize for HttpClientError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("message", &redact(&self.message))?;
+        map.end()
+    }
+}
+
+/// An HTTP backend able to send requests to the Spotify token endpoint.
+///
+/// Non-success status codes must be returned as a [`HttpResponse`], an [`HttpClientError`] is only
+/// expected when no response was received at all.
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    /// Send the request and return the response status and body.
+    ///
+    /// The request is borrowed mutably, so decorators like [`InterceptedClient`] can adjust it
+    /// and still inspect it after the response without copying it.
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError>;
+
+    /// Send a ``GET`` request to the Web API, e.g. for [`SpotifyToken::validate`](crate::SpotifyToken::validate).
+    ///
+    /// Backends which only talk to the token endpoint keep the default, which fails.
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        let _ = headers;
+        Err(HttpClientError::new(format!(
+            "GET {} is not supported by this HTTP client",
+            url
+        )))
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient + ?Sized> HttpClient for &C {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        (**self).fetch_token(request).await
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        (**self).get(url, headers).await
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient + ?Sized> HttpClient for Box<C> {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        (**self).fetch_token(request).await
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        (**self).get(url, headers).await
+    }
+}
+
+/// Hooks run around every request of an [`InterceptedClient`].
+///
+/// Both methods do nothing by default, implement the ones needed.
+pub trait Interceptor: Send + Sync {
+    /// Called before the request is sent, e.g. to add headers required by a proxy.
+    ///
+    /// Returning a result short-circuits the request: the ``before_request`` hooks of the
+    /// remaining interceptors are skipped and the wrapped client is not called. The result is
+    /// passed to the ``after_response`` hooks of all interceptors instead.
+    fn before_request(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Option<Result<HttpResponse, HttpClientError>> {
+        let _ = request;
+        None
+    }
+
+    /// Called with the request as sent, its outcome and the time it took, the result may be
+    /// replaced.
+    fn after_response(
+        &self,
+        request: &TokenRequest,
+        result: &mut Result<HttpResponse, HttpClientError>,
+        elapsed: Duration,
+    ) {
+        let _ = (request, result, elapsed);
+    }
+}
+
+/// An [`HttpClient`] decorator running [`Interceptor`]s around the requests of another client.
+///
+/// ``before_request`` hooks run in the order the interceptors were added, ``after_response`` hooks
+/// in reverse order.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{Header, InterceptedClient, Interceptor, SurfClient, TokenRequest};
+/// struct ProxyAuth;
+///
+/// impl Interceptor for ProxyAuth {
+///     fn before_request(&self, request: &mut TokenRequest) -> Option<Result<spotify_oauth::HttpResponse, spotify_oauth::HttpClientError>> {
+///         request.headers.push(Header::new("Proxy-Authorization", "Basic cHJveHk6cHJveHk="));
+///         None
+///     }
+/// }
+///
+/// let http = InterceptedClient::new(SurfClient::new()).with(ProxyAuth);
+/// ```
+pub struct InterceptedClient<C> {
+    inner: C,
+    interceptors: Vec<Box<dyn Interceptor>>,
+}
+
+impl<C> InterceptedClient<C> {
+    /// Wrap a client without any interceptors.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Add an interceptor.
+    pub fn with(mut self, interceptor: impl Interceptor + 'static) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// The wrapped client.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for InterceptedClient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterceptedClient")
+            .field("inner", &self.inner)
+            .field("interceptors", &self.interceptors.len())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for InterceptedClient<C> {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let started = Instant::now();
+        let short_circuit = self
+            .interceptors
+            .iter()
+            .find_map(|x| x.before_request(request));
+
+        let mut result = match short_circuit {
+            Some(x) => x,
+            None => self.inner.fetch_token(request).await,
+        };
+
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after_response(request, &mut result, started.elapsed());
+        }
+
+        result
+    }
+
+    /// Web API requests are passed on without running the interceptors.
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        self.inner.get(url, headers).await
+    }
+}
+
+/// An [`HttpClient`] decorator passing a description of every request and response to a sink.
+///
+/// The ``Authorization`` header, authorization codes and tokens are masked, so the output can be
+/// shared when debugging e.g. a ``redirect_uri`` mismatch.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{LoggingClient, SurfClient};
+/// let http = LoggingClient::new(SurfClient::new(), |line| eprintln!("{}", line));
+/// ```
+pub struct LoggingClient<C> {
+    inner: C,
+    sink: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl<C> LoggingClient<C> {
+    /// Wrap a client, passing every logged line to ``sink``.
+    pub fn new(inner: C, sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            inner,
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// The wrapped client.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for LoggingClient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoggingClient")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for LoggingClient<C> {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let headers = request
+            .headers
+            .iter()
+            .map(|x| format!("{}: {}", x.name(), x.value()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let body = request.body();
+        (self.sink)(&redact(&format!(
+            "POST {} ({}) {}",
+            request.url, headers, body
+        )));
+
+        let result = self.inner.fetch_token(request).await;
+        self.log_result(&result);
+
+        result
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        (self.sink)(&redact(&format!("GET {}", url)));
+
+        let result = self.inner.get(url, headers).await;
+        self.log_result(&result);
+
+        result
+    }
+}
+
+impl<C> LoggingClient<C> {
+    fn log_result(&self, result: &Result<HttpResponse, HttpClientError>) {
+        match result {
+            Ok(x) => (self.sink)(&redact(&format!("{} {}", x.status, x.body))),
+            Err(x) => (self.sink)(&redact(&format!("transport error: {}", x))),
+        }
+    }
+}
+
+/// The default [`HttpClient`] backed by surf.
+///
+/// Only available with the ``surf`` feature, which is enabled by default.
+#[cfg(feature = "surf")]
+#[derive(Debug, Clone, Default)]
+pub struct SurfClient {
+    client: surf::Client,
+}
+
+#[cfg(feature = "surf")]
+impl SurfClient {
+    /// Create a new client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "surf")]
+impl From<surf::Client> for SurfClient {
+    fn from(client: surf::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "surf")]
+#[async_trait]
+impl HttpClient for SurfClient {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self.client.post(&request.url);
+        for header in &request.headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let mut body = surf::Body::from_string(request.body().to_owned());
+        body.set_mime(TokenRequest::CONTENT_TYPE);
+        let mut response = builder
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| HttpClientError::new(format!("{err:?}")))?;
+
+        // Read the response body.
+        let body = response
+            .body_string()
+            .await
+            .map_err(|err| HttpClientError::new(format!("{err:?}")))?;
+
+        Ok(HttpResponse {
+            status: response.status().into(),
+            body,
+        })
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self.client.get(url);
+        for header in headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let mut response = builder
+            .send()
+            .await
+            .map_err(|err| HttpClientError::new(format!("{err:?}")))?;
+        let body = response
+            .body_string()
+            .await
+            .map_err(|err| HttpClientError::new(format!("{err:?}")))?;
+
+        Ok(HttpResponse {
+            status: response.status().into(),
+            body,
+        })
+    }
+}
+
+/// An [`HttpClient`] backed by reqwest, for applications running on tokio.
+///
+/// No TLS backend of reqwest is enabled by this crate, enable one in your own manifest, e.g.
+/// ``reqwest = { version = "0.13", features = ["rustls"] }``.
+///
+/// Only available with the ``reqwest`` feature.
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestClient {
+    /// Create a new client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Client> for ReqwestClient {
+    fn from(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait]
+impl HttpClient for ReqwestClient {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self
+            .client
+            .post(&request.url)
+            .header("Content-Type", TokenRequest::CONTENT_TYPE);
+        for header in &request.headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let response = builder
+            .body(request.body().to_owned())
+            .send()
+            .await
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+        let status = response.status().as_u16();
+
+        // Read the response body.
+        let body = response
+            .text()
+            .await
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+
+        Ok(HttpResponse { status, body })
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self.client.get(url);
+        for header in headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let response = builder
+            .send()
+            .await
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+
+        Ok(HttpResponse { status, body })
+    }
+}
+
+/// An [`HttpClient`] backed by the blocking ureq client.
+///
+/// Requests block the thread polling the future, use it from blocking contexts such as CLI tools
+/// or with ``block_on``. No TLS backend of ureq is enabled by this crate, enable one in your own
+/// manifest, e.g. ``ureq = { version = "3", features = ["rustls"] }``. Without one, requests to
+/// ``https`` URLs such as the Spotify Accounts service fail with an error saying so. The default
+/// agent uses rustls, pass an agent configured for native-tls with ``From<ureq::Agent>``.
+///
+/// Only available with the ``ureq`` feature.
+#[cfg(feature = "ureq")]
+#[derive(Debug, Clone)]
+pub struct UreqClient {
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "ureq")]
+impl UreqClient {
+    /// Create a new client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Default for UreqClient {
+    fn default() -> Self {
+        // Error statuses carry the OAuth error body, they must not be turned into errors.
+        let config = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .build();
+
+        Self {
+            agent: ureq::Agent::new_with_config(config),
+        }
+    }
+}
+
+/// The agent must be configured with ``http_status_as_error(false)``.
+#[cfg(feature = "ureq")]
+impl From<ureq::Agent> for UreqClient {
+    fn from(agent: ureq::Agent) -> Self {
+        Self { agent }
+    }
+}
+
+#[cfg(feature = "ureq")]
+#[async_trait]
+impl HttpClient for UreqClient {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self
+            .agent
+            .post(&request.url)
+            .content_type(TokenRequest::CONTENT_TYPE);
+        for header in &request.headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let mut response = builder.send(request.body()).map_err(ureq_error)?;
+
+        // Read the response body.
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+
+        Ok(HttpResponse {
+            status: response.status().as_u16(),
+            body,
+        })
+    }
+
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        let mut builder = self.agent.get(url);
+        for header in headers {
+            builder = builder.header(header.name(), header.value());
+        }
+
+        let mut response = builder.call().map_err(ureq_error)?;
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|err| HttpClientError::new(err.to_string()))?;
+
+        Ok(HttpResponse {
+            status: response.status().as_u16(),
+            body,
+        })
+    }
+}
+
+/// The error of a ureq request, naming the missing TLS backend for ``https`` URLs.
+#[cfg(feature = "ureq")]
+fn ureq_error(err: ureq::Error) -> HttpClientError {
+    match err {
+        ureq::Error::TlsRequired => {
+            HttpClientError::new("ureq has no TLS backend, enable its rustls or native-tls feature")
+        }
+        err => HttpClientError::new(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHttpClient, MockResponse};
+    use std::sync::Mutex;
+
+    struct Cached;
+
+    impl Interceptor for Cached {
+        fn before_request(
+            &self,
+            request: &mut TokenRequest,
+        ) -> Option<Result<HttpResponse, HttpClientError>> {
+            request.headers.push(Header::new("X-Cached", "1"));
+            Some(Ok(HttpResponse {
+                status: 200,
+                body: "{}".to_string(),
+            }))
+        }
+    }
+
+    struct Timing(Arc<Mutex<Vec<String>>>);
+
+    impl Interceptor for Timing {
+        fn after_response(
+            &self,
+            request: &TokenRequest,
+            result: &mut Result<HttpResponse, HttpClientError>,
+            _elapsed: Duration,
+        ) {
+            let status = result.as_ref().map(|x| x.status).unwrap_or_default();
+            let cached = request.header("x-cached").unwrap_or("0");
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("cached={} {}", cached, status));
+        }
+    }
+
+    fn request() -> TokenRequest {
+        TokenRequest::new(&Endpoints::default(), "id", "secret", Vec::new())
+    }
+
+    #[async_std::test]
+    async fn test_interceptor_short_circuit() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let http = InterceptedClient::new(MockHttpClient::new())
+            .with(Timing(seen.clone()))
+            .with(Cached);
+
+        let response = http.fetch_token(&mut request()).await.unwrap();
+        assert_eq!(response.body, "{}");
+        assert!(http.inner().requests().is_empty());
+        assert_eq!(*seen.lock().unwrap(), vec!["cached=1 200"]);
+    }
+
+    #[cfg(feature = "http-types")]
+    #[async_std::test]
+    async fn test_http_types_conversion() {
+        use std::convert::TryFrom;
+
+        let request = TokenRequest::new(
+            &Endpoints::default(),
+            "id",
+            "secret",
+            vec![
+                ("grant_type".into(), "refresh_token".into()),
+                ("refresh_token".into(), "Ng Aa".into()),
+            ],
+        );
+        let mut request = http_types::Request::try_from(request).unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://accounts.spotify.com/api/token"
+        );
+        assert_eq!(request["Authorization"], "Basic aWQ6c2VjcmV0");
+        assert_eq!(request.content_type(), Some(http_types::mime::FORM));
+        assert_eq!(
+            request.body_string().await.unwrap(),
+            "grant_type=refresh_token&refresh_token=Ng+Aa"
+        );
+
+        let mut response = http_types::Response::new(400);
+        response.set_body(r#"{"error":"invalid_grant"}"#);
+        let response = HttpResponse::from_http_types(response).await.unwrap();
+        assert_eq!(response.status, 400);
+        assert_eq!(response.body, r#"{"error":"invalid_grant"}"#);
+    }
+
+    #[async_std::test]
+    async fn test_interceptor_forwards_request() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let http =
+            InterceptedClient::new(MockHttpClient::new().with(MockResponse::status(503, "")))
+                .with(Timing(seen.clone()));
+
+        http.fetch_token(&mut request()).await.unwrap();
+        assert_eq!(http.inner().requests().len(), 1);
+        assert_eq!(*seen.lock().unwrap(), vec!["cached=0 503"]);
+    }
+
+    #[async_std::test]
+    async fn test_logging_client_masks_secrets() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let http = LoggingClient::new(
+            MockHttpClient::new().with(MockResponse::token("NgCXRK", Some("NgAagA"), "streaming")),
+            move |x| sink.lock().unwrap().push(x.to_string()),
+        );
+
+        let mut request = TokenRequest::new(
+            &Endpoints::default(),
+            "id",
+            "secret",
+            vec![
+                ("grant_type".into(), "authorization_code".into()),
+                ("code".into(), "AQD0yXvF".into()),
+                ("redirect_uri".into(), "http://localhost/callback".into()),
+            ],
+        );
+        http.fetch_token(&mut request).await.unwrap();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(
+            lines[0],
+            "POST https://accounts.spotify.com/api/token (Authorization: Basic ***) grant_type=authorization_code&code=***&redirect_uri=http%3A%2F%2Flocalhost%2Fcallback"
+        );
+        assert!(lines[1].starts_with("200 "));
+        assert!(!lines[1].contains("NgCXRK") && !lines[1].contains("NgAagA"));
+    }
+
+    #[cfg(feature = "ureq")]
+    #[async_std::test]
+    async fn test_ureq_without_tls_fails_cleanly() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("https://{}", listener.local_addr().unwrap());
+        let endpoints = Endpoints::from_base_url(&base_url).unwrap();
+        let mut request = TokenRequest::new(&endpoints, "id", "secret", Vec::new());
+
+        let err = UreqClient::new()
+            .fetch_token(&mut request)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no TLS backend"), "{}", err);
+    }
+
+    #[cfg(feature = "serde-errors")]
+    #[test]
+    fn test_http_client_error_serialize_masks_secrets() {
+        let err = HttpClientError::new("connection reset: refresh_token=NgAagA");
+
+        assert_eq!(
+            serde_json::to_value(err).unwrap(),
+            serde_json::json!({ "message": "connection reset: refresh_token=***" })
+        );
+    }
+}
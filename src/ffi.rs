@@ -0,0 +1,354 @@
+//! C API of the authorization code flow, see ``include/spotify_oauth.h``.
+//!
+//! Only available with the ``ffi`` feature. All strings are UTF-8 and NUL terminated. Strings and
+//! objects returned by the library are owned by the caller and have to be released with the
+//! matching ``spotify_oauth_*_free`` function. Failing functions return ``NULL``, the reason can
+//! be read with [`spotify_oauth_last_error`].
+//!
+//! Network calls block the calling thread.
+
+use crate::{
+    convert_callback_into_token, refresh_access_token, SpotifyAuth, SpotifyCallback, SpotifyScope,
+    SpotifyToken,
+};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    ptr,
+    str::FromStr,
+};
+use url::Url;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Remember the error of the current thread and return ``NULL``.
+fn fail<T>(message: impl Into<String>) -> *mut T {
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(message.into()));
+    ptr::null_mut()
+}
+
+/// Copy a C string argument.
+///
+/// # Safety
+///
+/// ``value`` must be ``NULL`` or point to a NUL terminated string.
+unsafe fn read_str(value: *const c_char, name: &str) -> Result<String, String> {
+    if value.is_null() {
+        return Err(format!("{} must not be NULL", name));
+    }
+
+    CStr::from_ptr(value)
+        .to_str()
+        .map(str::to_string)
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+/// Hand a string over to the caller.
+fn into_c_string(value: &str) -> *mut c_char {
+    match CString::new(value) {
+        Ok(x) => x.into_raw(),
+        Err(_) => fail("string contains a NUL byte"),
+    }
+}
+
+/// The message of the last error on the calling thread, or ``NULL`` if there was none.
+///
+/// The string has to be released with [`spotify_oauth_string_free`].
+#[no_mangle]
+pub extern "C" fn spotify_oauth_last_error() -> *mut c_char {
+    match LAST_ERROR.with(|x| x.borrow().clone()) {
+        None => ptr::null_mut(),
+        Some(x) => into_c_string(&x),
+    }
+}
+
+/// Create an authorization configuration with a freshly generated state.
+///
+/// ``scopes`` is a space separated list of Spotify scope names, e.g. ``"streaming user-read-email"``.
+///
+/// # Safety
+///
+/// All string arguments must be ``NULL`` or point to NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_auth_new(
+    client_id: *const c_char,
+    client_secret: *const c_char,
+    redirect_uri: *const c_char,
+    scopes: *const c_char,
+    show_dialog: bool,
+) -> *mut SpotifyAuth {
+    let args = (|| {
+        Ok::<_, String>((
+            read_str(client_id, "client_id")?,
+            read_str(client_secret, "client_secret")?,
+            read_str(redirect_uri, "redirect_uri")?,
+            read_str(scopes, "scopes")?,
+        ))
+    })();
+
+    let (client_id, client_secret, redirect_uri, scopes) = match args {
+        Ok(x) => x,
+        Err(err) => return fail(err),
+    };
+
+    if let Err(err) = Url::parse(&redirect_uri) {
+        return fail(format!("invalid redirect_uri: {}", err));
+    }
+
+    let mut scope = Vec::new();
+    for name in scopes.split_whitespace() {
+        match SpotifyScope::from_str(name) {
+            Ok(x) if x.is_known() => scope.push(x),
+            _ => return fail(format!("unknown scope: {}", name)),
+        }
+    }
+
+    let auth = SpotifyAuth::new(
+        client_id,
+        client_secret,
+        "code".to_string(),
+        redirect_uri,
+        scope,
+        show_dialog,
+    );
+    Box::into_raw(Box::new(auth))
+}
+
+/// Release an authorization configuration.
+///
+/// # Safety
+///
+/// ``auth`` must be ``NULL`` or have been returned by [`spotify_oauth_auth_new`] and not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_auth_free(auth: *mut SpotifyAuth) {
+    if !auth.is_null() {
+        drop(Box::from_raw(auth));
+    }
+}
+
+/// The authorization URL to open in a browser.
+///
+/// # Safety
+///
+/// ``auth`` must be ``NULL`` or a live pointer returned by [`spotify_oauth_auth_new`].
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_authorize_url(auth: *const SpotifyAuth) -> *mut c_char {
+    let auth = match auth.as_ref() {
+        None => return fail("auth must not be NULL"),
+        Some(x) => x,
+    };
+
+    match auth.authorize_url() {
+        Ok(x) => into_c_string(&x),
+        Err(err) => fail(err.to_string()),
+    }
+}
+
+/// Parse the URL Spotify redirected to, verify its state and exchange the code for a token.
+///
+/// # Safety
+///
+/// ``auth`` must be ``NULL`` or a live pointer returned by [`spotify_oauth_auth_new`],
+/// ``callback_url`` must be ``NULL`` or point to a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_exchange_callback(
+    auth: *const SpotifyAuth,
+    callback_url: *const c_char,
+) -> *mut SpotifyToken {
+    let auth = match auth.as_ref() {
+        None => return fail("auth must not be NULL"),
+        Some(x) => x,
+    };
+    let callback = match read_str(callback_url, "callback_url")
+        .and_then(|x| SpotifyCallback::from_str(&x).map_err(|err| err.to_string()))
+    {
+        Ok(x) => x,
+        Err(err) => return fail(err),
+    };
+
+    if callback.state != auth.state {
+        return fail("state of the callback does not match");
+    }
+
+    let result = futures_lite::future::block_on(convert_callback_into_token(
+        callback,
+        auth.client_id.clone(),
+        auth.client_secret.clone(),
+        auth.redirect_uri.clone(),
+    ));
+
+    match result {
+        Ok(x) => Box::into_raw(Box::new(x)),
+        Err(err) => fail(err.to_string()),
+    }
+}
+
+/// Request a new access token with a refresh token.
+///
+/// # Safety
+///
+/// ``auth`` must be ``NULL`` or a live pointer returned by [`spotify_oauth_auth_new`],
+/// ``refresh_token`` must be ``NULL`` or point to a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_refresh(
+    auth: *const SpotifyAuth,
+    refresh_token: *const c_char,
+) -> *mut SpotifyToken {
+    let auth = match auth.as_ref() {
+        None => return fail("auth must not be NULL"),
+        Some(x) => x,
+    };
+    let refresh_token = match read_str(refresh_token, "refresh_token") {
+        Ok(x) => x,
+        Err(err) => return fail(err),
+    };
+
+    let result = futures_lite::future::block_on(refresh_access_token(
+        refresh_token,
+        auth.client_id.clone(),
+        auth.client_secret.clone(),
+    ));
+
+    match result {
+        Ok(x) => Box::into_raw(Box::new(x)),
+        Err(err) => fail(err.to_string()),
+    }
+}
+
+/// The access token of a token.
+///
+/// # Safety
+///
+/// ``token`` must be ``NULL`` or a live pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_token_access_token(
+    token: *const SpotifyToken,
+) -> *mut c_char {
+    match token.as_ref() {
+        None => fail("token must not be NULL"),
+        Some(x) => into_c_string(&x.access_token),
+    }
+}
+
+/// The refresh token of a token, ``NULL`` if none was issued.
+///
+/// # Safety
+///
+/// ``token`` must be ``NULL`` or a live pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_token_refresh_token(
+    token: *const SpotifyToken,
+) -> *mut c_char {
+    match token.as_ref() {
+        None => fail("token must not be NULL"),
+        Some(x) => match x.refresh_token {
+            None => fail("token has no refresh token"),
+            Some(ref x) => into_c_string(x),
+        },
+    }
+}
+
+/// The unix timestamp at which the access token expires, ``0`` if unknown.
+///
+/// # Safety
+///
+/// ``token`` must be ``NULL`` or a live pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_token_expires_at(token: *const SpotifyToken) -> i64 {
+    token
+        .as_ref()
+        .and_then(|x| x.expires_at)
+        .unwrap_or_default()
+}
+
+/// Release a token.
+///
+/// # Safety
+///
+/// ``token`` must be ``NULL`` or have been returned by this library and not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_token_free(token: *mut SpotifyToken) {
+    if !token.is_null() {
+        drop(Box::from_raw(token));
+    }
+}
+
+/// Release a string returned by this library.
+///
+/// # Safety
+///
+/// ``value`` must be ``NULL`` or have been returned by this library and not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn spotify_oauth_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn take_string(value: *mut c_char) -> String {
+        let out = CStr::from_ptr(value).to_str().unwrap().to_string();
+        spotify_oauth_string_free(value);
+        out
+    }
+
+    #[test]
+    fn test_ffi_authorize_url() {
+        unsafe {
+            let auth = spotify_oauth_auth_new(
+                c"id".as_ptr(),
+                c"secret".as_ptr(),
+                c"http://localhost:8000/callback".as_ptr(),
+                c"streaming user-read-email".as_ptr(),
+                false,
+            );
+            assert!(!auth.is_null());
+
+            let url = take_string(spotify_oauth_authorize_url(auth));
+            assert!(url.contains("scope=streaming+user-read-email"));
+            spotify_oauth_auth_free(auth);
+        }
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        unsafe {
+            let auth = spotify_oauth_auth_new(
+                c"id".as_ptr(),
+                ptr::null(),
+                c"http://localhost:8000/callback".as_ptr(),
+                c"".as_ptr(),
+                false,
+            );
+            assert!(auth.is_null());
+            assert_eq!(
+                take_string(spotify_oauth_last_error()),
+                "client_secret must not be NULL"
+            );
+
+            let auth = spotify_oauth_auth_new(
+                c"id".as_ptr(),
+                c"secret".as_ptr(),
+                c"http://localhost:8000/callback".as_ptr(),
+                c"".as_ptr(),
+                false,
+            );
+            let token = spotify_oauth_exchange_callback(
+                auth,
+                c"http://localhost:8000/callback?code=AQD0&state=forged".as_ptr(),
+            );
+            assert!(token.is_null());
+            assert_eq!(
+                take_string(spotify_oauth_last_error()),
+                "state of the callback does not match"
+            );
+            spotify_oauth_auth_free(auth);
+        }
+    }
+}
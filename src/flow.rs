@@ -0,0 +1,310 @@
+use crate::{
+    error::*,
+    protocol::{self, ParseMode},
+    AppClient, Clock, HttpResponse, SpotifyAuth, SpotifyCallback, SpotifyToken, SystemClock,
+    TokenRequest,
+};
+use std::sync::Arc;
+
+/// The authorization code flow as a state machine without I/O and without await points.
+///
+/// GUI and game loop applications drive the flow frame by frame: every event is fed to
+/// [`AuthFlow::step`], which answers with the [`NextAction`] to perform. The application opens
+/// the URL, waits for the callback and sends the token request with any HTTP stack, e.g. on a
+/// worker thread, and renders the progress with [`AuthFlow::stage`] in the meantime.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{AuthFlow, FlowInput, HttpResponse, NextAction, SpotifyAuth, SpotifyCallback, SpotifyScope};
+/// # use std::str::FromStr;
+/// let auth = SpotifyAuth::new("00000000000".into(), "secret".into(), "code".into(), "http://localhost:8888/callback".into(), vec![SpotifyScope::Streaming], false);
+/// let state = auth.state.clone();
+/// let mut flow = AuthFlow::new(auth);
+///
+/// let NextAction::OpenUrl(url) = flow.step(FlowInput::Start) else { unreachable!() };
+/// // Open the URL, then, frames later, the redirect arrives.
+/// let callback = SpotifyCallback::from_str(&format!("http://localhost:8888/callback?code=NApCCgBkWtQ&state={}", state)).unwrap();
+/// let NextAction::SendRequest(request) = flow.step(FlowInput::Callback(callback)) else { unreachable!() };
+/// // Send the request, then, frames later, the response arrives.
+/// let response = HttpResponse {
+///     status: 200,
+///     body: r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "streaming", "expires_in": 3600, "refresh_token": "NgAagA"}"#.into(),
+/// };
+/// let NextAction::Done(token) = flow.step(FlowInput::Response(response)) else { unreachable!() };
+/// # assert_eq!(token.access_token, "NgCXRK");
+/// ```
+pub struct AuthFlow {
+    auth: SpotifyAuth,
+    stage: FlowStage,
+    mode: ParseMode,
+    clock: Arc<dyn Clock>,
+}
+
+/// The progress of an [`AuthFlow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowStage {
+    /// [`FlowInput::Start`] was not given yet.
+    NotStarted,
+    /// The user authorizes the application in the browser.
+    WaitingForCallback,
+    /// The code is exchanged for a token.
+    ExchangingCode,
+    /// The flow succeeded or failed.
+    Finished,
+}
+
+/// An event fed to [`AuthFlow::step`].
+#[derive(Debug)]
+pub enum FlowInput {
+    /// Begin the flow.
+    Start,
+    /// The browser was redirected to the redirect URI.
+    Callback(SpotifyCallback),
+    /// The response to the [`NextAction::SendRequest`] request.
+    Response(HttpResponse),
+    /// The request of [`NextAction::SendRequest`] could not be sent.
+    TransportFailed(String),
+    /// The user aborted the login, e.g. by closing the dialog.
+    Cancel,
+}
+
+/// What the application has to do next, returned by [`AuthFlow::step`].
+#[derive(Debug)]
+pub enum NextAction {
+    /// Send the user to this authorization URL, then wait for the callback.
+    OpenUrl(String),
+    /// Send this request to the token endpoint and feed the response back.
+    SendRequest(TokenRequest),
+    /// The flow succeeded.
+    Done(SpotifyToken),
+    /// The flow failed, or the input did not fit the current stage.
+    Failed(SpotifyError),
+}
+
+impl AuthFlow {
+    /// A flow authorizing with the settings, including the state, of ``auth``.
+    pub fn new(auth: SpotifyAuth) -> Self {
+        Self {
+            auth,
+            stage: FlowStage::NotStarted,
+            mode: ParseMode::Tolerant,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Stamp the ``expires_at`` of the token with ``clock`` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fail fast on anything unexpected: besides the checks of [`AppClient::strict`] the token
+    /// must grant exactly the scopes of the authorization.
+    pub fn strict(mut self) -> Self {
+        self.mode = ParseMode::Strict;
+        self
+    }
+
+    /// The progress of the flow.
+    pub fn stage(&self) -> FlowStage {
+        self.stage
+    }
+
+    /// Advance the flow with an event.
+    ///
+    /// An input which does not fit the current stage, e.g. a second callback, yields
+    /// [`NextAction::Failed`] and leaves the flow unchanged. Every other failure finishes the flow.
+    pub fn step(&mut self, input: FlowInput) -> NextAction {
+        let action = match (self.stage, input) {
+            (FlowStage::NotStarted, FlowInput::Start) => self.start(),
+            (FlowStage::WaitingForCallback, FlowInput::Callback(callback)) => {
+                self.exchange(&callback)
+            }
+            (FlowStage::ExchangingCode, FlowInput::Response(response)) => {
+                match self.finish(&response) {
+                    Ok(token) => NextAction::Done(token),
+                    Err(err) => NextAction::Failed(err),
+                }
+            }
+            (FlowStage::ExchangingCode, FlowInput::TransportFailed(context)) => {
+                NextAction::Failed(SpotifyError::Transport { context })
+            }
+            (stage, FlowInput::Cancel) if stage != FlowStage::Finished => {
+                NextAction::Failed(SpotifyError::Cancelled)
+            }
+            _ => {
+                return NextAction::Failed(SpotifyError::FlowFailure {
+                    context: "The input does not fit the current stage of the flow.",
+                })
+            }
+        };
+
+        self.stage = match action {
+            NextAction::OpenUrl(_) => FlowStage::WaitingForCallback,
+            NextAction::SendRequest(_) => FlowStage::ExchangingCode,
+            NextAction::Done(_) | NextAction::Failed(_) => FlowStage::Finished,
+        };
+
+        action
+    }
+
+    fn start(&self) -> NextAction {
+        match self.auth.authorize_url() {
+            Ok(url) => NextAction::OpenUrl(url),
+            Err(err) => NextAction::Failed(err),
+        }
+    }
+
+    fn exchange(&self, callback: &SpotifyCallback) -> NextAction {
+        if self.mode == ParseMode::Strict {
+            if let Err(err) = protocol::check_callback_strict(callback) {
+                return NextAction::Failed(err);
+            }
+        }
+
+        if callback.state() != self.auth.state {
+            return NextAction::Failed(SpotifyError::CallbackFailure {
+                context: "The state of the callback does not match the authorization.",
+            });
+        }
+
+        match (callback.code(), callback.error()) {
+            (Some(code), _) => NextAction::SendRequest(protocol::exchange_request(
+                &AppClient::from(&self.auth),
+                code,
+                &self.auth.redirect_uri,
+            )),
+            (None, error) => NextAction::Failed(SpotifyError::Protocol {
+                status: None,
+                error: error.unwrap_or("access_denied").to_string(),
+                description: None,
+            }),
+        }
+    }
+
+    fn finish(&self, response: &HttpResponse) -> SpotifyResult<SpotifyToken> {
+        if self.mode == ParseMode::Tolerant {
+            return protocol::finish_exchange(response, &*self.clock);
+        }
+
+        protocol::check_response_strict(response)?;
+        let token = protocol::finish_exchange(response, &*self.clock)?;
+        token.ensure_scopes(&self.auth.scope)?;
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Endpoints, SpotifyScope};
+    use std::{str::FromStr, sync::Arc};
+    use url::Url;
+
+    fn flow() -> AuthFlow {
+        AuthFlow::new(SpotifyAuth {
+            client_id: "00000000000".to_string(),
+            client_secret: "secret".to_string(),
+            response_type: "code".to_string(),
+            redirect_uri: Url::parse("http://localhost:8888/callback").unwrap(),
+            redirect_uris: Vec::new(),
+            state: "Ys6Lq0R2".to_string(),
+            state_generator: Arc::new(|| "Ys6Lq0R2".to_string()),
+            scope: vec![SpotifyScope::Streaming],
+            show_dialog: false,
+            endpoints: Endpoints::default(),
+        })
+    }
+
+    #[test]
+    fn test_flow_rejects_inputs_out_of_order() {
+        let mut flow = flow();
+        let callback = |state: &str| {
+            let url = format!(
+                "http://localhost:8888/callback?code=NApCCgBkWtQ&state={}",
+                state
+            );
+            FlowInput::Callback(SpotifyCallback::from_str(&url).unwrap())
+        };
+
+        assert!(matches!(
+            flow.step(callback("Ys6Lq0R2")),
+            NextAction::Failed(SpotifyError::FlowFailure { .. })
+        ));
+        assert_eq!(flow.stage(), FlowStage::NotStarted);
+
+        assert!(matches!(
+            flow.step(FlowInput::Start),
+            NextAction::OpenUrl(_)
+        ));
+        assert!(matches!(
+            flow.step(FlowInput::Start),
+            NextAction::Failed(SpotifyError::FlowFailure { .. })
+        ));
+        assert_eq!(flow.stage(), FlowStage::WaitingForCallback);
+
+        let NextAction::SendRequest(request) = flow.step(callback("Ys6Lq0R2")) else {
+            panic!("expected a token request");
+        };
+        assert_eq!(request.form_field("code"), Some("NApCCgBkWtQ"));
+
+        assert!(matches!(
+            flow.step(FlowInput::TransportFailed("connection reset".to_string())),
+            NextAction::Failed(SpotifyError::Transport { .. })
+        ));
+        assert_eq!(flow.stage(), FlowStage::Finished);
+    }
+
+    #[test]
+    fn test_flow_checks_state() {
+        let mut flow = flow();
+        flow.step(FlowInput::Start);
+        let callback =
+            SpotifyCallback::from_str("http://localhost:8888/callback?code=NApCCgBkWtQ&state=x")
+                .unwrap();
+
+        assert!(matches!(
+            flow.step(FlowInput::Callback(callback)),
+            NextAction::Failed(SpotifyError::CallbackFailure { .. })
+        ));
+        assert_eq!(flow.stage(), FlowStage::Finished);
+    }
+
+    #[test]
+    fn test_strict_flow_requires_requested_scopes() {
+        let mut flow = flow().strict();
+        flow.step(FlowInput::Start);
+        let callback = SpotifyCallback::from_str(
+            "http://localhost:8888/callback?code=NApCCgBkWtQ&state=Ys6Lq0R2",
+        )
+        .unwrap();
+        flow.step(FlowInput::Callback(callback));
+
+        let response = HttpResponse {
+            status: 200,
+            body: r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "", "expires_in": 3600}"#.into(),
+        };
+        assert!(matches!(
+            flow.step(FlowInput::Response(response)),
+            NextAction::Failed(SpotifyError::TokenFailure { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flow_cancel() {
+        let mut flow = flow();
+        flow.step(FlowInput::Start);
+
+        assert!(matches!(
+            flow.step(FlowInput::Cancel),
+            NextAction::Failed(SpotifyError::Cancelled)
+        ));
+        assert_eq!(flow.stage(), FlowStage::Finished);
+        assert!(matches!(
+            flow.step(FlowInput::Cancel),
+            NextAction::Failed(SpotifyError::FlowFailure { .. })
+        ));
+    }
+}
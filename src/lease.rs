@@ -0,0 +1,128 @@
+//! Coordination of token refreshes between processes of one machine.
+
+use crate::error::*;
+use futures_channel::oneshot;
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::PathBuf,
+    thread,
+};
+
+/// Lock files granting one process at a time the right to refresh the token of an account.
+///
+/// CLI tools and daemons sharing a token store take the lease of an account before refreshing
+/// its token and re-read the store once they hold it: if another process refreshed the token in
+/// the meantime they use that token instead of rotating the refresh token again. The leases are
+/// advisory OS locks on ``<dir>/<key>.lock``, released when the lease is dropped or the process
+/// exits.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::FileRefreshLock;
+/// let dir = std::env::temp_dir().join("spotify-oauth-doc-leases");
+/// let locks = FileRefreshLock::new(&dir);
+///
+/// let lease = locks.acquire("alice").unwrap();
+/// # assert!(locks.try_acquire("alice").unwrap().is_none());
+/// // Re-read the store, refresh if still needed and save.
+/// drop(lease);
+/// # assert!(locks.try_acquire("alice").unwrap().is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRefreshLock {
+    dir: PathBuf,
+}
+
+/// The right to refresh the token of one account, held until dropped.
+#[derive(Debug)]
+pub struct RefreshLease {
+    _file: File,
+}
+
+impl FileRefreshLock {
+    /// Keep the lock files in ``dir``, which is created if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Wait until no other process holds the lease of ``key`` and take it.
+    pub fn acquire(&self, key: &str) -> SpotifyResult<RefreshLease> {
+        let file = self.open(key)?;
+        file.lock().map_err(lock_failure)?;
+
+        Ok(RefreshLease { _file: file })
+    }
+
+    /// Take the lease of ``key``, ``None`` if another process holds it.
+    pub fn try_acquire(&self, key: &str) -> SpotifyResult<Option<RefreshLease>> {
+        let file = self.open(key)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(RefreshLease { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(fs::TryLockError::Error(err)) => Err(lock_failure(err)),
+        }
+    }
+
+    /// Like [`FileRefreshLock::acquire`], but waits for the lease on a helper thread, so an async
+    /// caller does not block its executor.
+    pub(crate) async fn acquire_async(&self, key: &str) -> SpotifyResult<RefreshLease> {
+        if let Some(lease) = self.try_acquire(key)? {
+            return Ok(lease);
+        }
+
+        let (locks, key) = (self.clone(), key.to_owned());
+        let (sender, receiver) = oneshot::channel();
+        thread::spawn(move || {
+            // The caller may have given up waiting.
+            let _ = sender.send(locks.acquire(&key));
+        });
+
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(lock_failure(io::Error::other("the waiting thread stopped"))))
+    }
+
+    fn open(&self, key: &str) -> SpotifyResult<File> {
+        fs::create_dir_all(&self.dir).map_err(lock_failure)?;
+
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(format!("{}.lock", file_name(key))))
+            .map_err(lock_failure)
+    }
+}
+
+/// A file name for a store key, e.g. a [`crate::scoped_key`], escaping characters some file
+/// systems reject.
+fn file_name(key: &str) -> String {
+    key.bytes()
+        .map(|x| match x {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (x as char).to_string(),
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
+
+fn lock_failure(err: io::Error) -> SpotifyError {
+    SpotifyError::StoreFailure {
+        context: format!("refresh lock: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_escapes_scoped_keys() {
+        assert_eq!(
+            file_name("alice:streaming,user-read-email"),
+            "alice%3Astreaming%2Cuser-read-email"
+        );
+        assert_eq!(file_name("../bob"), "..%2Fbob");
+    }
+}
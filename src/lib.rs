@@ -5,8 +5,8 @@
 //! # Basic Example
 //!
 //! ```no_run
-//! use std::{io::stdin, str::FromStr, error::Error};
-//! use spotify_oauth::{convert_callback_into_token, SpotifyAuth, SpotifyCallback, SpotifyScope};
+//! use std::{io::stdin, str::FromStr, error::Error, sync::Arc};
+//! use spotify_oauth::{convert_callback_into_token, Endpoints, RandomStateGenerator, SpotifyAuth, SpotifyCallback, SpotifyScope};
 //! use url::Url;
 //!
 //! #[async_std::main]
@@ -20,7 +20,10 @@
 //!         client_id : "YOUR_SPOTIFY_CLIENT_ID".to_string(),
 //!         client_secret : "YOUR_SPOTIFY_CLIENT_SECRET".to_string(),
 //!         redirect_uri : Url::parse("http://localhost:8080/callback").unwrap(),
-//!         state : "-use-a-radom-string-".to_string()
+//!         redirect_uris : vec![],
+//!         state : "-use-a-radom-string-".to_string(),
+//!         state_generator : Arc::new(RandomStateGenerator),
+//!         endpoints : Endpoints::default(),
 //!     };
 //!     let auth_url = auth.authorize_url()?;
 //!
@@ -41,15 +44,61 @@
 //! }
 //! ```
 
+mod audit;
 mod auth;
+#[cfg(feature = "uniffi")]
+pub mod bindings;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "open")]
+mod browser;
 mod callback;
+mod cancel;
+mod client;
+mod clock;
+mod endpoints;
 mod error;
+mod export;
+mod fetch;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod flow;
+mod lease;
+mod manager;
+#[cfg(feature = "prometheus")]
+mod metrics;
+#[cfg(feature = "otel")]
+mod otel;
+mod pending;
+mod probe;
+pub mod protocol;
+mod provider;
+pub mod rfc3339;
 mod scope;
+#[cfg(feature = "server")]
+mod server;
+mod store;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 mod token;
 mod util;
+#[cfg(feature = "wasm")]
+pub mod web;
 
 use crate::error::*;
 
-pub use crate::{auth::*, callback::*, scope::*, token::*, util::*};
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
 
-const SPOTIFY_AUTH_URL: &str = "https://accounts.spotify.com/authorize";
+#[cfg(feature = "open")]
+pub use crate::browser::*;
+pub use crate::error::{ErrorCategory, SpotifyError, SpotifyResult};
+#[cfg(feature = "prometheus")]
+pub use crate::metrics::*;
+#[cfg(feature = "server")]
+pub use crate::server::*;
+pub use crate::{
+    audit::*, auth::*, callback::*, cancel::*, client::*, clock::*, endpoints::*, export::*,
+    fetch::*, flow::*, lease::*, manager::*, pending::*, probe::*, provider::*, scope::*, store::*,
+    token::*, util::*,
+};
@@ -0,0 +1,790 @@
+//! Keeping the token of an account valid.
+//!
+//! The refresh logic lives in [`ManagerCore`], which is shared by the async [`TokenManager`] and
+//! ``blocking::TokenManager``. They only differ in how they wait for the refresh lease and for the
+//! refresh itself.
+
+use crate::{
+    error::*, scoped_key, store::same_version, AccessTokenProvider, AppClient, Clock,
+    FileRefreshLock, HttpClient, SpotifyScope, SpotifyToken, SystemClock, TokenStore,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_channel::mpsc::{self as channel, UnboundedSender};
+use futures_core::Stream;
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Keeps the token of one account in a [`TokenStore`] and refreshes it when it expired.
+///
+/// The async counterpart of ``blocking::TokenManager`` with the same features: the refresh is
+/// awaited on the HTTP client, so the executor thread is never blocked by it, and so is the
+/// refresh lease. The store is called directly from the futures, use one which answers without
+/// blocking, e.g. a [`MemoryTokenStore`](crate::MemoryTokenStore).
+///
+/// Web API clients accept the manager as an [`AccessTokenProvider`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{AccessTokenProvider, AppClient, MemoryTokenStore, SpotifyToken, SurfClient, TokenManager};
+/// # use std::time::Duration;
+/// # async fn run(token: SpotifyToken) -> spotify_oauth::SpotifyResult<()> {
+/// let app = AppClient::new("00000000000", "secret");
+/// let manager = TokenManager::new(app, SurfClient::new(), MemoryTokenStore::default(), "alice")
+///     .with_refresh_margin(Duration::from_secs(60));
+/// manager.save(&token)?;
+///
+/// // A valid access token, refreshed when it expires within a minute.
+/// let access_token = manager.access_token().await?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct TokenManager<S, C> {
+    app: AppClient,
+    http: C,
+    core: ManagerCore<S>,
+}
+
+impl<S: TokenStore, C: HttpClient> TokenManager<S, C> {
+    /// Manage the token stored under ``key`` in ``store``, refreshing it with ``app`` over
+    /// ``http``.
+    pub fn new(app: AppClient, http: C, store: S, key: impl Into<String>) -> Self {
+        Self {
+            app,
+            http,
+            core: ManagerCore::new(store, key.into()),
+        }
+    }
+
+    /// Manage the token of ``account`` granting exactly ``scope``, stored under the
+    /// [`scoped_key`] of both.
+    pub fn for_scopes(
+        app: AppClient,
+        http: C,
+        store: S,
+        account: &str,
+        scope: &[SpotifyScope],
+    ) -> Self {
+        Self::new(app, http, store, scoped_key(account, scope))
+    }
+
+    /// Refresh only while holding the lease of the key in ``lock``, so one process at a time
+    /// refreshes the token of an account shared by several processes of the machine.
+    ///
+    /// The lease is awaited on a helper thread, the other processes use the token stored by its
+    /// holder.
+    pub fn with_refresh_lock(mut self, lock: FileRefreshLock) -> Self {
+        self.core.lock = Some(lock);
+        self
+    }
+
+    /// Refresh the token once it expires within ``margin`` instead of once it expired.
+    pub fn with_refresh_margin(mut self, margin: Duration) -> Self {
+        self.core.margin = margin;
+        self
+    }
+
+    /// Handle failed refreshes according to ``policy`` instead of failing right away.
+    pub fn with_refresh_failure_policy(mut self, policy: RefreshFailurePolicy) -> Self {
+        self.core.policy = policy;
+        self
+    }
+
+    /// Read the time from ``clock`` instead of the system clock, also when stamping the expiry of
+    /// refreshed tokens, see [`AppClient::with_clock`].
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.app.clock = clock.clone();
+        self.core.clock = clock;
+        self
+    }
+
+    /// Store the token of a new authorization.
+    pub fn save(&self, token: &SpotifyToken) -> SpotifyResult<()> {
+        self.core.save(token)
+    }
+
+    /// The stored token, refreshed and stored again if the access token expired or expires within
+    /// the refresh margin.
+    ///
+    /// The refreshed token is stored with [`TokenStore::compare_and_swap`]. If another process
+    /// refreshed the token in the meantime, its token is kept and returned instead. Like the
+    /// blocking manager, it also checks the tokens it saved or refreshed itself against the
+    /// monotonic clock.
+    ///
+    /// Yields ``None`` if no usable token is stored and the user has to log in again. Fails with
+    /// [`SpotifyError::TokenFailure`] once the manager was shut down.
+    pub async fn token(&self) -> SpotifyResult<Option<SpotifyToken>> {
+        self.token_within(self.core.margin).await
+    }
+
+    /// A token valid for at least ``min``, refreshed first if needed.
+    ///
+    /// Fails with [`SpotifyError::NeedsReauthorization`] if no usable token is stored, and with
+    /// [`SpotifyError::TokenFailure`] if even a refreshed token expires within ``min``.
+    pub async fn get_valid_token(&self, min: Duration) -> SpotifyResult<ValidToken> {
+        let token = self.token_within(self.core.margin.max(min)).await?;
+        self.core.valid_token(token, min)
+    }
+
+    async fn token_within(&self, margin: Duration) -> SpotifyResult<Option<SpotifyToken>> {
+        let mut token = match self.core.begin(margin)? {
+            Step::Done(x) => return Ok(x),
+            Step::Refresh(x) => x,
+        };
+
+        // Held until the refreshed token is stored.
+        let _lease = match &self.core.lock {
+            Some(lock) => {
+                let lease = lock.acquire_async(&self.core.key).await?;
+                token = match self.core.check(margin)? {
+                    Step::Done(x) => return Ok(x),
+                    Step::Refresh(x) => x,
+                };
+                Some(lease)
+            }
+            None => None,
+        };
+
+        let refreshed = self
+            .refresh(token.refresh_token.as_deref().unwrap_or_default())
+            .await;
+        self.core.finish(token, refreshed)
+    }
+
+    /// Refresh the token, retrying transient failures as configured.
+    async fn refresh(&self, refresh_token: &str) -> SpotifyResult<SpotifyToken> {
+        match &self.core.policy {
+            #[cfg(feature = "backoff")]
+            RefreshFailurePolicy::Retry(policy) => {
+                self.app
+                    .refresh_with_backoff(&self.http, refresh_token, policy())
+                    .await
+            }
+            _ => self.app.refresh(&self.http, refresh_token).await,
+        }
+    }
+
+    /// The tokens saved or refreshed by the manager from now on, ending when the manager is
+    /// dropped or shut down.
+    pub fn updates(&self) -> impl Stream<Item = SpotifyToken> + Send + Unpin + 'static {
+        self.core.updates()
+    }
+
+    /// Resume refreshing tokens after [`TokenManager::pause_refresh`] or
+    /// [`TokenManager::shutdown`].
+    pub fn start(&self) {
+        self.core.set_lifecycle(Lifecycle::Running);
+    }
+
+    /// Stop refreshing tokens, [`TokenManager::token`] then returns the stored token as is.
+    pub fn pause_refresh(&self) {
+        self.core.set_lifecycle(Lifecycle::Paused);
+    }
+
+    /// Stop the purge threads, end the [`TokenManager::updates`] streams and reject further token
+    /// requests.
+    pub fn shutdown(&self) {
+        self.core.shutdown();
+    }
+
+    /// Forget the token, e.g. when the user logs out.
+    pub fn remove(&self) -> SpotifyResult<()> {
+        self.core.remove()
+    }
+
+    /// Call [`TokenStore::purge_expired`] on the store every ``interval`` on a background thread,
+    /// until the returned task or the manager is dropped or the manager is shut down.
+    pub fn spawn_purge(&self, interval: Duration) -> PurgeTask
+    where
+        S: Clone + 'static,
+    {
+        self.core.spawn_purge(interval)
+    }
+}
+
+/// The access token of the managed account, refreshed without blocking the executor.
+#[async_trait]
+impl<S: TokenStore, C: HttpClient> AccessTokenProvider for TokenManager<S, C> {
+    async fn access_token(&self) -> SpotifyResult<String> {
+        match self.token().await? {
+            Some(x) => Ok(x.access_token),
+            None => Err(SpotifyError::NeedsReauthorization { description: None }),
+        }
+    }
+}
+
+/// What a token manager does when refreshing the token fails, see
+/// [`TokenManager::with_refresh_failure_policy`].
+#[derive(Clone, Default)]
+pub enum RefreshFailurePolicy {
+    /// Return the error right away.
+    #[default]
+    FailFast,
+    /// Retry transport failures, rate limits and server errors with the delays of a [`backoff`]
+    /// policy, see [`RefreshFailurePolicy::retry`] and [`AppClient::refresh_with_backoff`].
+    ///
+    /// Only available with the ``backoff`` feature.
+    #[cfg(feature = "backoff")]
+    Retry(Arc<dyn Fn() -> Box<dyn backoff::backoff::Backoff + Send> + Send + Sync>),
+    /// Keep returning the stored token until it expired and try again on the next call.
+    ///
+    /// Only tokens refreshed early because of a refresh margin are served stale, see
+    /// [`TokenManager::with_refresh_margin`].
+    ServeStale,
+}
+
+impl RefreshFailurePolicy {
+    /// Retry with a fresh copy of ``policy`` for every refresh, e.g. a
+    /// ``backoff::ExponentialBackoff`` giving up after a minute.
+    ///
+    /// Only available with the ``backoff`` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::RefreshFailurePolicy;
+    /// # use std::time::Duration;
+    /// let policy = RefreshFailurePolicy::retry(
+    ///     backoff::ExponentialBackoffBuilder::new()
+    ///         .with_max_elapsed_time(Some(Duration::from_secs(60)))
+    ///         .build(),
+    /// );
+    /// ```
+    #[cfg(feature = "backoff")]
+    pub fn retry<B>(policy: B) -> Self
+    where
+        B: backoff::backoff::Backoff + Clone + Send + Sync + 'static,
+    {
+        Self::Retry(Arc::new(move || Box::new(policy.clone())))
+    }
+}
+
+impl fmt::Debug for RefreshFailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FailFast => f.write_str("FailFast"),
+            #[cfg(feature = "backoff")]
+            Self::Retry(_) => f.write_str("Retry"),
+            Self::ServeStale => f.write_str("ServeStale"),
+        }
+    }
+}
+
+/// A token of [`TokenManager::get_valid_token`], valid at least for the requested duration.
+///
+/// It is a snapshot: the manager may refresh the stored token while it is in use.
+#[derive(Clone)]
+pub struct ValidToken {
+    token: SpotifyToken,
+    valid_until: Instant,
+    /// The clock of the manager.
+    clock: Arc<dyn Clock>,
+}
+
+impl ValidToken {
+    /// The time until the access token expires, the shorter of the monotonic and the wall clock
+    /// count, both read from the clock of the manager.
+    pub fn remaining(&self) -> Duration {
+        let wall = self.token.remaining_with(&*self.clock).unwrap_or_default();
+        wall.min(
+            self.valid_until
+                .saturating_duration_since(self.clock.instant()),
+        )
+    }
+
+    /// The token itself.
+    pub fn into_inner(self) -> SpotifyToken {
+        self.token
+    }
+}
+
+impl fmt::Debug for ValidToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidToken")
+            .field("token", &self.token)
+            .field("valid_until", &self.valid_until)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for ValidToken {
+    type Target = SpotifyToken;
+
+    fn deref(&self) -> &SpotifyToken {
+        &self.token
+    }
+}
+
+/// The periodic purge of a token store started by [`TokenManager::spawn_purge`], stopped when
+/// dropped.
+#[derive(Debug)]
+pub struct PurgeTask {
+    stop: Option<Sender<()>>,
+    pub(crate) thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for PurgeTask {
+    fn drop(&mut self) {
+        // The manager holds the channel as well, so the thread is woken up with a message.
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The state and the refresh logic of a token manager, without the I/O of the refresh itself.
+///
+/// A token request calls [`ManagerCore::begin`], and on [`Step::Refresh`] takes the refresh lease
+/// if configured, calls [`ManagerCore::check`] again, refreshes the token and hands the outcome to
+/// [`ManagerCore::finish`].
+pub(crate) struct ManagerCore<S> {
+    pub(crate) store: S,
+    pub(crate) key: String,
+    pub(crate) lock: Option<FileRefreshLock>,
+    pub(crate) margin: Duration,
+    pub(crate) policy: RefreshFailurePolicy,
+    pub(crate) clock: Arc<dyn Clock>,
+    deadline: Mutex<Option<Deadline>>,
+    pub(crate) subscribers: Mutex<Vec<UnboundedSender<SpotifyToken>>>,
+    lifecycle: Mutex<Lifecycle>,
+    /// Stops the background tasks spawned by the manager.
+    tasks: Mutex<Vec<Sender<()>>>,
+}
+
+/// What a token request does next.
+pub(crate) enum Step {
+    /// Return the stored token as is.
+    Done(Option<SpotifyToken>),
+    /// Refresh the stored token, which has a refresh token.
+    Refresh(SpotifyToken),
+}
+
+/// The monotonic deadline of the last token a manager saved or refreshed.
+#[derive(Debug)]
+struct Deadline {
+    access_token: String,
+    /// The wall clock expiry the deadline was derived from.
+    expires_at: i64,
+    instant: Instant,
+}
+
+/// Whether a manager refreshes tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lifecycle {
+    Running,
+    Paused,
+    ShutDown,
+}
+
+impl<S: TokenStore> ManagerCore<S> {
+    pub(crate) fn new(store: S, key: String) -> Self {
+        Self {
+            store,
+            key,
+            lock: None,
+            margin: Duration::ZERO,
+            policy: RefreshFailurePolicy::FailFast,
+            clock: Arc::new(SystemClock),
+            deadline: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            lifecycle: Mutex::new(Lifecycle::Running),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn save(&self, token: &SpotifyToken) -> SpotifyResult<()> {
+        self.store.save(&self.key, token)?;
+        self.remember(token);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self) -> SpotifyResult<()> {
+        self.store.remove(&self.key)
+    }
+
+    /// The first step of a token request, which fails once the manager was shut down and skips the
+    /// refresh while it is paused.
+    pub(crate) fn begin(&self, margin: Duration) -> SpotifyResult<Step> {
+        match *self.lifecycle.lock().unwrap() {
+            Lifecycle::Running => self.check(margin),
+            Lifecycle::Paused => self.load().map(Step::Done),
+            Lifecycle::ShutDown => Err(SpotifyError::TokenFailure {
+                context: "The token manager has been shut down.",
+            }),
+        }
+    }
+
+    /// Whether the stored token has to be refreshed before it expires within ``margin``.
+    pub(crate) fn check(&self, margin: Duration) -> SpotifyResult<Step> {
+        Ok(match self.load()? {
+            Some(x) if self.needs_refresh(&x, margin) => Step::Refresh(x),
+            x => Step::Done(x),
+        })
+    }
+
+    /// Store the outcome of refreshing ``token``, falling back on the token of another process and
+    /// on the failure policy if the refresh failed.
+    pub(crate) fn finish(
+        &self,
+        token: SpotifyToken,
+        refreshed: SpotifyResult<SpotifyToken>,
+    ) -> SpotifyResult<Option<SpotifyToken>> {
+        let refreshed = match refreshed {
+            Ok(x) => x,
+            Err(err) => {
+                if let Some(other) = self.refreshed_elsewhere(&token)? {
+                    return Ok(Some(other));
+                }
+                if matches!(self.policy, RefreshFailurePolicy::ServeStale)
+                    && !self.is_expired(&token)
+                {
+                    return Ok(Some(token));
+                }
+                return Err(err);
+            }
+        };
+
+        if self.store.compare_and_swap(&self.key, &token, &refreshed)? {
+            self.remember(&refreshed);
+            Ok(Some(refreshed))
+        } else {
+            self.load()
+        }
+    }
+
+    /// ``token`` as a [`ValidToken`], if it is valid for at least ``min``.
+    pub(crate) fn valid_token(
+        &self,
+        token: Option<SpotifyToken>,
+        min: Duration,
+    ) -> SpotifyResult<ValidToken> {
+        let token = token
+            .filter(|x| !self.is_expired(x))
+            .ok_or(SpotifyError::NeedsReauthorization { description: None })?;
+
+        let remaining = self.remaining(&token).unwrap_or_default();
+        if remaining < min {
+            return Err(SpotifyError::TokenFailure {
+                context: "The token expires within the requested minimum lifetime.",
+            });
+        }
+
+        Ok(ValidToken {
+            token,
+            valid_until: self.clock.instant() + remaining,
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// The stored token if another process replaced ``token`` with a valid one.
+    fn refreshed_elsewhere(&self, token: &SpotifyToken) -> SpotifyResult<Option<SpotifyToken>> {
+        Ok(self
+            .load()?
+            .filter(|x| !same_version(x, token) && !self.is_expired(x)))
+    }
+
+    fn load(&self) -> SpotifyResult<Option<SpotifyToken>> {
+        self.store.load(&self.key)
+    }
+
+    /// The remaining lifetime of ``token``, also measured with the monotonic clock if the manager
+    /// saved or refreshed it itself.
+    ///
+    /// The shorter of both counts: the wall clock may be set back, and the monotonic clock stops
+    /// while the machine is suspended.
+    fn remaining(&self, token: &SpotifyToken) -> Option<Duration> {
+        let wall = token.remaining_with(&*self.clock)?;
+
+        match &*self.deadline.lock().unwrap() {
+            Some(x)
+                if x.access_token == token.access_token
+                    && token.expires_at == Some(x.expires_at) =>
+            {
+                Some(wall.min(x.instant.saturating_duration_since(self.clock.instant())))
+            }
+            _ => Some(wall),
+        }
+    }
+
+    fn is_expired(&self, token: &SpotifyToken) -> bool {
+        self.remaining(token).is_none_or(|x| x == Duration::ZERO)
+    }
+
+    /// Whether the token has to be refreshed before it expires within ``margin``.
+    fn needs_refresh(&self, token: &SpotifyToken, margin: Duration) -> bool {
+        token.refresh_token.is_some() && self.remaining(token).is_none_or(|x| x <= margin)
+    }
+
+    fn remember(&self, token: &SpotifyToken) {
+        *self.deadline.lock().unwrap() = token.remaining_with(&*self.clock).map(|x| Deadline {
+            access_token: token.access_token.clone(),
+            expires_at: token.expires_at.unwrap_or_default(),
+            instant: self.clock.instant() + x,
+        });
+
+        // Subscribers which dropped their stream are forgotten.
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|x| x.unbounded_send(token.clone()).is_ok());
+    }
+
+    pub(crate) fn spawn_purge(&self, interval: Duration) -> PurgeTask
+    where
+        S: Clone + 'static,
+    {
+        let store = self.store.clone();
+        let (stop, stopped) = mpsc::channel::<()>();
+        self.tasks.lock().unwrap().push(stop.clone());
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let _ = store.purge_expired(Utc::now());
+            }
+        });
+
+        PurgeTask {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<S> ManagerCore<S> {
+    pub(crate) fn updates(&self) -> impl Stream<Item = SpotifyToken> + Send + Unpin + 'static {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub(crate) fn set_lifecycle(&self, lifecycle: Lifecycle) {
+        *self.lifecycle.lock().unwrap() = lifecycle;
+    }
+
+    pub(crate) fn shutdown(&self) {
+        self.set_lifecycle(Lifecycle::ShutDown);
+        self.stop_tasks();
+        self.subscribers.lock().unwrap().clear();
+    }
+
+    fn stop_tasks(&self) {
+        // Tasks which already stopped have hung up.
+        for stop in self.tasks.lock().unwrap().drain(..) {
+            let _ = stop.send(());
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for ManagerCore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagerCore")
+            .field("store", &self.store)
+            .field("key", &self.key)
+            .field("lock", &self.lock)
+            .field("margin", &self.margin)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Drop for ManagerCore<S> {
+    fn drop(&mut self) {
+        self.stop_tasks();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{MockHttpClient, MockResponse},
+        MemoryTokenStore,
+    };
+    use async_std::stream::StreamExt;
+
+    #[async_std::test]
+    async fn test_token_manager_provides_access_tokens() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+        let manager = TokenManager::new(app, &http, MemoryTokenStore::default(), "alice");
+        let provider: &dyn AccessTokenProvider = &manager;
+
+        let err = provider.access_token().await.unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+
+        manager
+            .save(&SpotifyToken::fixture().expires_at(1_600_000_000).build())
+            .unwrap();
+        assert_eq!(provider.access_token().await.unwrap(), "NgCXRL");
+        assert_eq!(provider.access_token().await.unwrap(), "NgCXRL");
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_token_manager_refreshes_within_margin() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::token("NgCXRL", None, "streaming"))
+            .with(MockResponse::status(503, "Service Unavailable"));
+        let app = AppClient::new("id", "secret");
+        let clock = Arc::new(crate::MockClock::default());
+        let manager = TokenManager::new(app, &http, MemoryTokenStore::default(), "alice")
+            .with_refresh_margin(Duration::from_secs(60))
+            .with_refresh_failure_policy(RefreshFailurePolicy::ServeStale)
+            .with_clock(clock.clone());
+        let minutes = |x: u64| Duration::from_secs(60 * x);
+
+        manager
+            .save(&SpotifyToken::fixture().expires_in(30).build_with(&*clock))
+            .unwrap();
+        let token = manager.get_valid_token(minutes(5)).await.unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(token.remaining(), minutes(60));
+
+        // The failed refresh serves the stored token, which expires too soon.
+        let err = manager.get_valid_token(minutes(61)).await.unwrap_err();
+        assert!(matches!(err, SpotifyError::TokenFailure { .. }));
+        assert_eq!(
+            manager.token().await.unwrap().unwrap().access_token,
+            "NgCXRL"
+        );
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[async_std::test]
+    async fn test_token_manager_refreshes_with_its_clock() {
+        use chrono::{TimeZone, Utc};
+
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let clock = Arc::new(crate::MockClock::new(
+            Utc.timestamp_opt(1_600_000_000, 0).unwrap(),
+        ));
+        let app = AppClient::new("id", "secret");
+        let manager = TokenManager::new(app, &http, MemoryTokenStore::default(), "alice")
+            .with_clock(clock.clone());
+
+        manager
+            .save(&SpotifyToken::fixture().expires_at(1_599_999_000).build())
+            .unwrap();
+        let token = manager.token().await.unwrap().unwrap();
+        assert_eq!(token.access_token, "NgCXRL");
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+    }
+
+    #[cfg(feature = "backoff")]
+    #[async_std::test]
+    async fn test_token_manager_retries_with_backoff() {
+        let http = MockHttpClient::new()
+            .with(MockResponse::status(503, "Service Unavailable"))
+            .with(MockResponse::token("NgCXRL", None, "streaming"));
+        let policy = backoff::ExponentialBackoffBuilder::new()
+            .with_initial_interval(Duration::from_millis(1))
+            .with_max_elapsed_time(Some(Duration::from_millis(50)))
+            .build();
+        let app = AppClient::new("id", "secret");
+        let manager = TokenManager::new(app, &http, MemoryTokenStore::default(), "alice")
+            .with_refresh_failure_policy(RefreshFailurePolicy::retry(policy));
+
+        manager
+            .save(&SpotifyToken::fixture().expired().build())
+            .unwrap();
+        assert_eq!(
+            manager.token().await.unwrap().unwrap().access_token,
+            "NgCXRL"
+        );
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[async_std::test]
+    async fn test_token_manager_lifecycle() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRL", None, "streaming"));
+        let app = AppClient::new("id", "secret");
+        let manager = TokenManager::new(app, &http, MemoryTokenStore::default(), "alice");
+        let mut updates = manager.updates();
+
+        manager
+            .save(
+                &SpotifyToken::fixture()
+                    .access_token("NgCXRK")
+                    .expired()
+                    .build(),
+            )
+            .unwrap();
+
+        manager.pause_refresh();
+        assert_eq!(
+            manager.token().await.unwrap().unwrap().access_token,
+            "NgCXRK"
+        );
+        assert!(http.requests().is_empty());
+
+        manager.start();
+        assert_eq!(
+            manager.token().await.unwrap().unwrap().access_token,
+            "NgCXRL"
+        );
+
+        manager.shutdown();
+        assert!(matches!(
+            manager.token().await,
+            Err(SpotifyError::TokenFailure { .. })
+        ));
+
+        let mut access_tokens = Vec::new();
+        while let Some(token) = updates.next().await {
+            access_tokens.push(token.access_token);
+        }
+        assert_eq!(access_tokens, ["NgCXRK", "NgCXRL"]);
+    }
+
+    #[async_std::test]
+    async fn test_token_manager_waits_for_refresh_lease() {
+        let dir =
+            std::env::temp_dir().join(format!("spotify-oauth-async-lease-{}", std::process::id()));
+        let lock = FileRefreshLock::new(&dir);
+        let store = Arc::new(MemoryTokenStore::default());
+        let http = MockHttpClient::new();
+        let app = AppClient::new("id", "secret");
+        let manager =
+            TokenManager::new(app, &http, store.clone(), "alice").with_refresh_lock(lock.clone());
+        store
+            .save(
+                "alice",
+                &SpotifyToken::fixture().expires_at(1_600_000_000).build(),
+            )
+            .unwrap();
+
+        // Another process is refreshing the token.
+        let lease = lock.acquire("alice").unwrap();
+        let other = {
+            let store = store.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                store
+                    .save(
+                        "alice",
+                        &SpotifyToken::fixture().access_token("b3RoZXI").build(),
+                    )
+                    .unwrap();
+                drop(lease);
+            })
+        };
+
+        let token = manager.token().await.unwrap().unwrap();
+        other.join().unwrap();
+        assert_eq!(token.access_token, "b3RoZXI");
+        assert!(http.requests().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
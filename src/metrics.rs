@@ -0,0 +1,216 @@
+use crate::{
+    error::*, protocol, Clock, HttpClientError, HttpResponse, Interceptor, SpotifyToken,
+    SystemClock, TokenRequest,
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    Result as PrometheusResult,
+};
+use std::time::Duration;
+
+/// Prometheus metrics of the requests to the token endpoint.
+///
+/// Add it to an [`InterceptedClient`](crate::InterceptedClient) to count and time every code
+/// exchange and refresh, and register it with the registry of the service. Clones share the
+/// metrics. Exported are
+///
+/// - ``spotify_oauth_token_requests_total``: requests by ``grant_type`` and ``outcome``
+///   (``success`` or ``failure``),
+/// - ``spotify_oauth_token_request_failures_total``: failed requests by ``grant_type`` and
+///   ``kind``, the [`SpotifyError::kind_name`] of the failure,
+/// - ``spotify_oauth_token_request_duration_seconds``: a latency histogram by ``grant_type``,
+/// - ``spotify_oauth_tokens_near_expiry``: the tokens given to [`PrometheusMetrics::observe_expiry`]
+///   which expired or expire soon.
+///
+/// Only available with the ``prometheus`` feature.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{InterceptedClient, PrometheusMetrics, SurfClient};
+/// let registry = prometheus::Registry::new();
+/// let metrics = PrometheusMetrics::new();
+/// metrics.register(&registry).unwrap();
+///
+/// let http = InterceptedClient::new(SurfClient::new()).with(metrics.clone());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    requests: IntCounterVec,
+    failures: IntCounterVec,
+    duration: HistogramVec,
+    near_expiry: IntGauge,
+}
+
+impl PrometheusMetrics {
+    /// Create the metrics, not registered with any registry yet.
+    pub fn new() -> Self {
+        Self {
+            requests: IntCounterVec::new(
+                Opts::new(
+                    "spotify_oauth_token_requests_total",
+                    "Requests to the Spotify token endpoint.",
+                ),
+                &["grant_type", "outcome"],
+            )
+            .unwrap(),
+            failures: IntCounterVec::new(
+                Opts::new(
+                    "spotify_oauth_token_request_failures_total",
+                    "Failed requests to the Spotify token endpoint by error kind.",
+                ),
+                &["grant_type", "kind"],
+            )
+            .unwrap(),
+            duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "spotify_oauth_token_request_duration_seconds",
+                    "Latency of the requests to the Spotify token endpoint.",
+                ),
+                &["grant_type"],
+            )
+            .unwrap(),
+            near_expiry: IntGauge::new(
+                "spotify_oauth_tokens_near_expiry",
+                "Observed tokens which expired or expire soon.",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Register all metrics with ``registry``.
+    pub fn register(&self, registry: &Registry) -> PrometheusResult<()> {
+        registry.register(Box::new(self.requests.clone()))?;
+        registry.register(Box::new(self.failures.clone()))?;
+        registry.register(Box::new(self.duration.clone()))?;
+        registry.register(Box::new(self.near_expiry.clone()))
+    }
+
+    /// Set the near expiry gauge to the number of ``tokens`` which expired or expire ``within``
+    /// the given time, e.g. the tokens of all accounts a service manages.
+    pub fn observe_expiry<'a>(
+        &self,
+        tokens: impl IntoIterator<Item = &'a SpotifyToken>,
+        within: Duration,
+    ) {
+        self.observe_expiry_with(tokens, within, &SystemClock)
+    }
+
+    /// Like [`PrometheusMetrics::observe_expiry`], according to the given clock.
+    pub fn observe_expiry_with<'a>(
+        &self,
+        tokens: impl IntoIterator<Item = &'a SpotifyToken>,
+        within: Duration,
+        clock: &dyn Clock,
+    ) {
+        let count = tokens
+            .into_iter()
+            .filter(|x| x.remaining_with(clock).is_none_or(|x| x <= within))
+            .count();
+
+        self.near_expiry.set(count as i64);
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interceptor for PrometheusMetrics {
+    fn after_response(
+        &self,
+        request: &TokenRequest,
+        result: &mut Result<HttpResponse, HttpClientError>,
+        elapsed: Duration,
+    ) {
+        let grant_type = request.form_field("grant_type").unwrap_or_default();
+
+        let failure = match result {
+            Err(_) => Some("transport"),
+            Ok(response) => match protocol::parse_token_response(response) {
+                Ok(_) => None,
+                // Mirrors ``protocol::finish_refresh``.
+                Err(SpotifyError::Protocol { error, .. })
+                    if error == "invalid_grant" && grant_type == "refresh_token" =>
+                {
+                    Some("needs_reauthorization")
+                }
+                Err(err) => Some(err.kind_name()),
+            },
+        };
+
+        let outcome = match failure {
+            None => "success",
+            Some(kind) => {
+                self.failures.with_label_values(&[grant_type, kind]).inc();
+                "failure"
+            }
+        };
+
+        self.requests
+            .with_label_values(&[grant_type, outcome])
+            .inc();
+        self.duration
+            .with_label_values(&[grant_type])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{MockHttpClient, MockResponse},
+        AppClient, InterceptedClient, MockClock,
+    };
+    use chrono::{TimeZone, Utc};
+    use prometheus::{Encoder, TextEncoder};
+
+    #[async_std::test]
+    async fn test_metrics_count_refreshes_by_outcome() {
+        let metrics = PrometheusMetrics::new();
+        let registry = Registry::new();
+        metrics.register(&registry).unwrap();
+
+        let http = InterceptedClient::new(
+            MockHttpClient::new()
+                .with(MockResponse::token("NgCXRK", None, "streaming"))
+                .with(MockResponse::oauth_error(
+                    "invalid_grant",
+                    "Refresh token revoked",
+                ))
+                .with(MockResponse::transport_error("dns failure")),
+        )
+        .with(metrics.clone());
+        let app = AppClient::new("id", "secret");
+        for _ in 0..3 {
+            let _ = app.refresh(&http, "NgAagA").await;
+        }
+
+        let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let tokens = [
+            SpotifyToken::fixture().expires_in(60).build_with(&clock),
+            SpotifyToken::fixture().expires_in(3600).build_with(&clock),
+            SpotifyToken::fixture().expires_at(1_500_000_000).build(),
+        ];
+        metrics.observe_expiry_with(&tokens, Duration::from_secs(300), &clock);
+
+        let mut output = Vec::new();
+        TextEncoder::new()
+            .encode(&registry.gather(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        for line in [
+            r#"spotify_oauth_token_requests_total{grant_type="refresh_token",outcome="success"} 1"#,
+            r#"spotify_oauth_token_requests_total{grant_type="refresh_token",outcome="failure"} 2"#,
+            r#"spotify_oauth_token_request_failures_total{grant_type="refresh_token",kind="needs_reauthorization"} 1"#,
+            r#"spotify_oauth_token_request_failures_total{grant_type="refresh_token",kind="transport"} 1"#,
+            r#"spotify_oauth_token_request_duration_seconds_count{grant_type="refresh_token"} 3"#,
+            "spotify_oauth_tokens_near_expiry 2",
+        ] {
+            assert!(output.contains(line), "{} missing in\n{}", line, output);
+        }
+    }
+}
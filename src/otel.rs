@@ -0,0 +1,118 @@
+//! OpenTelemetry spans for the requests sent to the token endpoint.
+
+use crate::{error::*, util::redact, TokenRequest};
+use opentelemetry::{
+    global,
+    trace::{SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use url::Url;
+
+/// The name of the instrumentation scope of the spans.
+const TRACER: &str = "spotify-oauth";
+
+/// Start an HTTP client span for ``request`` as child of the current context.
+pub(crate) fn start(request: &TokenRequest) -> Context {
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", "POST"),
+        KeyValue::new("url.full", request.url.clone()),
+    ];
+
+    if let Ok(url) = Url::parse(&request.url) {
+        if let Some(host) = url.host_str() {
+            attributes.push(KeyValue::new("server.address", host.to_string()));
+        }
+        if let Some(port) = url.port_or_known_default() {
+            attributes.push(KeyValue::new("server.port", i64::from(port)));
+        }
+    }
+
+    if let Some(grant_type) = request.form_field("grant_type") {
+        attributes.push(KeyValue::new(
+            "spotify_oauth.grant_type",
+            grant_type.to_string(),
+        ));
+    }
+
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder("POST")
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start(&tracer);
+
+    Context::current_with_span(span)
+}
+
+/// Record the response status and the outcome of the request, then end the span.
+pub(crate) fn finish<T>(cx: &Context, status: Option<u16>, result: &SpotifyResult<T>) {
+    let span = cx.span();
+
+    if let Some(status) = status {
+        span.set_attribute(KeyValue::new(
+            "http.response.status_code",
+            i64::from(status),
+        ));
+    }
+
+    if let Err(err) = result {
+        // The semantic conventions use the status code for failed responses.
+        let error_type = match status {
+            Some(status) if status >= 400 => status.to_string(),
+            _ => err.kind_name().to_string(),
+        };
+        span.set_attribute(KeyValue::new("error.type", error_type));
+        span.set_status(Status::error(redact(&err.to_string())));
+    }
+
+    span.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{MockHttpClient, MockResponse},
+        AppClient, Endpoints,
+    };
+    use opentelemetry::{global, trace::SpanKind, KeyValue};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    #[async_std::test]
+    async fn test_spans_carry_http_attributes() {
+        let exporter = InMemorySpanExporter::default();
+        global::set_tracer_provider(
+            SdkTracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build(),
+        );
+
+        let http = MockHttpClient::new().with(MockResponse::oauth_error(
+            "invalid_grant",
+            "Refresh token revoked",
+        ));
+        let app = AppClient::new("id", "secret")
+            .with_endpoints(Endpoints::from_base_url("https://otel.test:8443").unwrap());
+        app.refresh(&http, "NgAagA").await.unwrap_err();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = spans
+            .iter()
+            .find(|x| {
+                x.attributes
+                    .contains(&KeyValue::new("server.address", "otel.test"))
+            })
+            .unwrap();
+        assert_eq!(span.name, "POST");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        for attribute in [
+            KeyValue::new("http.request.method", "POST"),
+            KeyValue::new("server.port", 8443),
+            KeyValue::new("http.response.status_code", 400),
+            KeyValue::new("error.type", "400"),
+            KeyValue::new("spotify_oauth.grant_type", "refresh_token"),
+        ] {
+            assert!(span.attributes.contains(&attribute), "{:?}", attribute);
+        }
+        assert!(!format!("{:?}", span).contains("NgAagA"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Server side storage of authorizations waiting for their callback.
+
+use crate::error::*;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A place to keep the code verifier of an authorization until its callback arrives, by state.
+///
+/// Web apps insert the state and the PKCE code verifier before redirecting the user to Spotify and
+/// take them when the callback arrives. Confidential clients without PKCE store an empty verifier.
+/// An authorization is taken once, so replayed and stale callbacks find nothing.
+pub trait PendingAuthStore: Send + Sync {
+    /// Keep the code verifier of the authorization with the given state for ``ttl``.
+    fn insert(&self, state: &str, code_verifier: &str, ttl: Duration) -> SpotifyResult<()>;
+
+    /// Remove and return the code verifier of the given state, ``None`` if it is unknown, was
+    /// already taken or expired.
+    fn take(&self, state: &str) -> SpotifyResult<Option<String>>;
+}
+
+/// A [`PendingAuthStore`] keeping the authorizations in memory, for apps running on a single
+/// instance.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{MemoryPendingAuthStore, PendingAuthStore};
+/// # use std::time::Duration;
+/// let store = MemoryPendingAuthStore::default();
+/// store.insert("Ys6Lq0R2", "dBjftJeZ4CVP", Duration::from_secs(600)).unwrap();
+///
+/// # assert_eq!(store.take("Ys6Lq0R2").unwrap().as_deref(), Some("dBjftJeZ4CVP"));
+/// # assert_eq!(store.take("Ys6Lq0R2").unwrap(), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryPendingAuthStore {
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl PendingAuthStore for MemoryPendingAuthStore {
+    fn insert(&self, state: &str, code_verifier: &str, ttl: Duration) -> SpotifyResult<()> {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, x| x.1 > now);
+        pending.insert(state.to_string(), (code_verifier.to_string(), now + ttl));
+
+        Ok(())
+    }
+
+    fn take(&self, state: &str) -> SpotifyResult<Option<String>> {
+        let pending = self.pending.lock().unwrap().remove(state);
+
+        Ok(pending.filter(|x| x.1 > Instant::now()).map(|x| x.0))
+    }
+}
+
+/// A [`PendingAuthStore`] in Redis, so the callback can be handled by any instance of a
+/// horizontally scaled app.
+///
+/// Authorizations are stored under ``spotify_oauth:pending:<state>`` with a Redis TTL and taken
+/// atomically with ``GETDEL``, which needs Redis 6.2 or later. The connection is shared by all
+/// calls.
+///
+/// Only available with the ``redis`` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{PendingAuthStore, RedisPendingAuthStore};
+/// # use std::time::Duration;
+/// let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let store = RedisPendingAuthStore::new(client.get_connection().unwrap());
+///
+/// store.insert("Ys6Lq0R2", "dBjftJeZ4CVP", Duration::from_secs(600)).unwrap();
+/// ```
+#[cfg(feature = "redis")]
+pub struct RedisPendingAuthStore<C> {
+    connection: Mutex<C>,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl<C: redis::ConnectionLike + Send> RedisPendingAuthStore<C> {
+    /// Store the authorizations over the given connection.
+    pub fn new(connection: C) -> Self {
+        Self {
+            connection: Mutex::new(connection),
+            prefix: "spotify_oauth:pending:".to_string(),
+        }
+    }
+
+    /// Prefix the keys with ``prefix`` instead of ``spotify_oauth:pending:``, e.g. to separate
+    /// the apps sharing a Redis.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, state: &str) -> String {
+        format!("{}{}", self.prefix, state)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl<C: redis::ConnectionLike + Send> PendingAuthStore for RedisPendingAuthStore<C> {
+    fn insert(&self, state: &str, code_verifier: &str, ttl: Duration) -> SpotifyResult<()> {
+        // Redis rejects a TTL of zero.
+        let millis = ttl.as_millis().clamp(1, u64::MAX.into()) as u64;
+
+        redis::cmd("SET")
+            .arg(self.key(state))
+            .arg(code_verifier)
+            .arg("PX")
+            .arg(millis)
+            .query(&mut *self.connection.lock().unwrap())
+            .map_err(store_failure)
+    }
+
+    fn take(&self, state: &str) -> SpotifyResult<Option<String>> {
+        redis::cmd("GETDEL")
+            .arg(self.key(state))
+            .query(&mut *self.connection.lock().unwrap())
+            .map_err(store_failure)
+    }
+}
+
+#[cfg(feature = "redis")]
+fn store_failure(err: redis::RedisError) -> SpotifyError {
+    SpotifyError::StoreFailure {
+        context: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_takes_once_until_expiry() {
+        let store = MemoryPendingAuthStore::default();
+        store
+            .insert("alice", "YWxpY2U", Duration::from_secs(600))
+            .unwrap();
+        store.insert("bob", "Ym9i", Duration::ZERO).unwrap();
+
+        assert_eq!(store.take("alice").unwrap().as_deref(), Some("YWxpY2U"));
+        assert_eq!(store.take("alice").unwrap(), None);
+        assert_eq!(store.take("bob").unwrap(), None);
+        assert_eq!(store.take("eve").unwrap(), None);
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_store_commands() {
+        use redis_test::{MockCmd, MockRedisConnection};
+
+        let connection = MockRedisConnection::new(vec![
+            MockCmd::new(
+                redis::cmd("SET")
+                    .arg("app:alice")
+                    .arg("YWxpY2U")
+                    .arg("PX")
+                    .arg(600_000),
+                Ok(redis::Value::Okay),
+            ),
+            MockCmd::new(redis::cmd("GETDEL").arg("app:alice"), Ok("YWxpY2U")),
+            MockCmd::new(redis::cmd("GETDEL").arg("app:alice"), Ok(redis::Value::Nil)),
+            MockCmd::new(
+                redis::cmd("GETDEL").arg("app:bob"),
+                Err::<redis::Value, _>(redis::RedisError::from((
+                    redis::ErrorKind::IoError,
+                    "connection refused",
+                ))),
+            ),
+        ]);
+        let store = RedisPendingAuthStore::new(connection).with_prefix("app:");
+
+        store
+            .insert("alice", "YWxpY2U", Duration::from_secs(600))
+            .unwrap();
+        assert_eq!(store.take("alice").unwrap().as_deref(), Some("YWxpY2U"));
+        assert_eq!(store.take("alice").unwrap(), None);
+        assert!(matches!(
+            store.take("bob"),
+            Err(SpotifyError::StoreFailure { .. })
+        ));
+    }
+}
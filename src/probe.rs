@@ -0,0 +1,118 @@
+//! Checking whether the Web API accepts a token.
+
+use crate::{error::*, protocol::snippet, Header, HttpClient, SpotifyToken};
+use serde::Deserialize;
+
+/// The endpoint of the current user's profile, which needs no scope.
+const SPOTIFY_ME_URL: &str = "https://api.spotify.com/v1/me";
+
+/// Whether the Web API accepts a token, as probed by [`SpotifyToken::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStatus {
+    /// The access token is accepted.
+    Valid,
+    /// The access token expired, refresh it.
+    Expired,
+    /// The access token is invalid, e.g. because the user removed the access of the app.
+    Revoked,
+    /// The access token is valid but not allowed to read the profile, e.g. because the user is
+    /// not registered for an app in development mode.
+    InsufficientScope,
+}
+
+/// The error object of a Web API response.
+#[derive(Deserialize)]
+struct ApiError {
+    error: ApiErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ApiErrorDetails {
+    message: String,
+}
+
+impl SpotifyToken {
+    /// Check whether the Web API accepts the access token, with a request for the profile of the
+    /// current user.
+    ///
+    /// Useful after loading a cached token of unknown freshness, the expiry of a cached token
+    /// does not tell whether it was revoked. The HTTP client must support
+    /// [`HttpClient::get`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{SpotifyToken, SurfClient, TokenStatus};
+    /// # async fn check(token: SpotifyToken) -> spotify_oauth::SpotifyResult<()> {
+    /// match token.validate(&SurfClient::new()).await? {
+    ///     TokenStatus::Valid => {}
+    ///     TokenStatus::Expired => { /* refresh */ }
+    ///     TokenStatus::Revoked | TokenStatus::InsufficientScope => { /* log in again */ }
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn validate(&self, http: &(impl HttpClient + ?Sized)) -> SpotifyResult<TokenStatus> {
+        let authorization = Header::new("Authorization", format!("Bearer {}", self.access_token));
+        let response = http
+            .get(SPOTIFY_ME_URL, &[authorization])
+            .await
+            .map_err(|err| SpotifyError::Transport {
+                context: err.to_string(),
+            })?;
+
+        match response.status {
+            200..=299 => Ok(TokenStatus::Valid),
+            401 => {
+                let message = serde_json::from_str::<ApiError>(&response.body)
+                    .map(|x| x.error.message.to_lowercase())
+                    .unwrap_or_default();
+                if message.contains("expired") {
+                    Ok(TokenStatus::Expired)
+                } else {
+                    Ok(TokenStatus::Revoked)
+                }
+            }
+            403 => Ok(TokenStatus::InsufficientScope),
+            status => Err(SpotifyError::UnexpectedResponse {
+                status,
+                snippet: snippet(&response.body),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHttpClient, MockResponse};
+    use serde_json::json;
+
+    #[async_std::test]
+    async fn test_validate_token() {
+        let api_error = |status, message| {
+            MockResponse::json(
+                status,
+                json!({"error": {"status": status, "message": message}}),
+            )
+        };
+        let http = MockHttpClient::new()
+            .with(MockResponse::json(200, json!({"id": "alice"})))
+            .with(api_error(401, "The access token expired"))
+            .with(api_error(401, "Invalid access token"))
+            .with(api_error(403, "Insufficient client scope"))
+            .with(MockResponse::status(503, "Service Unavailable"));
+        let token = SpotifyToken::fixture().build();
+
+        assert_eq!(token.validate(&http).await.unwrap(), TokenStatus::Valid);
+        assert_eq!(token.validate(&http).await.unwrap(), TokenStatus::Expired);
+        assert_eq!(token.validate(&http).await.unwrap(), TokenStatus::Revoked);
+        assert_eq!(
+            token.validate(&http).await.unwrap(),
+            TokenStatus::InsufficientScope
+        );
+        assert!(matches!(
+            token.validate(&http).await,
+            Err(SpotifyError::UnexpectedResponse { status: 503, .. })
+        ));
+    }
+}
@@ -0,0 +1,371 @@
+//! The I/O free core of the authorization code flow.
+//!
+//! Every step of the flow is a pure function from plain inputs to plain outputs, the caller moves
+//! the bytes: [`SpotifyAuth::authorize_url`](crate::SpotifyAuth::authorize_url) builds the URL to
+//! send the user to, [`SpotifyCallback`](crate::SpotifyCallback) parses the redirect, the
+//! ``*_request`` functions build the [`TokenRequest`] to POST and the ``finish_*`` functions
+//! interpret the [`HttpResponse`] received for it.
+//!
+//! [`AppClient`] drives these functions with an async [`HttpClient`](crate::HttpClient), use them
+//! directly to run the flow on a custom event loop, or let [`AuthFlow`](crate::AuthFlow) sequence
+//! them.
+//!
+//! # Example
+//!
+//! ```
+//! # use spotify_oauth::{protocol, AppClient, HttpResponse, SystemClock};
+//! # use url::Url;
+//! let app = AppClient::new("id", "secret");
+//! let redirect_uri = Url::parse("http://localhost:8000/callback").unwrap();
+//!
+//! let request = protocol::exchange_request(&app, "NApCCgBkWtQ", &redirect_uri);
+//! // Send request.url, request.headers and request.body() with any HTTP stack ...
+//! let response = HttpResponse {
+//!     status: 200,
+//!     body: r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "streaming", "expires_in": 3600, "refresh_token": "NgAagA"}"#.into(),
+//! };
+//! let token = protocol::finish_exchange(&response, &SystemClock).unwrap();
+//! # assert_eq!(token.access_token, "NgCXRK");
+//! ```
+
+use crate::{
+    error::*, AppClient, Clock, Endpoints, FormData, HttpResponse, SpotifyCallback, SpotifyScope,
+    SpotifyToken, TokenRequest, TokenResponse,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::str::FromStr;
+use url::Url;
+
+/// The error body returned by the Spotify Accounts service.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Build the request exchanging an authorization code for a token.
+///
+/// ``redirect_uri`` must be the redirect URI the authorization was requested with.
+pub fn exchange_request(app: &AppClient, code: &str, redirect_uri: &Url) -> TokenRequest {
+    let payload: FormData = vec![
+        ("grant_type".to_owned(), "authorization_code".to_owned()),
+        ("code".to_owned(), code.to_owned()),
+        ("redirect_uri".to_owned(), redirect_uri.to_string()),
+    ];
+
+    app_request(app, payload)
+}
+
+/// Build the request exchanging an authorization code of the PKCE flow for a token.
+///
+/// The request carries the client ID and the ``code_verifier`` the code challenge of the
+/// authorization was derived from instead of client credentials, see
+/// [`SpotifyAuth::authorize_url_with_pkce`](crate::SpotifyAuth::authorize_url_with_pkce).
+pub fn pkce_exchange_request(
+    endpoints: &Endpoints,
+    client_id: &str,
+    code: &str,
+    redirect_uri: &Url,
+    code_verifier: &str,
+) -> TokenRequest {
+    let form: FormData = vec![
+        ("grant_type".to_owned(), "authorization_code".to_owned()),
+        ("code".to_owned(), code.to_owned()),
+        ("redirect_uri".to_owned(), redirect_uri.to_string()),
+        ("client_id".to_owned(), client_id.to_owned()),
+        ("code_verifier".to_owned(), code_verifier.to_owned()),
+    ];
+
+    TokenRequest::with_form(endpoints.token_url.to_string(), Vec::new(), form)
+}
+
+/// Build the request for a new access token using a refresh token.
+pub fn refresh_request(app: &AppClient, refresh_token: &str) -> TokenRequest {
+    let payload: FormData = vec![
+        ("grant_type".to_owned(), "refresh_token".to_owned()),
+        ("refresh_token".to_owned(), refresh_token.to_owned()),
+    ];
+
+    app_request(app, payload)
+}
+
+/// A request authenticated with the credentials of ``app``, carrying its correlation ID.
+fn app_request(app: &AppClient, payload: FormData) -> TokenRequest {
+    let mut request =
+        TokenRequest::new(&app.endpoints, &app.client_id, &app.client_secret, payload);
+    request.headers.extend(app.correlation_id.clone());
+
+    request
+}
+
+/// How strictly responses and callbacks are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Accept unknown fields and scopes and an ``expires_in`` given as string or float.
+    #[default]
+    Tolerant,
+    /// Fail fast on anything unexpected, see [`check_response_strict`] and
+    /// [`check_callback_strict`].
+    Strict,
+}
+
+/// The fields of a token response known to this library.
+const TOKEN_FIELDS: [&str; 5] = [
+    "access_token",
+    "token_type",
+    "scope",
+    "expires_in",
+    "refresh_token",
+];
+
+/// The number of characters of a body kept in [`SpotifyError::UnexpectedResponse`].
+const SNIPPET_LENGTH: usize = 100;
+
+/// Interpret a response of the token endpoint.
+///
+/// Non-success responses are mapped to [`SpotifyError::Protocol`], with the OAuth error code if
+/// the body contains one and ``unexpected_response`` otherwise. Bodies which are no JSON at all,
+/// e.g. the HTML page of a proxy or of a maintenance window, yield
+/// [`SpotifyError::UnexpectedResponse`] with the start of the body.
+pub fn parse_token_response(response: &HttpResponse) -> SpotifyResult<TokenResponse> {
+    let body = response.body.trim_start();
+    if !body.is_empty() && !body.starts_with(['{', '[']) {
+        return Err(SpotifyError::UnexpectedResponse {
+            status: response.status,
+            snippet: snippet(body),
+        });
+    }
+
+    if response.is_success() {
+        return serde_json::from_str(&response.body).context(Deserialization);
+    }
+
+    if let Ok(body) = serde_json::from_str::<ErrorResponse>(&response.body) {
+        return Err(SpotifyError::Protocol {
+            status: Some(response.status),
+            error: body.error,
+            description: body.error_description,
+        });
+    }
+
+    Err(SpotifyError::Protocol {
+        status: Some(response.status),
+        error: "unexpected_response".to_string(),
+        description: None,
+    })
+}
+
+/// Interpret the response to an [`exchange_request`], stamping the expiry with the given clock.
+pub fn finish_exchange(response: &HttpResponse, clock: &dyn Clock) -> SpotifyResult<SpotifyToken> {
+    let mut token = parse_token_response(response)?.into_token(None);
+    token.update_expires_at(clock);
+
+    Ok(token)
+}
+
+/// Interpret the response to a [`refresh_request`], stamping the expiry with the given clock.
+///
+/// The refresh token the request was made with is kept if the response contains none. A rejected
+/// refresh token yields [`SpotifyError::NeedsReauthorization`].
+pub fn finish_refresh(
+    response: &HttpResponse,
+    refresh_token: &str,
+    clock: &dyn Clock,
+) -> SpotifyResult<SpotifyToken> {
+    let response = match parse_token_response(response) {
+        Err(SpotifyError::Protocol {
+            error, description, ..
+        }) if error == "invalid_grant" => {
+            return Err(SpotifyError::NeedsReauthorization { description })
+        }
+        x => x?,
+    };
+
+    let mut token = response.into_token(Some(refresh_token));
+    token.update_expires_at(clock);
+
+    Ok(token)
+}
+
+/// Check a response of the token endpoint before interpreting it in [`ParseMode::Strict`].
+///
+/// Successful responses must not contain fields or scopes unknown to this library and must give
+/// ``expires_in`` as integer. Failed responses are left to the ``finish_*`` functions.
+pub fn check_response_strict(response: &HttpResponse) -> SpotifyResult<()> {
+    if !response.is_success() {
+        return Ok(());
+    }
+
+    let body: Value = serde_json::from_str(&response.body).context(Deserialization)?;
+    let body = body.as_object().ok_or(SpotifyError::TokenFailure {
+        context: "The token response is no JSON object.",
+    })?;
+
+    if body.keys().any(|x| !TOKEN_FIELDS.contains(&x.as_str())) {
+        return Err(SpotifyError::TokenFailure {
+            context: "The token response contains unknown fields.",
+        });
+    }
+
+    if !body.get("expires_in").is_some_and(Value::is_u64) {
+        return Err(SpotifyError::TokenFailure {
+            context: "The expires_in field of the token response is no integer.",
+        });
+    }
+
+    let scope = body
+        .get("scope")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    if scope
+        .split_whitespace()
+        .any(|x| !SpotifyScope::from_str(x).is_ok_and(|x| x.is_known()))
+    {
+        return Err(SpotifyError::TokenFailure {
+            context: "The token response contains unknown scopes.",
+        });
+    }
+
+    Ok(())
+}
+
+/// Check a callback before exchanging its code in [`ParseMode::Strict`]: the state must not be
+/// empty.
+pub fn check_callback_strict(callback: &SpotifyCallback) -> SpotifyResult<()> {
+    if callback.state().is_empty() {
+        return Err(SpotifyError::CallbackFailure {
+            context: "The state of the callback is empty.",
+        });
+    }
+
+    Ok(())
+}
+
+/// The start of ``body`` on a single line, for error messages.
+pub(crate) fn snippet(body: &str) -> String {
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match body.char_indices().nth(SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+    use chrono::{TimeZone, Utc};
+
+    fn response(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_refresh_round_trip() {
+        let app = AppClient::new("id", "secret");
+        let request = refresh_request(&app, "NgAagA");
+        assert_eq!(request.header("authorization"), Some("Basic aWQ6c2VjcmV0"));
+        assert_eq!(
+            request.body(),
+            "grant_type=refresh_token&refresh_token=NgAagA"
+        );
+
+        let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let token = finish_refresh(
+            &response(
+                200,
+                r#"{"access_token": "NgCXRK", "token_type": "Bearer", "expires_in": 3600}"#,
+            ),
+            "NgAagA",
+            &clock,
+        )
+        .unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+    }
+
+    #[test]
+    fn test_pkce_exchange_request() {
+        let redirect_uri = Url::parse("http://localhost:8000/callback").unwrap();
+        let request = pkce_exchange_request(
+            &Endpoints::default(),
+            "id",
+            "NApCCgBkWtQ",
+            &redirect_uri,
+            "dBjftJeZ4CVP",
+        );
+
+        assert_eq!(request.header("authorization"), None);
+        assert_eq!(request.form_field("client_id"), Some("id"));
+        assert_eq!(request.form_field("code_verifier"), Some("dBjftJeZ4CVP"));
+    }
+
+    #[test]
+    fn test_parse_error_responses() {
+        let err = finish_refresh(
+            &response(400, r#"{"error": "invalid_grant"}"#),
+            "NgAagA",
+            &MockClock::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+
+        let err = parse_token_response(&response(502, r#"{"status": 502}"#)).unwrap_err();
+        assert!(matches!(
+            err,
+            SpotifyError::Protocol { status: Some(502), ref error, .. } if error == "unexpected_response"
+        ));
+
+        let err = parse_token_response(&response(502, "Bad Gateway")).unwrap_err();
+        assert!(matches!(
+            err,
+            SpotifyError::UnexpectedResponse { status: 502, ref snippet } if snippet == "Bad Gateway"
+        ));
+    }
+
+    #[test]
+    fn test_check_response_strict() {
+        let valid = r#"{"access_token": "a", "token_type": "Bearer", "scope": "streaming ugc-image-upload", "expires_in": 3600}"#;
+        assert!(check_response_strict(&response(200, valid)).is_ok());
+        assert!(check_response_strict(&response(400, r#"{"error": "invalid_grant"}"#)).is_ok());
+
+        for body in [
+            r#"{"access_token": "a", "token_type": "Bearer", "expires_in": 3600, "id_token": "x"}"#,
+            r#"{"access_token": "a", "token_type": "Bearer", "expires_in": "3600"}"#,
+            r#"{"access_token": "a", "token_type": "Bearer", "scope": "streaming app-remote-future", "expires_in": 3600}"#,
+        ] {
+            let err = check_response_strict(&response(200, body)).unwrap_err();
+            assert!(matches!(err, SpotifyError::TokenFailure { .. }), "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_non_json_bodies() {
+        let page = format!(
+            "<!DOCTYPE html>\n<html>\n  <title>Maintenance</title>\n{}</html>",
+            "<p>We'll be back soon.</p>".repeat(10)
+        );
+        let err = finish_exchange(&response(200, &page), &MockClock::default()).unwrap_err();
+        let SpotifyError::UnexpectedResponse { status, snippet } = &err else {
+            panic!("unexpected error: {:?}", err);
+        };
+        assert_eq!(*status, 200);
+        assert!(snippet.starts_with("<!DOCTYPE html> <html> <title>Maintenance</title>"));
+        assert_eq!(snippet.chars().count(), SNIPPET_LENGTH + 1);
+
+        let err = parse_token_response(&response(429, "")).unwrap_err();
+        assert!(matches!(
+            err,
+            SpotifyError::Protocol {
+                status: Some(429),
+                ..
+            }
+        ));
+    }
+}
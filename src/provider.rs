@@ -0,0 +1,55 @@
+//! The integration point for Spotify Web API clients.
+
+use crate::error::*;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A source of valid access tokens, refreshed as needed.
+///
+/// Web API client crates accept an ``AccessTokenProvider`` instead of a concrete token type or
+/// manager and call [`AccessTokenProvider::access_token`] before every request, e.g. to set the
+/// ``Authorization: Bearer`` header. Implemented by [`TokenManager`](crate::TokenManager) and by
+/// ``String`` for a fixed access token, e.g. in tests.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{AccessTokenProvider, SpotifyResult};
+/// async fn authorization(tokens: &impl AccessTokenProvider) -> SpotifyResult<String> {
+///     Ok(format!("Bearer {}", tokens.access_token().await?))
+/// }
+///
+/// # async_std::task::block_on(async {
+/// let header = authorization(&"NgCXRK".to_string()).await.unwrap();
+/// # assert_eq!(header, "Bearer NgCXRK");
+/// # });
+/// ```
+#[async_trait]
+pub trait AccessTokenProvider: Send + Sync {
+    /// A valid access token.
+    ///
+    /// Fails with [`SpotifyError::NeedsReauthorization`] if no token is available and the user
+    /// has to log in again.
+    async fn access_token(&self) -> SpotifyResult<String>;
+}
+
+#[async_trait]
+impl AccessTokenProvider for String {
+    async fn access_token(&self) -> SpotifyResult<String> {
+        Ok(self.clone())
+    }
+}
+
+#[async_trait]
+impl<P: AccessTokenProvider + ?Sized> AccessTokenProvider for &P {
+    async fn access_token(&self) -> SpotifyResult<String> {
+        (**self).access_token().await
+    }
+}
+
+#[async_trait]
+impl<P: AccessTokenProvider + ?Sized> AccessTokenProvider for Arc<P> {
+    async fn access_token(&self) -> SpotifyResult<String> {
+        (**self).access_token().await
+    }
+}
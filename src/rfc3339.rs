@@ -0,0 +1,87 @@
+//! Serde functions writing ``expires_at`` as RFC 3339 date instead of a unix timestamp.
+//!
+//! Use them with ``#[serde(with = "spotify_oauth::rfc3339")]`` on an ``Option<i64>`` field of your
+//! own cache or log types, dates like ``2020-09-13T12:26:40Z`` are easier to read for humans and
+//! external tools. Unix timestamps are accepted when reading, so existing files keep working.
+//!
+//! # Example
+//!
+//! ```
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Session {
+//!     #[serde(with = "spotify_oauth::rfc3339")]
+//!     expires_at: Option<i64>,
+//! }
+//!
+//! let json = serde_json::to_string(&Session { expires_at: Some(1_600_000_000) }).unwrap();
+//! # assert_eq!(json, r#"{"expires_at":"2020-09-13T12:26:40Z"}"#);
+//! ```
+
+use chrono::{DateTime, SecondsFormat};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use serde_json::Value;
+
+/// Write the timestamp as RFC 3339 date in UTC, ``None`` as null.
+pub fn serialize<S: Serializer>(expires_at: &Option<i64>, ser: S) -> Result<S::Ok, S::Error> {
+    match expires_at {
+        None => ser.serialize_none(),
+        Some(x) => {
+            let date = DateTime::from_timestamp(*x, 0).ok_or_else(|| {
+                serde::ser::Error::custom(format!("timestamp {} out of range", x))
+            })?;
+            ser.serialize_str(&date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        }
+    }
+}
+
+/// Read an RFC 3339 date or a unix timestamp, fractions of a second are dropped.
+pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<i64>, D::Error> {
+    match Value::deserialize(de)? {
+        Value::Null => Ok(None),
+        Value::Number(x) => x
+            .as_i64()
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("invalid timestamp {}", x))),
+        Value::String(x) => DateTime::parse_from_rfc3339(&x)
+            .map(|x| Some(x.timestamp()))
+            .map_err(|_| D::Error::custom(format!("invalid RFC 3339 date {}", x))),
+        x => Err(D::Error::custom(format!(
+            "invalid expiry {}, expected a date or a timestamp",
+            x
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Session {
+        #[serde(default, with = "super")]
+        expires_at: Option<i64>,
+    }
+
+    #[test]
+    fn test_rfc3339_expires_at() {
+        let session = Session {
+            expires_at: Some(1_600_000_000),
+        };
+        let text = toml::to_string(&session).unwrap();
+        assert_eq!(text, "expires_at = \"2020-09-13T12:26:40Z\"\n");
+        assert_eq!(toml::from_str::<Session>(&text).unwrap(), session);
+
+        for json in [
+            r#"{"expires_at": 1600000000}"#,
+            r#"{"expires_at": "2020-09-13T14:26:40.5+02:00"}"#,
+        ] {
+            assert_eq!(serde_json::from_str::<Session>(json).unwrap(), session);
+        }
+        assert_eq!(
+            serde_json::from_str::<Session>(r#"{"expires_at": null}"#).unwrap(),
+            Session { expires_at: None }
+        );
+        assert!(serde_json::from_str::<Session>(r#"{"expires_at": "tomorrow"}"#).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
-use strum_macros::{Display, EnumString};
+use std::fmt;
+use strum_macros::{EnumString, IntoStaticStr};
 
 /// Spotify Scopes for the API.
-/// This enum implements FromStr and ToString / Display through strum.
+/// This enum implements FromStr through strum, and ToString / Display.
 ///
 /// All the Spotify API scopes can be found [here](https://developer.spotify.com/documentation/general/guides/scopes/ "Spotify Scopes").
 ///
@@ -17,13 +18,22 @@ use strum_macros::{Display, EnumString};
 /// // It can also convert the scope back into a string.
 /// let scope = scope.to_string();
 /// # assert_eq!(scope, "streaming");
+/// // Scopes unknown to this library are kept by name.
+/// let scope = SpotifyScope::from_str("app-remote-future").unwrap();
+/// # assert_eq!(scope, SpotifyScope::Other("app-remote-future".into()));
+/// # assert_eq!(scope.to_string(), "app-remote-future");
 /// ```
-#[derive(EnumString, Serialize, Deserialize, Display, Debug, Clone, PartialEq)]
+#[derive(EnumString, IntoStaticStr, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum SpotifyScope {
+    #[strum(serialize = "ugc-image-upload")]
+    UgcImageUpload,
+
     #[strum(serialize = "user-read-recently-played")]
     UserReadRecentlyPlayed,
     #[strum(serialize = "user-top-read")]
     UserTopRead,
+    #[strum(serialize = "user-read-playback-position")]
+    UserReadPlaybackPosition,
 
     #[strum(serialize = "user-library-modify")]
     UserLibraryModify,
@@ -45,6 +55,8 @@ pub enum SpotifyScope {
     UserReadBirthDate,
     #[strum(serialize = "user-read-private")]
     UserReadPrivate,
+    #[strum(serialize = "user-personalized")]
+    UserPersonalized,
 
     #[strum(serialize = "user-read-playback-state")]
     UserReadPlaybackState,
@@ -62,4 +74,64 @@ pub enum SpotifyScope {
     UserFollowRead,
     #[strum(serialize = "user-follow-modify")]
     UserFollowModify,
+
+    #[strum(serialize = "user-soa-link")]
+    UserSoaLink,
+    #[strum(serialize = "user-soa-unlink")]
+    UserSoaUnlink,
+    #[strum(serialize = "soa-manage-entitlements")]
+    SoaManageEntitlements,
+    #[strum(serialize = "soa-manage-partner")]
+    SoaManagePartner,
+    #[strum(serialize = "soa-create-partner")]
+    SoaCreatePartner,
+
+    /// A scope unknown to this library, e.g. one Spotify added after this release, by its name.
+    ///
+    /// Converting it into a ``&'static str`` yields ``"Other"``, use [`SpotifyScope::as_str`] for
+    /// the name.
+    #[strum(default)]
+    Other(String),
+}
+
+impl SpotifyScope {
+    /// The name of the scope as used by Spotify, e.g. ``user-read-email``.
+    ///
+    /// Unlike ``to_string()`` this does not allocate.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Other(x) => x,
+            x => x.into(),
+        }
+    }
+
+    /// Whether the scope is known to this library, i.e. not [`SpotifyScope::Other`].
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Self::Other(_))
+    }
+}
+
+impl fmt::Display for SpotifyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether two lists grant the same scopes, ignoring their order and duplicates.
+///
+/// Spotify returns the granted scopes in an order of its own, so tokens and cache entries compare
+/// their scopes as sets.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{scopes_eq, SpotifyScope};
+/// assert!(scopes_eq(
+///     &[SpotifyScope::Streaming, SpotifyScope::UserReadEmail],
+///     &[SpotifyScope::UserReadEmail, SpotifyScope::Streaming],
+/// ));
+/// # assert!(!scopes_eq(&[SpotifyScope::Streaming], &[SpotifyScope::UserReadEmail, SpotifyScope::Streaming]));
+/// ```
+pub fn scopes_eq(a: &[SpotifyScope], b: &[SpotifyScope]) -> bool {
+    a.iter().all(|x| b.contains(x)) && b.iter().all(|x| a.contains(x))
 }
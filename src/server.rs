@@ -0,0 +1,854 @@
+//! A local HTTP server receiving the redirect of the browser, for desktop apps and CLI tools.
+
+use crate::{error::*, SpotifyCallback};
+use futures_channel::oneshot;
+use snafu::ResultExt;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use url::{Host, Url};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often idle listeners are checked for connections and for the shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long a shared server accepts the callback of a login, authorization codes of Spotify
+/// expire after about ten minutes as well.
+const DEFAULT_STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[cfg(feature = "mdns")]
+mod mdns;
+
+#[cfg(feature = "mdns")]
+pub use mdns::MdnsAdvertisement;
+
+/// Listens on the port of a loopback redirect URI and catches the redirect from Spotify.
+///
+/// The server runs on a dedicated thread and uses no async runtime, [`CallbackServer::receive`]
+/// can be awaited under tokio, async-std, smol or any other executor. Requests to other paths,
+/// e.g. ``/favicon.ico``, are answered with ``404 Not Found``. Every authorization code is handed
+/// out once, repeated requests with the same code, e.g. from prefetching browsers, are answered
+/// with ``409 Conflict``.
+///
+/// Only available with the ``server`` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{CallbackServer, SpotifyAuth, SpotifyScope};
+/// # async fn run(auth: SpotifyAuth) -> spotify_oauth::SpotifyResult<()> {
+/// let server = CallbackServer::bind(&auth.redirect_uri)?;
+/// println!("Open this URL in a browser: {}", auth.authorize_url()?);
+///
+/// let callback = server.receive().await?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct CallbackServer {
+    listeners: Vec<TcpListener>,
+    redirect_uri: Url,
+    success_redirect: Option<Url>,
+    state_ttl: Duration,
+}
+
+impl CallbackServer {
+    /// Listen on the host and port of ``redirect_uri``.
+    ///
+    /// For ``localhost`` the server listens on both ``127.0.0.1`` and ``::1``, because browsers
+    /// differ in which address they try first. Literal addresses, e.g. ``127.0.0.1`` or ``[::1]``,
+    /// are used as they are. Port ``0`` selects a free port, see [`CallbackServer::local_addr`].
+    pub fn bind(redirect_uri: &Url) -> SpotifyResult<Self> {
+        let port = port(redirect_uri)?;
+        let listeners = match redirect_uri.host() {
+            None => {
+                return Err(SpotifyError::CallbackFailure {
+                    context: "The redirect URI has no host.",
+                })
+            }
+            Some(Host::Domain(x)) if x.eq_ignore_ascii_case("localhost") => bind_loopback(port),
+            Some(Host::Domain(x)) => TcpListener::bind((x, port)).map(|x| vec![x]),
+            Some(Host::Ipv4(x)) => TcpListener::bind((x, port)).map(|x| vec![x]),
+            Some(Host::Ipv6(x)) => TcpListener::bind((x, port)).map(|x| vec![x]),
+        };
+
+        Ok(Self {
+            listeners: listeners.context(ServerFailure)?,
+            redirect_uri: redirect_uri.clone(),
+            success_redirect: None,
+            state_ttl: DEFAULT_STATE_TTL,
+        })
+    }
+
+    /// Listen on the given interface and the port of ``redirect_uri``.
+    ///
+    /// Binding to ``0.0.0.0`` lets a headless device on the LAN receive the redirect of the
+    /// browser of a phone, with a redirect URI pointing at the address of the device. The server is
+    /// then reachable by every host of the network, always check the state of the callback.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::CallbackServer;
+    /// # use std::net::Ipv4Addr;
+    /// # use url::Url;
+    /// let redirect_uri = Url::parse("http://192.168.1.20:8888/callback").unwrap();
+    /// let server = CallbackServer::bind_address(&redirect_uri, Ipv4Addr::UNSPECIFIED.into()).unwrap();
+    /// ```
+    pub fn bind_address(redirect_uri: &Url, address: IpAddr) -> SpotifyResult<Self> {
+        let listener = TcpListener::bind((address, port(redirect_uri)?)).context(ServerFailure)?;
+
+        Ok(Self {
+            listeners: vec![listener],
+            redirect_uri: redirect_uri.clone(),
+            success_redirect: None,
+            state_ttl: DEFAULT_STATE_TTL,
+        })
+    }
+
+    /// Redirect the browser to ``url`` once the code was received, e.g. back into the web app or
+    /// to a "return to the app" page, instead of showing a static page.
+    ///
+    /// Callbacks carrying an error, e.g. ``access_denied``, still get the static page.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::CallbackServer;
+    /// # use url::Url;
+    /// let redirect_uri = Url::parse("http://127.0.0.1:8888/callback").unwrap();
+    /// let server = CallbackServer::bind(&redirect_uri)
+    ///     .unwrap()
+    ///     .with_success_redirect(Url::parse("https://app.example/welcome").unwrap());
+    /// ```
+    pub fn with_success_redirect(mut self, url: Url) -> Self {
+        self.success_redirect = Some(url);
+        self
+    }
+
+    /// Reject callbacks of a [`SharedCallbackServer`] whose state was registered longer than
+    /// ``ttl`` ago, ten minutes by default.
+    ///
+    /// The waiting login fails with [`SpotifyError::CallbackFailure`] and the browser gets
+    /// ``400 Bad Request``. States of finished logins are remembered for the same time, so a
+    /// replayed callback is rejected even if the state is registered again.
+    pub fn with_state_ttl(mut self, ttl: Duration) -> Self {
+        self.state_ttl = ttl;
+        self
+    }
+
+    /// The address the server listens on, the IPv4 one if it listens on both loopback addresses.
+    pub fn local_addr(&self) -> SpotifyResult<SocketAddr> {
+        self.listeners[0].local_addr().context(ServerFailure)
+    }
+
+    /// Wait for the redirect, blocking the current thread.
+    pub fn receive_blocking(self) -> SpotifyResult<SpotifyCallback> {
+        self.serve_once(&AtomicBool::new(false))
+    }
+
+    /// Wait for the redirect without blocking the executor.
+    ///
+    /// Dropping the future stops the server.
+    pub async fn receive(self) -> SpotifyResult<SpotifyCallback> {
+        self.spawn()?.callback().await
+    }
+
+    /// Run the server on a background thread.
+    ///
+    /// The returned handle yields the callback and stops the server when it is shut down or
+    /// dropped, e.g. when the user cancels the login.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::CallbackServer;
+    /// # use url::Url;
+    /// # async fn run() -> spotify_oauth::SpotifyResult<()> {
+    /// let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+    /// let mut server = CallbackServer::bind(&redirect_uri)?.spawn()?;
+    /// println!("Listening on {}", server.local_addr());
+    ///
+    /// // The user cancelled the login.
+    /// server.shutdown();
+    /// # Ok(()) }
+    /// ```
+    pub fn spawn(self) -> SpotifyResult<CallbackServerHandle> {
+        let (tx, rx) = oneshot::channel();
+        let worker = Worker::start(self, move |server, stop| {
+            drop(tx.send(server.serve_once(stop)))
+        })?;
+
+        Ok(CallbackServerHandle { worker, result: rx })
+    }
+
+    /// Run the server on a background thread for any number of logins.
+    ///
+    /// Instead of binding a server per attempt, e.g. for retries or several users, every login
+    /// registers its state with [`SharedCallbackServer::expect`] and receives the callback carrying
+    /// that state. Callbacks with an unknown, expired or already used state are rejected with
+    /// ``400 Bad Request``, see [`CallbackServer::with_state_ttl`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::{CallbackServer, SpotifyAuth};
+    /// # async fn run(auth: SpotifyAuth) -> spotify_oauth::SpotifyResult<()> {
+    /// let server = CallbackServer::bind(&auth.redirect_uri)?.spawn_shared()?;
+    ///
+    /// // Per login.
+    /// let pending = server.expect(&auth.state);
+    /// println!("Open this URL in a browser: {}", auth.authorize_url()?);
+    /// let callback = pending.callback().await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn spawn_shared(self) -> SpotifyResult<SharedCallbackServer> {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let ttl = self.state_ttl;
+        let worker = Worker::start(self, {
+            let pending = pending.clone();
+            move |server, stop| {
+                let _ = server.serve(stop, |callback| {
+                    pending.lock().unwrap().dispatch(callback, ttl)
+                });
+
+                // Wake up the logins still waiting.
+                pending.lock().unwrap().waiting.clear();
+            }
+        })?;
+
+        Ok(SharedCallbackServer {
+            worker,
+            pending,
+            ttl,
+        })
+    }
+
+    /// Answer requests until the first callback arrives or ``stop`` is set.
+    fn serve_once(&self, stop: &AtomicBool) -> SpotifyResult<SpotifyCallback> {
+        let mut result = None;
+        self.serve(stop, |callback| {
+            result = Some(callback);
+            Dispatch::Finish
+        })?;
+
+        result.ok_or_else(shut_down)
+    }
+
+    /// Answer requests until ``dispatch`` finishes or ``stop`` is set.
+    fn serve(
+        &self,
+        stop: &AtomicBool,
+        mut dispatch: impl FnMut(SpotifyCallback) -> Dispatch,
+    ) -> SpotifyResult<()> {
+        // The listeners are polled, so several of them can be served by one thread.
+        for listener in &self.listeners {
+            listener.set_nonblocking(true).context(ServerFailure)?;
+        }
+        // The codes handed out, with the time they were received.
+        let mut codes = HashMap::new();
+
+        while !stop.load(Ordering::SeqCst) {
+            let mut idle = true;
+            for listener in &self.listeners {
+                let stream = match listener.accept() {
+                    Ok((x, _)) => x,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(err) => return Err(err).context(ServerFailure),
+                };
+                idle = false;
+
+                if let Dispatch::Finish = self.answer(stream, &mut codes, &mut dispatch) {
+                    return Ok(());
+                }
+            }
+
+            if idle {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer one request, handing the callback to ``dispatch`` if it was the redirect.
+    fn answer(
+        &self,
+        mut stream: TcpStream,
+        codes: &mut HashMap<String, Instant>,
+        dispatch: &mut impl FnMut(SpotifyCallback) -> Dispatch,
+    ) -> Dispatch {
+        // A broken connection must not stop the server.
+        let callback = match self.read_callback(&mut stream) {
+            Ok(Some(x)) => x,
+            _ => return Dispatch::Continue,
+        };
+
+        // Spotify rejects a code exchanged twice, so a duplicate never reaches the login.
+        codes.retain(|_, received| received.elapsed() <= self.state_ttl);
+        let code = callback.code().map(str::to_string);
+        if code.as_ref().is_some_and(|x| codes.contains_key(x)) {
+            let _ = respond(
+                &mut stream,
+                "409 Conflict",
+                "This authorization code was already received, you can close this window.",
+            );
+            return Dispatch::Continue;
+        }
+
+        let redirect = self
+            .success_redirect
+            .as_ref()
+            .filter(|_| callback.code().is_some());
+        let dispatched = dispatch(callback);
+        if let (Dispatch::Finish | Dispatch::Continue, Some(code)) = (&dispatched, code) {
+            codes.insert(code, Instant::now());
+        }
+        let _ = match (&dispatched, redirect) {
+            (Dispatch::Finish | Dispatch::Continue, Some(url)) => write!(
+                stream,
+                "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                url
+            ),
+            (Dispatch::Finish | Dispatch::Continue, None) => respond(
+                &mut stream,
+                "200 OK",
+                "Authorization finished, you can close this window.",
+            ),
+            (Dispatch::Unknown, _) => respond(
+                &mut stream,
+                "400 Bad Request",
+                "This login is unknown or expired, please start it again.",
+            ),
+            (Dispatch::Expired, _) => respond(
+                &mut stream,
+                "400 Bad Request",
+                "This login expired, please start it again.",
+            ),
+            (Dispatch::Replayed, _) => respond(
+                &mut stream,
+                "400 Bad Request",
+                "This login was already finished, please start a new one.",
+            ),
+        };
+
+        dispatched
+    }
+
+    /// Read one request, yielding the callback if it was the redirect.
+    ///
+    /// Other requests are answered.
+    fn read_callback(&self, stream: &mut TcpStream) -> io::Result<Option<SpotifyCallback>> {
+        // A silent client must not block the server.
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut request_line = String::new();
+        BufReader::new(&*stream).read_line(&mut request_line)?;
+
+        let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let url = match self.redirect_uri.join(target) {
+            Ok(x) if x.path() == self.redirect_uri.path() => x,
+            _ => {
+                stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")?;
+                return Ok(None);
+            }
+        };
+
+        match SpotifyCallback::from_str(url.as_str()) {
+            Ok(x) => Ok(Some(x)),
+            Err(_) => {
+                stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// What the server does with a callback.
+enum Dispatch {
+    /// The callback was expected, stop serving.
+    Finish,
+    /// The callback was expected, keep serving.
+    Continue,
+    /// Nobody waits for the callback.
+    Unknown,
+    /// The state was registered too long ago.
+    Expired,
+    /// The state was used by a finished login.
+    Replayed,
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// The thread of a spawned server, stopped when dropped.
+#[derive(Debug)]
+struct Worker {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn start(
+        server: CallbackServer,
+        run: impl FnOnce(CallbackServer, &AtomicBool) + Send + 'static,
+    ) -> SpotifyResult<Self> {
+        let local_addr = server.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || run(server, &stop)
+        });
+
+        Ok(Self {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn stop(&mut self) {
+        let thread = match self.thread.take() {
+            None => return,
+            Some(x) => x,
+        };
+
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = thread.join();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A running [`CallbackServer`], see [`CallbackServer::spawn`].
+///
+/// Dropping the handle shuts the server down.
+#[derive(Debug)]
+pub struct CallbackServerHandle {
+    worker: Worker,
+    result: oneshot::Receiver<SpotifyResult<SpotifyCallback>>,
+}
+
+impl CallbackServerHandle {
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.worker.local_addr
+    }
+
+    /// Wait for the redirect.
+    ///
+    /// Fails if the server was shut down or the callback was already received. Cancel safe: a
+    /// callback arriving after the future was dropped is kept for the next call.
+    pub async fn callback(&mut self) -> SpotifyResult<SpotifyCallback> {
+        (&mut self.result)
+            .await
+            .unwrap_or_else(|_| Err(shut_down()))
+    }
+
+    /// Stop the server and wait for its thread to exit.
+    ///
+    /// The port is released when the function returns.
+    pub fn shutdown(&mut self) {
+        self.worker.stop();
+    }
+}
+
+/// The logins of a shared server by state.
+#[derive(Debug, Default)]
+struct Pending {
+    /// The logins waiting for their callback, with the time they were registered.
+    waiting: HashMap<String, (oneshot::Sender<SpotifyResult<SpotifyCallback>>, Instant)>,
+    /// The states of finished logins, with the time they finished.
+    consumed: HashMap<String, Instant>,
+}
+
+impl Pending {
+    /// Hand the callback to the login waiting for its state.
+    fn dispatch(&mut self, callback: SpotifyCallback, ttl: Duration) -> Dispatch {
+        let dispatched = self.dispatch_fresh(callback, ttl);
+        self.prune(ttl);
+
+        dispatched
+    }
+
+    fn dispatch_fresh(&mut self, callback: SpotifyCallback, ttl: Duration) -> Dispatch {
+        let state = callback.state().to_string();
+
+        if self.consumed.contains_key(&state) {
+            return Dispatch::Replayed;
+        }
+
+        let sender = match self.waiting.remove(&state) {
+            Some((x, created)) if created.elapsed() <= ttl => x,
+            Some((x, _)) => {
+                let _ = x.send(Err(expired()));
+                return Dispatch::Expired;
+            }
+            None => return Dispatch::Unknown,
+        };
+
+        match sender.send(Ok(callback)) {
+            Ok(()) => {
+                self.consumed.insert(state, Instant::now());
+                Dispatch::Continue
+            }
+            Err(_) => Dispatch::Unknown,
+        }
+    }
+
+    /// Fail the logins and forget the finished states older than ``ttl``.
+    fn prune(&mut self, ttl: Duration) {
+        self.consumed
+            .retain(|_, finished| finished.elapsed() <= ttl);
+
+        let stale: Vec<_> = self
+            .waiting
+            .iter()
+            .filter(|(_, (_, created))| created.elapsed() > ttl)
+            .map(|(state, _)| state.clone())
+            .collect();
+        for state in stale {
+            if let Some((sender, _)) = self.waiting.remove(&state) {
+                let _ = sender.send(Err(expired()));
+            }
+        }
+    }
+}
+
+/// A [`CallbackServer`] serving many logins, see [`CallbackServer::spawn_shared`].
+///
+/// Dropping the server shuts it down.
+#[derive(Debug)]
+pub struct SharedCallbackServer {
+    worker: Worker,
+    pending: Arc<Mutex<Pending>>,
+    ttl: Duration,
+}
+
+impl SharedCallbackServer {
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.worker.local_addr
+    }
+
+    /// Wait for the callback of the login with the given state.
+    ///
+    /// Register the state before sending the user to Spotify. A second registration of the same
+    /// state replaces the first one. States of finished logins must not be reused, their
+    /// registration fails until the state TTL passed.
+    pub fn expect(&self, state: impl Into<String>) -> PendingCallback {
+        let state = state.into();
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        pending.prune(self.ttl);
+
+        if pending.consumed.contains_key(&state) {
+            let _ = tx.send(Err(SpotifyError::CallbackFailure {
+                context: "The state was already used by a finished login.",
+            }));
+        } else {
+            pending.waiting.insert(state.clone(), (tx, Instant::now()));
+        }
+        drop(pending);
+
+        PendingCallback {
+            state,
+            result: rx,
+            pending: self.pending.clone(),
+        }
+    }
+
+    /// Stop the server and wait for its thread to exit, failing all pending logins.
+    ///
+    /// The port is released when the function returns.
+    pub fn shutdown(&mut self) {
+        self.worker.stop();
+    }
+}
+
+/// A login waiting for its callback, see [`SharedCallbackServer::expect`].
+///
+/// Dropping it, e.g. when the user gives up, unregisters the state.
+#[derive(Debug)]
+pub struct PendingCallback {
+    state: String,
+    result: oneshot::Receiver<SpotifyResult<SpotifyCallback>>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl PendingCallback {
+    /// Wait for the redirect.
+    ///
+    /// Fails if the server was shut down, the state was registered again, is already used or
+    /// expired. Dropping the future unregisters the state.
+    pub async fn callback(mut self) -> SpotifyResult<SpotifyCallback> {
+        (&mut self.result)
+            .await
+            .unwrap_or_else(|_| Err(shut_down()))
+    }
+}
+
+impl Drop for PendingCallback {
+    fn drop(&mut self) {
+        self.result.close();
+        let mut pending = self.pending.lock().unwrap();
+
+        // Keep a newer registration of the same state.
+        if pending
+            .waiting
+            .get(&self.state)
+            .is_some_and(|x| x.0.is_canceled())
+        {
+            pending.waiting.remove(&self.state);
+        }
+    }
+}
+
+fn expired() -> SpotifyError {
+    SpotifyError::CallbackFailure {
+        context: "The state of the login expired.",
+    }
+}
+
+fn shut_down() -> SpotifyError {
+    SpotifyError::ServerFailure {
+        source: io::Error::new(io::ErrorKind::Interrupted, "the server was shut down"),
+    }
+}
+
+/// Listen on the IPv4 and, if available, the IPv6 loopback address with the same port.
+fn bind_loopback(port: u16) -> io::Result<Vec<TcpListener>> {
+    let v4 = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let port = v4.local_addr()?.port();
+    let mut listeners = vec![v4];
+
+    // IPv6 may be disabled on the host.
+    if let Ok(v6) = TcpListener::bind((Ipv6Addr::LOCALHOST, port)) {
+        listeners.push(v6);
+    }
+
+    Ok(listeners)
+}
+
+/// The port of the redirect URI.
+fn port(redirect_uri: &Url) -> SpotifyResult<u16> {
+    redirect_uri
+        .port_or_known_default()
+        .ok_or(SpotifyError::CallbackFailure {
+            context: "The redirect URI has no port.",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    #[async_std::test]
+    async fn test_receive_callback() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let server = CallbackServer::bind(&redirect_uri).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            assert!(get(addr, "/favicon.ico").starts_with("HTTP/1.1 404"));
+            assert!(get(addr, "/callback").starts_with("HTTP/1.1 400"));
+            get(addr, "/callback?code=NApCCgBkWtQ&state=test")
+        });
+
+        let callback = server.receive().await.unwrap();
+        assert_eq!(callback.code(), Some("NApCCgBkWtQ"));
+        assert_eq!(callback.state(), "test");
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_bind_address() {
+        let redirect_uri = Url::parse("http://device.local:0/callback").unwrap();
+        let server = CallbackServer::bind_address(&redirect_uri, [127, 0, 0, 1].into()).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = thread::spawn(move || get(addr, "/callback?code=NApCCgBkWtQ&state=test"));
+
+        assert_eq!(server.receive_blocking().unwrap().state(), "test");
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_shutdown_releases_port() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let mut server = CallbackServer::bind(&redirect_uri)
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let addr = server.local_addr();
+
+        server.shutdown();
+        assert!(TcpStream::connect(addr).is_err());
+        assert!(async_std::task::block_on(server.callback()).is_err());
+    }
+
+    #[async_std::test]
+    async fn test_shared_server_dispatches_by_state() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let server = CallbackServer::bind(&redirect_uri)
+            .unwrap()
+            .spawn_shared()
+            .unwrap();
+        let addr = server.local_addr();
+        let (alice, bob) = (server.expect("alice"), server.expect("bob"));
+
+        let client = thread::spawn(move || {
+            [
+                get(addr, "/callback?code=Ym9i&state=bob"),
+                get(addr, "/callback?code=ZXZl&state=eve"),
+                get(addr, "/callback?code=YWxpY2U&state=alice"),
+            ]
+        });
+
+        assert_eq!(bob.callback().await.unwrap().code(), Some("Ym9i"));
+        assert_eq!(alice.callback().await.unwrap().code(), Some("YWxpY2U"));
+        let responses = client.join().unwrap();
+        assert!(responses[0].starts_with("HTTP/1.1 200"));
+        assert!(responses[1].starts_with("HTTP/1.1 400"));
+        assert!(responses[2].starts_with("HTTP/1.1 200"));
+
+        drop(server.expect("carol"));
+        assert!(server.pending.lock().unwrap().waiting.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_shared_server_rejects_stale_and_replayed_states() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let server = CallbackServer::bind(&redirect_uri)
+            .unwrap()
+            .with_state_ttl(Duration::from_millis(200))
+            .spawn_shared()
+            .unwrap();
+        let addr = server.local_addr();
+
+        let alice = server.expect("alice");
+        let response = get(addr, "/callback?code=YWxpY2U&state=alice");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(alice.callback().await.unwrap().code(), Some("YWxpY2U"));
+
+        let response = get(addr, "/callback?code=ZXZl&state=alice");
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("already finished"));
+        assert!(server.expect("alice").callback().await.is_err());
+
+        let bob = server.expect("bob");
+        thread::sleep(Duration::from_millis(300));
+        let response = get(addr, "/callback?code=Ym9i&state=bob");
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("This login expired"));
+        let err = bob.callback().await.unwrap_err();
+        assert!(matches!(err, SpotifyError::CallbackFailure { .. }));
+
+        // Finished states are forgotten after the TTL.
+        let alice = server.expect("alice");
+        let client = thread::spawn(move || get(addr, "/callback?code=YWxpY2U&state=alice"));
+        assert!(alice.callback().await.is_ok());
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[async_std::test]
+    async fn test_duplicate_code_conflicts() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let server = CallbackServer::bind(&redirect_uri)
+            .unwrap()
+            .spawn_shared()
+            .unwrap();
+        let addr = server.local_addr();
+        let (alice, bob) = (server.expect("alice"), server.expect("bob"));
+
+        let client = thread::spawn(move || {
+            [
+                get(addr, "/callback?code=YWxpY2U&state=alice"),
+                get(addr, "/callback?code=YWxpY2U&state=alice"),
+                get(addr, "/callback?code=YWxpY2U&state=bob"),
+                get(addr, "/callback?code=Ym9i&state=bob"),
+            ]
+        });
+
+        assert_eq!(alice.callback().await.unwrap().code(), Some("YWxpY2U"));
+        assert_eq!(bob.callback().await.unwrap().code(), Some("Ym9i"));
+        let responses = client.join().unwrap();
+        assert!(responses[0].starts_with("HTTP/1.1 200"));
+        assert!(responses[1].starts_with("HTTP/1.1 409"));
+        assert!(responses[2].starts_with("HTTP/1.1 409"));
+        assert!(responses[3].starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_success_redirect() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let mut server = CallbackServer::bind(&redirect_uri)
+            .unwrap()
+            .with_success_redirect(Url::parse("https://app.example/welcome").unwrap())
+            .spawn_shared()
+            .unwrap();
+        let addr = server.local_addr();
+        let (_alice, _bob) = (server.expect("alice"), server.expect("bob"));
+
+        let response = get(addr, "/callback?code=YWxpY2U&state=alice");
+        assert!(response.starts_with("HTTP/1.1 302"));
+        assert!(response.contains("Location: https://app.example/welcome\r\n"));
+
+        let response = get(addr, "/callback?error=access_denied&state=bob");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        server.shutdown();
+    }
+
+    #[async_std::test]
+    async fn test_localhost_binds_both_loopback_addresses() {
+        let redirect_uri = Url::parse("http://localhost:0/callback").unwrap();
+        let server = CallbackServer::bind(&redirect_uri).unwrap();
+        let addrs: Vec<_> = server
+            .listeners
+            .iter()
+            .map(|x| x.local_addr().unwrap())
+            .collect();
+        assert!(addrs[0].is_ipv4());
+        assert!(addrs.iter().all(|x| x.port() == addrs[0].port()));
+
+        let server = server.spawn_shared().unwrap();
+        for (i, addr) in addrs.into_iter().enumerate() {
+            let state = i.to_string();
+            let pending = server.expect(&state);
+            let target = format!("/callback?code=NApCCgBkWtQ{}&state={}", i, state);
+            let client = thread::spawn(move || get(addr, &target));
+
+            assert_eq!(pending.callback().await.unwrap().state(), state);
+            assert!(client.join().unwrap().starts_with("HTTP/1.1 200"));
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use super::CallbackServer;
+use crate::error::*;
+use snafu::ResultExt;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE: [&str; 3] = ["_http", "_tcp", "local"];
+const TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// The records of this host replace all cached records of the same name.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// The mDNS announcement of a [`CallbackServer`], stopped when dropped.
+///
+/// Only available with the ``mdns`` feature.
+#[derive(Debug)]
+pub struct MdnsAdvertisement {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for MdnsAdvertisement {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl CallbackServer {
+    /// Advertise the server as ``_http._tcp`` service via mDNS, so companion apps on the LAN can
+    /// discover where to send the user for pairing.
+    ///
+    /// The service is announced as ``<instance>._http._tcp.local`` with the port of the server and
+    /// the path of the redirect URI in the ``path`` TXT entry. The address is the one the server is
+    /// bound to or, for ``0.0.0.0``, the address of the interface with the multicast route. Only
+    /// IPv4 is supported. The responder runs on a background thread and ignores network errors
+    /// once started.
+    ///
+    /// Only available with the ``mdns`` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spotify_oauth::CallbackServer;
+    /// # use std::net::Ipv4Addr;
+    /// # use url::Url;
+    /// # async fn run() -> spotify_oauth::SpotifyResult<()> {
+    /// let redirect_uri = Url::parse("http://192.168.1.20:8888/callback").unwrap();
+    /// let server = CallbackServer::bind_address(&redirect_uri, Ipv4Addr::UNSPECIFIED.into())?;
+    /// let advertisement = server.advertise("Living Room Speaker")?;
+    ///
+    /// let callback = server.receive().await?;
+    /// drop(advertisement);
+    /// # Ok(()) }
+    /// ```
+    pub fn advertise(&self, instance: &str) -> SpotifyResult<MdnsAdvertisement> {
+        if instance.is_empty() || instance.len() > 63 {
+            return Err(invalid_input("the instance name must have 1 to 63 bytes"));
+        }
+
+        let local = self.local_addr()?;
+        let address = match local.ip() {
+            IpAddr::V4(x) if x.is_unspecified() => multicast_interface()?,
+            IpAddr::V4(x) => x,
+            IpAddr::V6(_) => return Err(invalid_input("mDNS advertisement supports IPv4 only")),
+        };
+        let service = Service {
+            instance: instance.to_string(),
+            host: host_label(instance),
+            address,
+            port: local.port(),
+            path: self.redirect_uri.path().to_string(),
+        };
+
+        let socket = multicast_socket()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || respond(&socket, &service, &stop)
+        });
+
+        Ok(MdnsAdvertisement {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn invalid_input(message: &str) -> SpotifyError {
+    SpotifyError::ServerFailure {
+        source: io::Error::new(io::ErrorKind::InvalidInput, message),
+    }
+}
+
+/// A UDP socket joined to the mDNS group, sharing the port with other responders of the host.
+fn multicast_socket() -> SpotifyResult<UdpSocket> {
+    let socket =
+        Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).context(ServerFailure)?;
+    socket.set_reuse_address(true).context(ServerFailure)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true).context(ServerFailure)?;
+    socket
+        .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())
+        .context(ServerFailure)?;
+    socket
+        .join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)
+        .context(ServerFailure)?;
+    // Wake up regularly to notice the end of the advertisement.
+    socket
+        .set_read_timeout(Some(Duration::from_millis(250)))
+        .context(ServerFailure)?;
+
+    Ok(socket.into())
+}
+
+/// The address of the interface multicast packets are sent from.
+fn multicast_interface() -> SpotifyResult<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context(ServerFailure)?;
+    socket
+        .connect((MDNS_GROUP, MDNS_PORT))
+        .context(ServerFailure)?;
+
+    match socket.local_addr().context(ServerFailure)?.ip() {
+        IpAddr::V4(x) if !x.is_unspecified() => Ok(x),
+        _ => Err(invalid_input("no IPv4 interface for mDNS found")),
+    }
+}
+
+/// A DNS label for the host name, derived from the instance name.
+fn host_label(instance: &str) -> String {
+    let label: String = instance
+        .chars()
+        .map(|x| match x {
+            'a'..='z' | '0'..='9' => x,
+            'A'..='Z' => x.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .collect();
+    let label = label.trim_matches('-');
+
+    match label.is_empty() {
+        true => "spotify-oauth".to_string(),
+        false => label.to_string(),
+    }
+}
+
+/// Announce the service, answer queries until stopped and withdraw it.
+fn respond(socket: &UdpSocket, service: &Service, stop: &AtomicBool) {
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let started = Instant::now();
+    let mut announced = false;
+    let mut buf = [0; 1500];
+
+    let _ = socket.send_to(&service.response(TTL), group);
+    while !stop.load(Ordering::Relaxed) {
+        // Announcements are repeated after one second, in case the first one was lost.
+        if !announced && started.elapsed() > Duration::from_secs(1) {
+            let _ = socket.send_to(&service.response(TTL), group);
+            announced = true;
+        }
+
+        if let Ok((len, _)) = socket.recv_from(&mut buf) {
+            if service.answers(&buf[..len]) {
+                let _ = socket.send_to(&service.response(TTL), group);
+            }
+        }
+    }
+
+    // A TTL of zero removes the records from the caches of the network.
+    let _ = socket.send_to(&service.response(0), group);
+}
+
+/// The DNS-SD records of the callback server.
+struct Service {
+    instance: String,
+    host: String,
+    address: Ipv4Addr,
+    port: u16,
+    path: String,
+}
+
+impl Service {
+    fn instance_name(&self) -> [&str; 4] {
+        [&self.instance, SERVICE[0], SERVICE[1], SERVICE[2]]
+    }
+
+    fn host_name(&self) -> [&str; 2] {
+        [&self.host, "local"]
+    }
+
+    /// A response carrying all records of the service.
+    fn response(&self, ttl: u32) -> Vec<u8> {
+        // Header: no id, an authoritative response with four answers.
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 4, 0, 0, 0, 0];
+
+        let mut instance = Vec::new();
+        write_name(&mut instance, &self.instance_name());
+        write_record(&mut packet, &SERVICE, TYPE_PTR, CLASS_IN, ttl, &instance);
+
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&self.port.to_be_bytes());
+        write_name(&mut srv, &self.host_name());
+        let class = CLASS_IN | CACHE_FLUSH;
+        write_record(
+            &mut packet,
+            &self.instance_name(),
+            TYPE_SRV,
+            class,
+            ttl,
+            &srv,
+        );
+
+        let entry = format!("path={}", self.path);
+        let mut txt = vec![entry.len().min(255) as u8];
+        txt.extend(entry.bytes().take(255));
+        write_record(
+            &mut packet,
+            &self.instance_name(),
+            TYPE_TXT,
+            class,
+            ttl,
+            &txt,
+        );
+
+        let address = self.address.octets();
+        write_record(&mut packet, &self.host_name(), TYPE_A, class, ttl, &address);
+
+        packet
+    }
+
+    /// Whether the packet is a query for one of the records of the service.
+    fn answers(&self, packet: &[u8]) -> bool {
+        let is_query = packet.len() >= 12 && packet[2] & 0x80 == 0;
+        if !is_query {
+            return false;
+        }
+
+        let questions = u16::from_be_bytes([packet[4], packet[5]]);
+        let mut pos = 12;
+        for _ in 0..questions {
+            let (name, end) = match read_name(packet, pos) {
+                Some(x) => x,
+                None => return false,
+            };
+            let qtype = match packet.get(end..end + 2) {
+                Some(x) => u16::from_be_bytes([x[0], x[1]]),
+                None => return false,
+            };
+            pos = end + 4;
+
+            let matches = |labels: &[&str], types: &[u16]| {
+                types.contains(&qtype)
+                    && name.len() == labels.len()
+                    && name
+                        .iter()
+                        .zip(labels)
+                        .all(|(a, b)| a.eq_ignore_ascii_case(b))
+            };
+            if matches(&SERVICE, &[TYPE_PTR, TYPE_ANY])
+                || matches(&self.instance_name(), &[TYPE_SRV, TYPE_TXT, TYPE_ANY])
+                || matches(&self.host_name(), &[TYPE_A, TYPE_ANY])
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+fn write_name(buf: &mut Vec<u8>, labels: &[&str]) {
+    for label in labels {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+fn write_record(buf: &mut Vec<u8>, name: &[&str], rtype: u16, class: u16, ttl: u32, data: &[u8]) {
+    write_name(buf, name);
+    buf.extend_from_slice(&rtype.to_be_bytes());
+    buf.extend_from_slice(&class.to_be_bytes());
+    buf.extend_from_slice(&ttl.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// Read a possibly compressed name, yielding its labels and the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(Vec<String>, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // Bounded, compression pointers may form loops.
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some((labels, end.unwrap_or(pos + 1))),
+            x if x & 0xc0 == 0xc0 => {
+                end.get_or_insert(pos + 2);
+                pos = (x & 0x3f) << 8 | *packet.get(pos + 1)? as usize;
+            }
+            x => {
+                let label = packet.get(pos + 1..pos + 1 + x)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + x;
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &[&str], qtype: u16) -> Vec<u8> {
+        let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        write_name(&mut packet, name);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+        packet
+    }
+
+    #[test]
+    fn test_service_answers_queries() {
+        let service = Service {
+            instance: "Living Room".to_string(),
+            host: host_label("Living Room"),
+            address: Ipv4Addr::new(192, 168, 1, 20),
+            port: 8888,
+            path: "/callback".to_string(),
+        };
+
+        assert_eq!(service.host, "living-room");
+        assert!(service.answers(&query(&["_HTTP", "_tcp", "local"], TYPE_PTR)));
+        assert!(service.answers(&query(&["living-room", "local"], TYPE_A)));
+        assert!(!service.answers(&query(&["_ipp", "_tcp", "local"], TYPE_PTR)));
+
+        let response = service.response(TTL);
+        let (name, _) = read_name(&response, 12).unwrap();
+        assert_eq!(name, SERVICE);
+        assert!(response.windows(2).any(|x| x == 8888u16.to_be_bytes()));
+        assert!(response.windows(4).any(|x| x == [192, 168, 1, 20]));
+        assert!(response.windows(14).any(|x| x == b"path=/callback"));
+    }
+}
@@ -0,0 +1,484 @@
+//! Persistence of tokens between runs of an application.
+
+use crate::{error::*, CachedToken, SpotifyScope, SpotifyToken};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// A place to keep tokens, e.g. a file, a keyring or the storage of a browser.
+///
+/// Tokens are stored under a key chosen by the application, e.g. the name of the account. Stores
+/// are expiry aware: a token whose access token expired and which has no refresh token is useless
+/// and not returned by [`TokenStore::load`].
+pub trait TokenStore: Send + Sync {
+    /// The token stored under the given key, if any.
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>>;
+
+    /// Store a token under the given key, replacing the previous one.
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()>;
+
+    /// Remove the token stored under the given key.
+    fn remove(&self, key: &str) -> SpotifyResult<()>;
+
+    /// Store ``token`` under the given key only if the stored token is still ``current``, and
+    /// return whether it was stored.
+    ///
+    /// Tokens are compared by their access and refresh tokens. Processes refreshing the same
+    /// account save the refreshed token with it, so a rotated refresh token saved by another
+    /// process is not overwritten. The default loads, compares and saves without holding a lock
+    /// in between, stores shared by several processes override it with an atomic update.
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: &SpotifyToken,
+        token: &SpotifyToken,
+    ) -> SpotifyResult<bool> {
+        match self.load(key)? {
+            Some(x) if same_version(&x, current) => self.save(key, token).map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Remove the tokens which cannot be refreshed and whose access token expired before the
+    /// given time, and return how many were removed.
+    ///
+    /// Long-running services call it regularly, e.g. with [`crate::TokenManager::spawn_purge`],
+    /// so the tokens of users who never return do not pile up. Stores which cannot list their tokens keep the default, which removes nothing.
+    fn purge_expired(&self, before: DateTime<Utc>) -> SpotifyResult<usize> {
+        let _ = before;
+        Ok(0)
+    }
+}
+
+/// A shared store, e.g. one store for the token managers of several scope sets.
+impl<T: TokenStore + ?Sized> TokenStore for Arc<T> {
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        (**self).load(key)
+    }
+
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        (**self).save(key, token)
+    }
+
+    fn remove(&self, key: &str) -> SpotifyResult<()> {
+        (**self).remove(key)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: &SpotifyToken,
+        token: &SpotifyToken,
+    ) -> SpotifyResult<bool> {
+        (**self).compare_and_swap(key, current, token)
+    }
+
+    fn purge_expired(&self, before: DateTime<Utc>) -> SpotifyResult<usize> {
+        (**self).purge_expired(before)
+    }
+}
+
+/// An asynchronous [`TokenStore`], e.g. over a database.
+///
+/// Every [`TokenStore`] is also an [`AsyncTokenStore`]. The futures are not ``Send``, so browser
+/// APIs like IndexedDB can implement the trait.
+#[async_trait(?Send)]
+pub trait AsyncTokenStore {
+    /// The token stored under the given key, if any.
+    async fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>>;
+
+    /// Store a token under the given key, replacing the previous one.
+    async fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()>;
+
+    /// Remove the token stored under the given key.
+    async fn remove(&self, key: &str) -> SpotifyResult<()>;
+}
+
+#[async_trait(?Send)]
+impl<T: TokenStore + ?Sized> AsyncTokenStore for T {
+    async fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        TokenStore::load(self, key)
+    }
+
+    async fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        TokenStore::save(self, key, token)
+    }
+
+    async fn remove(&self, key: &str) -> SpotifyResult<()> {
+        TokenStore::remove(self, key)
+    }
+}
+
+/// The store key of the token of ``account`` granting ``scope``, e.g.
+/// ``alice:streaming,user-read-email``.
+///
+/// Apps holding several tokens of one user, e.g. a minimal one and one with all scopes, keep each
+/// under its own key. The order and duplicates of the scopes do not matter.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{scoped_key, SpotifyScope};
+/// let key = scoped_key("alice", &[SpotifyScope::UserReadEmail, SpotifyScope::Streaming]);
+/// # assert_eq!(key, "alice:streaming,user-read-email");
+/// # assert_eq!(key, scoped_key("alice", &[SpotifyScope::Streaming, SpotifyScope::UserReadEmail]));
+/// ```
+pub fn scoped_key(account: &str, scope: &[SpotifyScope]) -> String {
+    let mut names: Vec<_> = scope.iter().map(SpotifyScope::as_str).collect();
+    names.sort_unstable();
+    names.dedup();
+
+    format!("{}:{}", account, names.join(","))
+}
+
+/// Whether a stored token can still be used, directly or by refreshing it.
+pub(crate) fn is_usable(token: &SpotifyToken) -> bool {
+    token.refresh_token.is_some() || !token.is_expired()
+}
+
+/// Whether two tokens are the same version of the token of an account.
+pub(crate) fn same_version(a: &SpotifyToken, b: &SpotifyToken) -> bool {
+    a.access_token == b.access_token && a.refresh_token == b.refresh_token
+}
+
+/// Whether a stored token cannot be refreshed and its access token expired before ``before``.
+fn is_dead(token: &CachedToken, before: DateTime<Utc>) -> bool {
+    token.refresh_token.is_none() && token.expires_at.is_none_or(|x| x < before.timestamp())
+}
+
+/// A [`TokenStore`] keeping the tokens in memory, e.g. for tests.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{MemoryTokenStore, SpotifyToken, TokenStore};
+/// let store = MemoryTokenStore::default();
+/// let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "NgAagA"}"#).unwrap();
+///
+/// store.save("alice", &token).unwrap();
+/// # assert_eq!(store.load("alice").unwrap().unwrap().access_token, "NgCXRK");
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: Mutex<HashMap<String, CachedToken>>,
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let token = match tokens.get(key) {
+            None => return Ok(None),
+            Some(x) => SpotifyToken::from(x.clone()),
+        };
+
+        if !is_usable(&token) {
+            tokens.remove(key);
+            return Ok(None);
+        }
+
+        Ok(Some(token))
+    }
+
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), token.clone().into());
+
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> SpotifyResult<()> {
+        self.tokens.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: &SpotifyToken,
+        token: &SpotifyToken,
+    ) -> SpotifyResult<bool> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let is_current = tokens
+            .get(key)
+            .is_some_and(|x| same_version(&SpotifyToken::from(x.clone()), current));
+        if is_current {
+            tokens.insert(key.to_string(), token.clone().into());
+        }
+
+        Ok(is_current)
+    }
+
+    fn purge_expired(&self, before: DateTime<Utc>) -> SpotifyResult<usize> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let count = tokens.len();
+        tokens.retain(|_, x| !is_dead(x, before));
+
+        Ok(count - tokens.len())
+    }
+}
+
+/// Encryption of the secrets kept in a store, see [`EncryptedTokenStore`].
+///
+/// Implementations call a KMS, an HSM or a cipher of the application's choice, the crate does
+/// not pick one.
+pub trait Encryptor: Send + Sync {
+    /// Encrypt a secret for storage.
+    fn encrypt(&self, plaintext: &[u8]) -> SpotifyResult<Vec<u8>>;
+
+    /// Decrypt a secret encrypted by [`Encryptor::encrypt`].
+    fn decrypt(&self, ciphertext: &[u8]) -> SpotifyResult<Vec<u8>>;
+}
+
+/// A [`TokenStore`] encrypting the access and refresh tokens before they reach another store.
+///
+/// The secrets are stored base64 encoded in place of the plain ones. The expiry and the scopes
+/// stay readable, so the inner store can still drop and purge expired tokens. Encryption may be
+/// randomized, tokens are compared after decryption.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{Encryptor, EncryptedTokenStore, MemoryTokenStore, SpotifyResult, SpotifyToken, TokenStore};
+/// struct Kms;
+///
+/// impl Encryptor for Kms {
+///     fn encrypt(&self, plaintext: &[u8]) -> SpotifyResult<Vec<u8>> {
+///         // Call the KMS.
+/// #       Ok(plaintext.iter().rev().copied().collect())
+///     }
+///
+///     fn decrypt(&self, ciphertext: &[u8]) -> SpotifyResult<Vec<u8>> {
+/// #       Ok(ciphertext.iter().rev().copied().collect())
+///     }
+/// }
+///
+/// let store = EncryptedTokenStore::new(MemoryTokenStore::default(), Kms);
+/// # let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "NgAagA"}"#).unwrap();
+/// store.save("alice", &token).unwrap();
+/// # assert_eq!(store.load("alice").unwrap().unwrap().access_token, "NgCXRK");
+/// # assert_eq!(store.inner().load("alice").unwrap().unwrap().access_token, base64::encode("KRXCgN"));
+/// ```
+#[derive(Debug)]
+pub struct EncryptedTokenStore<S, E> {
+    inner: S,
+    encryptor: E,
+}
+
+impl<S: TokenStore, E: Encryptor> EncryptedTokenStore<S, E> {
+    /// Encrypt the secrets of the tokens kept in ``inner`` with ``encryptor``.
+    pub fn new(inner: S, encryptor: E) -> Self {
+        Self { inner, encryptor }
+    }
+
+    /// The store of the encrypted tokens.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn encrypt(&self, token: &SpotifyToken) -> SpotifyResult<SpotifyToken> {
+        let seal = |x: &str| {
+            self.encryptor
+                .encrypt(x.as_bytes())
+                .map(|x| base64::encode(&x))
+        };
+
+        let mut sealed = token.clone();
+        sealed.access_token = seal(&token.access_token)?;
+        sealed.refresh_token = token.refresh_token.as_deref().map(seal).transpose()?;
+        Ok(sealed)
+    }
+
+    fn decrypt(&self, sealed: SpotifyToken) -> SpotifyResult<SpotifyToken> {
+        let open = |x: &str| {
+            let ciphertext = base64::decode(x).map_err(|_| SpotifyError::StoreFailure {
+                context: "encrypted token is not base64 encoded".into(),
+            })?;
+            String::from_utf8(self.encryptor.decrypt(&ciphertext)?).map_err(|_| {
+                SpotifyError::StoreFailure {
+                    context: "decrypted token is not UTF-8".into(),
+                }
+            })
+        };
+
+        let mut token = sealed;
+        token.access_token = open(&token.access_token)?;
+        token.refresh_token = token.refresh_token.as_deref().map(open).transpose()?;
+        Ok(token)
+    }
+}
+
+impl<S: TokenStore, E: Encryptor> TokenStore for EncryptedTokenStore<S, E> {
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        self.inner.load(key)?.map(|x| self.decrypt(x)).transpose()
+    }
+
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        self.inner.save(key, &self.encrypt(token)?)
+    }
+
+    fn remove(&self, key: &str) -> SpotifyResult<()> {
+        self.inner.remove(key)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: &SpotifyToken,
+        token: &SpotifyToken,
+    ) -> SpotifyResult<bool> {
+        let sealed = match self.inner.load(key)? {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+        if !same_version(&self.decrypt(sealed.clone())?, current) {
+            return Ok(false);
+        }
+
+        self.inner
+            .compare_and_swap(key, &sealed, &self.encrypt(token)?)
+    }
+
+    fn purge_expired(&self, before: DateTime<Utc>) -> SpotifyResult<usize> {
+        self.inner.purge_expired(before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryTokenStore, SpotifyToken, TokenStore};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_memory_store_drops_useless_tokens() {
+        let store = MemoryTokenStore::default();
+        let expired = SpotifyToken::fixture().expires_at(1_600_000_000);
+
+        store.save("alice", &expired.clone().build()).unwrap();
+        assert!(store.load("alice").unwrap().is_some());
+
+        store
+            .save("bob", &expired.without_refresh_token().build())
+            .unwrap();
+        assert_eq!(store.load("bob").unwrap(), None);
+
+        store.remove("alice").unwrap();
+        assert_eq!(store.load("alice").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memory_store_purges_dead_tokens() {
+        let store = MemoryTokenStore::default();
+        let expired = SpotifyToken::fixture()
+            .expires_at(1_600_000_000)
+            .without_refresh_token();
+
+        store.save("alice", &expired.clone().build()).unwrap();
+        store
+            .save("bob", &expired.expires_at(1_600_003_600).build())
+            .unwrap();
+        store
+            .save(
+                "carol",
+                &SpotifyToken::fixture().expires_at(1_600_000_000).build(),
+            )
+            .unwrap();
+
+        let before = Utc.timestamp_opt(1_600_001_800, 0).unwrap();
+        assert_eq!(store.purge_expired(before).unwrap(), 1);
+        assert_eq!(store.purge_expired(before).unwrap(), 0);
+
+        let tokens = store.tokens.lock().unwrap();
+        let mut keys: Vec<_> = tokens.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["bob", "carol"]);
+    }
+
+    #[test]
+    fn test_memory_store_keeps_unknown_fields() {
+        let store = MemoryTokenStore::default();
+        let mut token = SpotifyToken::fixture().build();
+        token.extra.insert("id_token".into(), "eyJhbGci".into());
+
+        store.save("alice", &token).unwrap();
+        let loaded = store.load("alice").unwrap().unwrap();
+        assert_eq!(loaded.extra, token.extra);
+        assert_eq!(
+            serde_json::to_value(loaded).unwrap()["id_token"],
+            "eyJhbGci"
+        );
+    }
+
+    #[test]
+    fn test_memory_store_compare_and_swap() {
+        let store = MemoryTokenStore::default();
+        let first = SpotifyToken::fixture().refresh_token("Zmlyc3Q").build();
+        let second = SpotifyToken::fixture().refresh_token("c2Vjb25k").build();
+        let third = SpotifyToken::fixture().refresh_token("dGhpcmQ").build();
+
+        assert!(!store.compare_and_swap("alice", &first, &second).unwrap());
+        store.save("alice", &first).unwrap();
+        assert!(store.compare_and_swap("alice", &first, &second).unwrap());
+        assert!(!store.compare_and_swap("alice", &first, &third).unwrap());
+        assert_eq!(store.load("alice").unwrap(), Some(second));
+    }
+
+    #[test]
+    fn test_encrypted_store_hides_secrets() {
+        use super::{EncryptedTokenStore, Encryptor, SpotifyResult};
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        /// Prefixes a nonce and flips the bits, so equal tokens encrypt differently.
+        #[derive(Default)]
+        struct Flip(AtomicU8);
+
+        impl Encryptor for Flip {
+            fn encrypt(&self, plaintext: &[u8]) -> SpotifyResult<Vec<u8>> {
+                let nonce = self.0.fetch_add(1, Ordering::Relaxed);
+                Ok(std::iter::once(nonce)
+                    .chain(plaintext.iter().map(|x| !x))
+                    .collect())
+            }
+
+            fn decrypt(&self, ciphertext: &[u8]) -> SpotifyResult<Vec<u8>> {
+                Ok(ciphertext[1..].iter().map(|x| !x).collect())
+            }
+        }
+
+        let store = EncryptedTokenStore::new(MemoryTokenStore::default(), Flip::default());
+        let first = SpotifyToken::fixture().refresh_token("Zmlyc3Q").build();
+        let second = SpotifyToken::fixture().refresh_token("c2Vjb25k").build();
+
+        store.save("alice", &first).unwrap();
+        let sealed = store.inner().load("alice").unwrap().unwrap();
+        assert_ne!(sealed.access_token, first.access_token);
+        assert_ne!(sealed.refresh_token, first.refresh_token);
+        assert_eq!(sealed.expires_at, first.expires_at);
+        assert_eq!(store.load("alice").unwrap(), Some(first.clone()));
+
+        assert!(store.compare_and_swap("alice", &first, &second).unwrap());
+        assert!(!store.compare_and_swap("alice", &first, &first).unwrap());
+        assert_eq!(store.load("alice").unwrap(), Some(second));
+    }
+
+    #[async_std::test]
+    async fn test_sync_store_is_async_store() {
+        use super::AsyncTokenStore;
+
+        let store = MemoryTokenStore::default();
+        let token = SpotifyToken::fixture().build();
+
+        AsyncTokenStore::save(&store, "alice", &token)
+            .await
+            .unwrap();
+        assert_eq!(
+            AsyncTokenStore::load(&store, "alice").await.unwrap(),
+            Some(token)
+        );
+    }
+}
@@ -0,0 +1,1074 @@
+//! Test utilities, only available with the ``test-util`` feature.
+
+#[cfg(any(test, feature = "wiremock"))]
+use crate::Endpoints;
+use crate::{
+    error::*, Clock, Header, HttpClient, HttpClientError, HttpResponse, SpotifyScope, SpotifyToken,
+    SystemClock, TokenRequest, TokenType,
+};
+use async_trait::async_trait;
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde_json::{json, Value};
+use snafu::ResultExt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+use url::Url;
+
+/// A scripted response of the [`MockHttpClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+    result: Result<HttpResponse, HttpClientError>,
+    delay: Option<Duration>,
+}
+
+impl MockResponse {
+    /// A response with the given status and raw body.
+    pub fn status(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            result: Ok(HttpResponse {
+                status,
+                body: body.into(),
+            }),
+            delay: None,
+        }
+    }
+
+    /// A response with the given status and JSON body.
+    pub fn json(status: u16, body: Value) -> Self {
+        Self::status(status, body.to_string())
+    }
+
+    /// A successful token response granting the given space separated scopes.
+    pub fn token(access_token: &str, refresh_token: Option<&str>, scope: &str) -> Self {
+        let mut body = json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "scope": scope,
+            "expires_in": 3600,
+        });
+
+        if let Some(x) = refresh_token {
+            body["refresh_token"] = json!(x);
+        }
+
+        Self::json(200, body)
+    }
+
+    /// An OAuth error response, e.g. ``invalid_grant`` with the description ``Refresh token revoked``.
+    pub fn oauth_error(error: &str, description: &str) -> Self {
+        Self::json(
+            400,
+            json!({
+                "error": error,
+                "error_description": description,
+            }),
+        )
+    }
+
+    /// A failure of the HTTP backend, no response is received at all.
+    pub fn transport_error(message: impl Into<String>) -> Self {
+        Self {
+            result: Err(HttpClientError::new(message)),
+            delay: None,
+        }
+    }
+
+    /// Wait for the given duration before returning the response.
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+/// An [`HttpClient`] returning scripted responses in order and recording all requests.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{refresh_access_token_with, testing::{MockHttpClient, MockResponse}, Endpoints};
+/// # #[async_std::main]
+/// # async fn main() {
+/// let http = MockHttpClient::new().with(MockResponse::token("NgCXRK", None, "streaming"));
+/// let token = refresh_access_token_with(&http, &Endpoints::default(), "NgAagA".into(), "id".into(), "secret".into()).await.unwrap();
+///
+/// # assert_eq!(token.access_token, "NgCXRK");
+/// assert_eq!(http.requests()[0].form_field("grant_type"), Some("refresh_token"));
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MockHttpClient {
+    responses: Mutex<VecDeque<MockResponse>>,
+    requests: Mutex<Vec<TokenRequest>>,
+}
+
+impl MockHttpClient {
+    /// Create a new client without any scripted responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a scripted response.
+    pub fn with(self, response: MockResponse) -> Self {
+        self.push(response);
+        self
+    }
+
+    /// Append a scripted response to a shared client.
+    pub fn push(&self, response: MockResponse) {
+        self.responses.lock().unwrap().push_back(response);
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<TokenRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The number of scripted responses which have not been returned yet.
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl HttpClient for MockHttpClient {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        self.requests.lock().unwrap().push(request.clone());
+
+        let response = self.responses.lock().unwrap().pop_front();
+        let response = response
+            .ok_or_else(|| HttpClientError::new("MockHttpClient has no scripted response left"))?;
+
+        if let Some(x) = response.delay {
+            futures_timer::Delay::new(x).await;
+        }
+
+        response.result
+    }
+
+    /// Web API requests get the next scripted response as well, but are not recorded.
+    async fn get(&self, _url: &str, _headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        let response = self.responses.lock().unwrap().pop_front();
+        let response = response
+            .ok_or_else(|| HttpClientError::new("MockHttpClient has no scripted response left"))?;
+
+        if let Some(x) = response.delay {
+            futures_timer::Delay::new(x).await;
+        }
+
+        response.result
+    }
+}
+
+/// A builder for valid [`SpotifyToken`] values, created by [`SpotifyToken::fixture`].
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{SpotifyScope, SpotifyToken};
+/// let token = SpotifyToken::fixture()
+///     .scope(vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail])
+///     .expired()
+///     .build();
+/// # assert!(token.is_expired());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenFixture {
+    access_token: String,
+    refresh_token: Option<String>,
+    scope: Vec<SpotifyScope>,
+    expires_in: u32,
+    expires_at: Option<i64>,
+}
+
+impl Default for TokenFixture {
+    fn default() -> Self {
+        Self {
+            access_token: "NgCXRKDjGUSKlfJODUjvnSUhcOMzYjw".to_string(),
+            refresh_token: Some("NgAagAHfVxDkSvCUm_SHo".to_string()),
+            scope: Vec::new(),
+            expires_in: 3600,
+            expires_at: None,
+        }
+    }
+}
+
+impl TokenFixture {
+    /// Set the access token.
+    pub fn access_token(mut self, access_token: impl Into<String>) -> Self {
+        self.access_token = access_token.into();
+        self
+    }
+
+    /// Set the refresh token.
+    pub fn refresh_token(mut self, refresh_token: impl Into<String>) -> Self {
+        self.refresh_token = Some(refresh_token.into());
+        self
+    }
+
+    /// Build a token without a refresh token.
+    pub fn without_refresh_token(mut self) -> Self {
+        self.refresh_token = None;
+        self
+    }
+
+    /// Set the granted scopes.
+    pub fn scope(mut self, scope: Vec<SpotifyScope>) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Let the token expire the given number of seconds after it is built, 3600 by default.
+    pub fn expires_in(mut self, expires_in: u32) -> Self {
+        self.expires_in = expires_in;
+        self.expires_at = None;
+        self
+    }
+
+    /// Set a fixed expiry timestamp instead of deriving it from ``expires_in``.
+    pub fn expires_at(mut self, expires_at: i64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Build an already expired token.
+    pub fn expired(self) -> Self {
+        let expires_at = SystemClock.now().timestamp() - 1;
+        self.expires_at(expires_at)
+    }
+
+    /// Build the token, deriving its expiry from the system clock.
+    pub fn build(self) -> SpotifyToken {
+        self.build_with(&SystemClock)
+    }
+
+    /// Build the token, deriving its expiry from the given clock.
+    pub fn build_with(self, clock: &dyn Clock) -> SpotifyToken {
+        let mut token = SpotifyToken {
+            access_token: self.access_token,
+            token_type: TokenType::Bearer,
+            scope: self.scope,
+            expires_in: self.expires_in,
+            expires_at: self.expires_at,
+            refresh_token: self.refresh_token,
+            extra: Default::default(),
+        };
+
+        if self.expires_at.is_none() {
+            token.update_expires_at(clock);
+        }
+
+        token
+    }
+}
+
+/// A failure injected by the [`ChaosClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The request fails without a response, as if the connection timed out.
+    Timeout,
+    /// The server answers with ``500 Internal Server Error``.
+    ServerError,
+    /// The server answers with ``200 OK`` but a truncated JSON body.
+    MalformedJson,
+    /// The request is forwarded after waiting for the given duration.
+    Slow(Duration),
+}
+
+/// An [`HttpClient`] decorator injecting failures into the requests of another client.
+///
+/// Every fault is rolled independently per request with its probability, the first hit is applied.
+/// Requests without an injected failure are forwarded to the wrapped client.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{refresh_access_token_with, testing::{ChaosClient, Fault, MockHttpClient, MockResponse}, Endpoints};
+/// # #[async_std::main]
+/// # async fn main() {
+/// let http = ChaosClient::new(MockHttpClient::new().with(MockResponse::token("NgCXRK", None, "streaming")))
+///     .with_fault(Fault::ServerError, 0.5)
+///     .with_seed(7);
+///
+/// // Retry logic under test.
+/// let mut result = refresh_access_token_with(&http, &Endpoints::default(), "NgAagA".into(), "id".into(), "secret".into()).await;
+/// while result.is_err() {
+///     result = refresh_access_token_with(&http, &Endpoints::default(), "NgAagA".into(), "id".into(), "secret".into()).await;
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ChaosClient<C> {
+    inner: C,
+    faults: Vec<(Fault, f64)>,
+    rng: Mutex<StdRng>,
+}
+
+impl<C> ChaosClient<C> {
+    /// Wrap a client, no faults are injected until added with [`ChaosClient::with_fault`].
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            faults: Vec::new(),
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Inject the given fault with a probability between ``0.0`` and ``1.0``.
+    pub fn with_fault(mut self, fault: Fault, probability: f64) -> Self {
+        self.faults.push((fault, probability.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Seed the random number generator, making the injected failures reproducible.
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// The wrapped client.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Roll all faults and return the first one hit.
+    fn roll(&self) -> Option<Fault> {
+        let mut rng = self.rng.lock().unwrap();
+
+        self.faults
+            .iter()
+            .find(|x| rng.gen_bool(x.1))
+            .map(|x| x.0.clone())
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for ChaosClient<C> {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        match self.roll() {
+            None => self.inner.fetch_token(request).await,
+            Some(Fault::Timeout) => {
+                Err(HttpClientError::new("injected fault: operation timed out"))
+            }
+            Some(Fault::ServerError) => Ok(HttpResponse {
+                status: 500,
+                body: "Internal Server Error".to_string(),
+            }),
+            Some(Fault::MalformedJson) => Ok(HttpResponse {
+                status: 200,
+                body: r#"{"access_token": "NgCXRK", "token_type": "Bea"#.to_string(),
+            }),
+            Some(Fault::Slow(x)) => {
+                futures_timer::Delay::new(x).await;
+                self.inner.fetch_token(request).await
+            }
+        }
+    }
+
+    /// Web API requests are passed on without faults.
+    async fn get(&self, url: &str, headers: &[Header]) -> Result<HttpResponse, HttpClientError> {
+        self.inner.get(url, headers).await
+    }
+}
+
+/// A local Spotify Accounts service mock with realistic ``/api/token`` behavior.
+///
+/// The server accepts the credentials and codes given by the associated constants:
+///
+/// * authorization codes are single use and require the registered redirect URI,
+/// * refresh responses omit the refresh token, like Spotify does,
+/// * [`MockAccountsServer::REVOKED_REFRESH_TOKEN`] is rejected with ``invalid_grant``,
+/// * [`MockAccountsServer::rate_limit`] makes the next requests fail with ``429 Too Many Requests``.
+///
+/// Only available with the ``wiremock`` feature.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{refresh_access_token_with, testing::MockAccountsServer, SurfClient};
+/// # #[async_std::main]
+/// # async fn main() {
+/// let server = MockAccountsServer::start().await;
+/// let token = refresh_access_token_with(
+///     &SurfClient::default(),
+///     &server.endpoints(),
+///     MockAccountsServer::REFRESH_TOKEN.into(),
+///     MockAccountsServer::CLIENT_ID.into(),
+///     MockAccountsServer::CLIENT_SECRET.into(),
+/// )
+/// .await
+/// .unwrap();
+/// # assert_eq!(token.access_token, MockAccountsServer::ACCESS_TOKEN);
+/// # }
+/// ```
+#[cfg(feature = "wiremock")]
+pub struct MockAccountsServer {
+    server: wiremock::MockServer,
+}
+
+#[cfg(feature = "wiremock")]
+impl MockAccountsServer {
+    /// The client ID accepted by the server.
+    pub const CLIENT_ID: &'static str = "mock-client-id";
+    /// The client secret accepted by the server.
+    pub const CLIENT_SECRET: &'static str = "mock-client-secret";
+    /// The redirect URI registered for the client.
+    pub const REDIRECT_URI: &'static str = "http://localhost:8888/callback";
+    /// The authorization code which can be exchanged once.
+    pub const AUTHORIZATION_CODE: &'static str = "mock-authorization-code";
+    /// The access token issued by the server.
+    pub const ACCESS_TOKEN: &'static str = "mock-access-token";
+    /// The refresh token issued on code exchange and accepted on refresh.
+    pub const REFRESH_TOKEN: &'static str = "mock-refresh-token";
+    /// A refresh token which has been revoked by the user.
+    pub const REVOKED_REFRESH_TOKEN: &'static str = "mock-revoked-refresh-token";
+    /// The scopes granted to issued tokens.
+    pub const SCOPE: &'static str = "streaming user-read-email";
+
+    /// Start a new server on a random local port.
+    pub async fn start() -> Self {
+        let server = wiremock::MockServer::start().await;
+
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/api/token"))
+            .respond_with(Self::model())
+            .mount(&server)
+            .await;
+
+        Self { server }
+    }
+
+    /// The token endpoint behavior, seeded with the associated constants.
+    fn model() -> AccountsModel {
+        let model = AccountsModel::new(
+            Self::CLIENT_ID,
+            Self::CLIENT_SECRET,
+            Self::ACCESS_TOKEN,
+            Self::REFRESH_TOKEN,
+        );
+        model.issue_code(Self::AUTHORIZATION_CODE, Self::REDIRECT_URI, Self::SCOPE);
+        model.accept_refresh_token(Self::REFRESH_TOKEN, Self::SCOPE);
+        model.revoke(Self::REVOKED_REFRESH_TOKEN);
+
+        model
+    }
+
+    /// The base URL of the server, e.g. ``http://127.0.0.1:34567``.
+    pub fn uri(&self) -> String {
+        self.server.uri()
+    }
+
+    /// The endpoints of this server, to be used instead of the Spotify Accounts service.
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints::from_base_url(&self.uri()).unwrap()
+    }
+
+    /// Answer the next ``times`` token requests with ``429 Too Many Requests``.
+    pub async fn rate_limit(&self, times: u64) {
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/api/token"))
+            .respond_with(wiremock::ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(times)
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// All requests received by the server so far.
+    pub async fn received_requests(&self) -> Vec<wiremock::Request> {
+        self.server.received_requests().await.unwrap_or_default()
+    }
+}
+
+/// An in-process model of the token endpoint of the Spotify Accounts service.
+///
+/// Authorization codes are single use and bound to a redirect URI, refresh responses omit the
+/// refresh token and revoked refresh tokens are rejected with ``invalid_grant``.
+#[derive(Debug)]
+struct AccountsModel {
+    client_id: String,
+    client_secret: String,
+    access_token: String,
+    refresh_token: String,
+    /// Pending authorization codes with their redirect URI and granted scope.
+    codes: Mutex<HashMap<String, (String, String)>>,
+    /// Valid refresh tokens with their granted scope.
+    refresh_tokens: Mutex<HashMap<String, String>>,
+    revoked: Mutex<HashSet<String>>,
+}
+
+impl AccountsModel {
+    fn new(client_id: &str, client_secret: &str, access_token: &str, refresh_token: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            access_token: access_token.to_string(),
+            refresh_token: refresh_token.to_string(),
+            codes: Default::default(),
+            refresh_tokens: Default::default(),
+            revoked: Default::default(),
+        }
+    }
+
+    fn issue_code(&self, code: &str, redirect_uri: &str, scope: &str) {
+        self.codes.lock().unwrap().insert(
+            code.to_string(),
+            (redirect_uri.to_string(), scope.to_string()),
+        );
+    }
+
+    fn accept_refresh_token(&self, refresh_token: &str, scope: &str) {
+        self.refresh_tokens
+            .lock()
+            .unwrap()
+            .insert(refresh_token.to_string(), scope.to_string());
+    }
+
+    fn revoke(&self, refresh_token: &str) {
+        self.refresh_tokens.lock().unwrap().remove(refresh_token);
+        self.revoked
+            .lock()
+            .unwrap()
+            .insert(refresh_token.to_string());
+    }
+
+    fn oauth_error(error: &str, description: &str) -> (u16, Value) {
+        (
+            400,
+            json!({
+                "error": error,
+                "error_description": description,
+            }),
+        )
+    }
+
+    fn token(&self, scope: &str, refresh_token: Option<&str>) -> (u16, Value) {
+        let mut body = json!({
+            "access_token": self.access_token,
+            "token_type": "Bearer",
+            "scope": scope,
+            "expires_in": 3600,
+        });
+
+        if let Some(x) = refresh_token {
+            body["refresh_token"] = json!(x);
+        }
+
+        (200, body)
+    }
+
+    /// Answer a token request with its status code and JSON body.
+    fn respond(&self, authorization: Option<&str>, form: &HashMap<String, String>) -> (u16, Value) {
+        let credentials = base64::encode(format!("{}:{}", self.client_id, self.client_secret));
+
+        if authorization != Some(format!("Basic {}", credentials).as_str()) {
+            return Self::oauth_error("invalid_client", "Invalid client secret");
+        }
+
+        let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+
+        match field("grant_type") {
+            "authorization_code" => {
+                let mut codes = self.codes.lock().unwrap();
+                let (redirect_uri, scope) = match codes.get(field("code")) {
+                    None => {
+                        return Self::oauth_error("invalid_grant", "Invalid authorization code")
+                    }
+                    Some(x) => x.clone(),
+                };
+
+                if field("redirect_uri") != redirect_uri {
+                    return Self::oauth_error("invalid_grant", "Invalid redirect URI");
+                }
+
+                codes.remove(field("code"));
+                self.accept_refresh_token(&self.refresh_token, &scope);
+                self.token(&scope, Some(&self.refresh_token))
+            }
+            "refresh_token" => {
+                let refresh_token = field("refresh_token");
+                if self.revoked.lock().unwrap().contains(refresh_token) {
+                    return Self::oauth_error("invalid_grant", "Refresh token revoked");
+                }
+
+                let scope = self
+                    .refresh_tokens
+                    .lock()
+                    .unwrap()
+                    .get(refresh_token)
+                    .cloned();
+                match scope {
+                    None => Self::oauth_error("invalid_grant", "Invalid refresh token"),
+                    Some(x) => self.token(&x, None),
+                }
+            }
+            _ => Self::oauth_error(
+                "unsupported_grant_type",
+                "grant_type must be client_credentials, authorization_code or refresh_token",
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "wiremock")]
+impl wiremock::Respond for AccountsModel {
+    fn respond(&self, request: &wiremock::Request) -> wiremock::ResponseTemplate {
+        let authorization = request
+            .headers
+            .get("authorization")
+            .and_then(|x| x.to_str().ok());
+        let form: HashMap<String, String> = url::form_urlencoded::parse(&request.body)
+            .into_owned()
+            .collect();
+
+        let (status, body) = self.respond(authorization, &form);
+        wiremock::ResponseTemplate::new(status).set_body_json(body)
+    }
+}
+
+/// Simulates the whole authorization code flow in-process, without a browser or network.
+///
+/// The simulator plays both parts of the Spotify Accounts service: [`FlowSimulator::authorize`]
+/// takes the authorization URL and returns the URL the browser would be redirected to after the
+/// user made a decision, and as an [`HttpClient`] it exchanges the issued codes for tokens.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{convert_callback_into_token_with, testing::FlowSimulator, Endpoints, SpotifyAuth, SpotifyCallback, SpotifyScope};
+/// # use std::str::FromStr;
+/// # #[async_std::main]
+/// # async fn main() {
+/// let auth = SpotifyAuth::new("id".into(), "secret".into(), "code".into(), "http://localhost:8000/callback".into(), vec![SpotifyScope::Streaming], false);
+/// let simulator = FlowSimulator::new("id", "secret");
+///
+/// // The user grants access in the "browser".
+/// let redirect = simulator.authorize(&auth.authorize_url().unwrap()).unwrap();
+///
+/// let callback = SpotifyCallback::from_str(&redirect).unwrap();
+/// let token = convert_callback_into_token_with(&simulator, &Endpoints::default(), callback, auth.client_id, auth.client_secret, auth.redirect_uri).await.unwrap();
+/// # assert_eq!(token.scope, vec![SpotifyScope::Streaming]);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FlowSimulator {
+    model: AccountsModel,
+    deny: bool,
+    state_override: Option<String>,
+}
+
+impl FlowSimulator {
+    /// The access token issued by the simulator.
+    pub const ACCESS_TOKEN: &'static str = "simulated-access-token";
+    /// The refresh token issued by the simulator.
+    pub const REFRESH_TOKEN: &'static str = "simulated-refresh-token";
+
+    /// Create a simulator accepting the given client credentials.
+    pub fn new(client_id: &str, client_secret: &str) -> Self {
+        Self {
+            model: AccountsModel::new(
+                client_id,
+                client_secret,
+                Self::ACCESS_TOKEN,
+                Self::REFRESH_TOKEN,
+            ),
+            deny: false,
+            state_override: None,
+        }
+    }
+
+    /// Let the user decline the authorization request.
+    pub fn deny_consent(mut self) -> Self {
+        self.deny = true;
+        self
+    }
+
+    /// Redirect with the given ``state`` instead of the requested one, e.g. to test CSRF protection.
+    pub fn tamper_state(mut self, state: impl Into<String>) -> Self {
+        self.state_override = Some(state.into());
+        self
+    }
+
+    /// Revoke a refresh token, as if the user removed the application.
+    pub fn revoke(&self, refresh_token: &str) {
+        self.model.revoke(refresh_token);
+    }
+
+    /// Process the authorization URL and return the redirect URL of the user's decision.
+    ///
+    /// Requests with an unknown client ID or without a redirect URI are not redirected by Spotify,
+    /// an error is returned for them.
+    pub fn authorize(&self, authorize_url: &str) -> SpotifyResult<String> {
+        let url = Url::parse(authorize_url).context(UrlError)?;
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let field = |name: &str| query.get(name).map(String::as_str);
+
+        if field("client_id") != Some(self.model.client_id.as_str()) {
+            return Err(SpotifyError::Protocol {
+                status: Some(400),
+                error: "invalid_client".to_string(),
+                description: Some("Invalid client".to_string()),
+            });
+        }
+
+        let redirect_uri = field("redirect_uri").ok_or_else(|| SpotifyError::Protocol {
+            status: Some(400),
+            error: "invalid_request".to_string(),
+            description: Some("Missing required parameter: redirect_uri".to_string()),
+        })?;
+        let mut redirect = Url::parse(redirect_uri).context(UrlError)?;
+
+        {
+            let mut pairs = redirect.query_pairs_mut();
+
+            if field("response_type") != Some("code") {
+                pairs.append_pair("error", "unsupported_response_type");
+            } else if self.deny {
+                pairs.append_pair("error", "access_denied");
+            } else {
+                let suffix: String = rand::thread_rng()
+                    .sample_iter(Alphanumeric)
+                    .take(16)
+                    .map(char::from)
+                    .collect();
+                let code = format!("simulated-code-{}", suffix);
+                self.model
+                    .issue_code(&code, redirect_uri, field("scope").unwrap_or_default());
+                pairs.append_pair("code", &code);
+            }
+
+            if let Some(x) = self.state_override.as_deref().or(field("state")) {
+                pairs.append_pair("state", x);
+            }
+        }
+
+        Ok(redirect.to_string())
+    }
+}
+
+#[async_trait]
+impl HttpClient for FlowSimulator {
+    async fn fetch_token(
+        &self,
+        request: &mut TokenRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let form: HashMap<String, String> = request.form().iter().cloned().collect();
+        let (status, body) = self.model.respond(request.header("authorization"), &form);
+
+        Ok(HttpResponse {
+            status,
+            body: body.to_string(),
+        })
+    }
+}
+
+/// proptest strategies generating callback URLs, only available with the ``proptest`` feature.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{testing::strategies, SpotifyCallback};
+/// # use std::str::FromStr;
+/// use proptest::prelude::*;
+///
+/// proptest!(|((url, expected) in strategies::callback_url())| {
+///     prop_assert_eq!(SpotifyCallback::from_str(&url).unwrap(), expected);
+/// });
+/// ```
+#[cfg(feature = "proptest")]
+pub mod strategies {
+    use crate::SpotifyCallback;
+    use proptest::prelude::*;
+    use url::form_urlencoded::Serializer;
+
+    /// Redirect URIs as they are registered in the Spotify dashboard.
+    pub fn redirect_uri() -> impl Strategy<Value = String> {
+        (
+            prop_oneof![Just("http"), Just("https")],
+            prop_oneof![Just("localhost"), Just("127.0.0.1"), Just("example.com")],
+            proptest::option::of(1024u16..),
+            "(/[a-z]{1,10}){0,3}",
+        )
+            .prop_map(|(scheme, host, port, path)| match port {
+                None => format!("{}://{}{}/callback", scheme, host, path),
+                Some(x) => format!("{}://{}:{}{}/callback", scheme, host, x, path),
+            })
+    }
+
+    /// Values of the ``code`` and ``state`` parameters, including characters which need encoding.
+    pub fn parameter_value() -> impl Strategy<Value = String> {
+        "[A-Za-z0-9_~. +/=-]{1,64}"
+    }
+
+    /// Valid callback URLs granting (``code``) or denying (``error``) access, together with the expected parse result.
+    pub fn callback_url() -> impl Strategy<Value = (String, SpotifyCallback)> {
+        (
+            redirect_uri(),
+            any::<bool>(),
+            parameter_value(),
+            parameter_value(),
+        )
+            .prop_map(|(base, granted, response, state)| {
+                let key = if granted { "code" } else { "error" };
+                let query = Serializer::new(String::new())
+                    .append_pair(key, &response)
+                    .append_pair("state", &state)
+                    .finish();
+
+                let expected = if granted {
+                    SpotifyCallback::new(Some(response), None, state)
+                } else {
+                    SpotifyCallback::new(None, Some(response), state)
+                };
+
+                (format!("{}?{}", base, query), expected)
+            })
+    }
+
+    /// Malformed and hostile callback URLs: missing state, duplicated parameters, fragment
+    /// callbacks, custom schemes, empty values and arbitrary text.
+    pub fn adversarial_callback_url() -> impl Strategy<Value = String> {
+        let value = parameter_value;
+
+        prop_oneof![
+            (redirect_uri(), value()).prop_map(|(base, code)| format!("{}?code={}", base, code)),
+            (redirect_uri(), value(), value(), value()).prop_map(|(base, a, b, state)| format!(
+                "{}?code={}&code={}&state={}",
+                base, a, b, state
+            )),
+            (redirect_uri(), value(), value(), value()).prop_map(|(base, code, a, b)| format!(
+                "{}?code={}&state={}&state={}",
+                base, code, a, b
+            )),
+            (redirect_uri(), value(), value())
+                .prop_map(|(base, code, state)| format!("{}#code={}&state={}", base, code, state)),
+            ("[a-z][a-z0-9+.-]{0,10}", value(), value()).prop_map(|(scheme, code, state)| format!(
+                "{}://callback?code={}&state={}",
+                scheme, code, state
+            )),
+            redirect_uri().prop_map(|base| format!("{}?code=&state=", base)),
+            (redirect_uri(), value(), value(), value()).prop_map(|(base, code, error, state)| {
+                format!("{}?error={}&code={}&state={}", base, error, code, state)
+            }),
+            "\\PC*",
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{refresh_access_token_with, ErrorCategory, SpotifyError};
+    use std::str::FromStr;
+
+    async fn refresh<C: HttpClient>(http: &C) -> crate::SpotifyResult<crate::SpotifyToken> {
+        refresh_access_token_with(
+            http,
+            &Endpoints::default(),
+            "NgAagA".into(),
+            "id".into(),
+            "secret".into(),
+        )
+        .await
+    }
+
+    fn chaos(fault: Fault) -> ChaosClient<MockHttpClient> {
+        ChaosClient::new(MockHttpClient::new().with(MockResponse::token("NgCXRK", None, "")))
+            .with_fault(fault, 1.0)
+    }
+
+    #[async_std::test]
+    async fn test_chaos_without_faults_forwards() {
+        let http =
+            ChaosClient::new(MockHttpClient::new().with(MockResponse::token("NgCXRK", None, "")));
+
+        assert_eq!(refresh(&http).await.unwrap().access_token, "NgCXRK");
+        assert_eq!(http.inner().remaining(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_chaos_faults() {
+        let err = refresh(&chaos(Fault::Timeout)).await.unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Transport);
+
+        let err = refresh(&chaos(Fault::ServerError)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SpotifyError::UnexpectedResponse { status: 500, .. }
+        ));
+
+        let err = refresh(&chaos(Fault::MalformedJson)).await.unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Deserialization);
+
+        let http = chaos(Fault::Slow(Duration::from_millis(10)));
+        assert!(refresh(&http).await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_simulated_flow() {
+        let auth = crate::SpotifyAuth {
+            client_id: "id".into(),
+            client_secret: "secret".into(),
+            response_type: "code".into(),
+            redirect_uri: Url::parse("http://localhost:8000/callback?app=1").unwrap(),
+            redirect_uris: Vec::new(),
+            state: "Ys6Lq0R2".into(),
+            state_generator: std::sync::Arc::new(|| "Ys6Lq0R2".to_string()),
+            scope: vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail],
+            show_dialog: false,
+            endpoints: Endpoints::default(),
+        };
+        let simulator = FlowSimulator::new("id", "secret");
+
+        let redirect = simulator.authorize(&auth.authorize_url().unwrap()).unwrap();
+        assert!(redirect.starts_with("http://localhost:8000/callback?app=1&code="));
+
+        let callback = crate::SpotifyCallback::from_str(&redirect).unwrap();
+        assert_eq!(callback.state, auth.state);
+
+        let token = crate::convert_callback_into_token_with(
+            &simulator,
+            &Endpoints::default(),
+            callback,
+            auth.client_id,
+            auth.client_secret,
+            auth.redirect_uri,
+        )
+        .await
+        .unwrap();
+        assert_eq!(token.access_token, FlowSimulator::ACCESS_TOKEN);
+        assert_eq!(
+            token.scope,
+            vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail]
+        );
+
+        let endpoints = Endpoints::default();
+        let refresh = || {
+            refresh_access_token_with(
+                &simulator,
+                &endpoints,
+                token.refresh_token.clone().unwrap(),
+                "id".into(),
+                "secret".into(),
+            )
+        };
+        assert_eq!(
+            refresh().await.unwrap().refresh_token.as_deref(),
+            Some(FlowSimulator::REFRESH_TOKEN)
+        );
+
+        simulator.revoke(FlowSimulator::REFRESH_TOKEN);
+        let err = refresh().await.unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+    }
+
+    #[test]
+    fn test_simulated_denial() {
+        let simulator = FlowSimulator::new("id", "secret").deny_consent();
+        let redirect = simulator
+            .authorize("https://accounts.spotify.com/authorize?client_id=id&response_type=code&redirect_uri=http%3A%2F%2Flocalhost%2Fcallback&state=sN")
+            .unwrap();
+
+        assert_eq!(
+            redirect,
+            "http://localhost/callback?error=access_denied&state=sN"
+        );
+    }
+
+    #[cfg(all(feature = "wiremock", feature = "surf"))]
+    async fn exchange(server: &MockAccountsServer) -> crate::SpotifyResult<crate::SpotifyToken> {
+        let callback = crate::SpotifyCallback::new(
+            Some(MockAccountsServer::AUTHORIZATION_CODE.to_string()),
+            None,
+            "state".to_string(),
+        );
+
+        crate::convert_callback_into_token_with(
+            &crate::SurfClient::default(),
+            &server.endpoints(),
+            callback,
+            MockAccountsServer::CLIENT_ID.into(),
+            MockAccountsServer::CLIENT_SECRET.into(),
+            url::Url::parse(MockAccountsServer::REDIRECT_URI).unwrap(),
+        )
+        .await
+    }
+
+    #[cfg(all(feature = "wiremock", feature = "surf"))]
+    #[async_std::test]
+    async fn test_code_is_single_use() {
+        let server = MockAccountsServer::start().await;
+
+        let token = exchange(&server).await.unwrap();
+        assert_eq!(
+            token.refresh_token.as_deref(),
+            Some(MockAccountsServer::REFRESH_TOKEN)
+        );
+
+        let err = exchange(&server).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Spotify authorization failure: invalid_grant (Invalid authorization code)"
+        );
+    }
+
+    #[cfg(all(feature = "wiremock", feature = "surf"))]
+    #[async_std::test]
+    async fn test_revoked_refresh_token() {
+        let server = MockAccountsServer::start().await;
+
+        let err = refresh_access_token_with(
+            &crate::SurfClient::default(),
+            &server.endpoints(),
+            MockAccountsServer::REVOKED_REFRESH_TOKEN.into(),
+            MockAccountsServer::CLIENT_ID.into(),
+            MockAccountsServer::CLIENT_SECRET.into(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+    }
+
+    #[cfg(all(feature = "wiremock", feature = "surf"))]
+    #[async_std::test]
+    async fn test_rate_limit() {
+        let server = MockAccountsServer::start().await;
+        server.rate_limit(1).await;
+
+        let err = exchange(&server).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SpotifyError::Protocol {
+                status: Some(429),
+                ..
+            }
+        ));
+        assert!(exchange(&server).await.is_ok());
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn test_parse_generated_callbacks((url, expected) in strategies::callback_url()) {
+            use std::str::FromStr;
+
+            proptest::prop_assert_eq!(crate::SpotifyCallback::from_str(&url).unwrap(), expected);
+        }
+
+        #[test]
+        fn test_parse_adversarial_callbacks(url in strategies::adversarial_callback_url()) {
+            use std::str::FromStr;
+
+            // Must never panic, errors are fine.
+            let _ = crate::SpotifyCallback::from_str(&url);
+        }
+    }
+}
@@ -1,7 +1,13 @@
-use crate::SpotifyScope;
-use serde::{Deserialize, Deserializer, Serialize};
-use serde_json::Value;
-use std::str::FromStr;
+use crate::{error::*, scopes_eq, Clock, SpotifyScope, SystemClock};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 /// The Spotify Token object.
 ///
@@ -24,49 +30,587 @@ use std::str::FromStr;
 /// convert_callback_into_token(callback, auth.client_id, auth.client_secret, auth.redirect_uri).await.unwrap();
 /// # Ok(()) }
 /// ```
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SpotifyToken {
     /// An access token that can be provided in subsequent calls, for example to Spotify Web API services.
     pub access_token: String,
     /// How the access token may be used.
-    pub token_type: String,
+    pub token_type: TokenType,
     /// A Vec of scopes which have been granted for this ``access_token``.
-    #[serde(deserialize_with = "deserialize_scope_field")]
+    #[serde(
+        default,
+        serialize_with = "serialize_scope_field",
+        deserialize_with = "deserialize_scope_field"
+    )]
     pub scope: Vec<SpotifyScope>,
     /// The time period (in seconds) for which the access token is valid.
+    ///
+    /// See [`SpotifyToken::lifetime`] for the same as a [`Duration`].
+    #[serde(deserialize_with = "deserialize_expires_in")]
     pub expires_in: u32,
     /// The timestamp for which the token will expire at.
     pub expires_at: Option<i64>,
     /// A token that can be sent to the Spotify Accounts service in place of an authorization code to request a new ``access_token``.
-    pub refresh_token: String,
+    ///
+    /// Refresh responses may omit it, the refresh functions of this library then keep the previous one.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Fields not known to this library, kept so tokens survive being cached and serialized again.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Expiry helper functions for SpotifyToken.
+impl SpotifyToken {
+    /// Start building a valid token for tests, see [`TokenFixture`](crate::testing::TokenFixture).
+    ///
+    /// Only available with the ``test-util`` feature.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn fixture() -> crate::testing::TokenFixture {
+        crate::testing::TokenFixture::default()
+    }
+
+    /// Set ``expires_at`` to ``expires_in`` seconds after the current time of the given clock.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{MockClock, SpotifyToken};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut token: SpotifyToken = serde_json::from_str(r#"{"access_token": "a", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "r"}"#).unwrap();
+    /// let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+    /// token.update_expires_at(&clock);
+    /// # assert_eq!(token.expires_at, Some(1_600_003_600));
+    /// ```
+    pub fn update_expires_at(&mut self, clock: &dyn Clock) {
+        self.expires_at = Some(clock.timestamp_in(self.expires_in));
+    }
+
+    /// The time period for which the access token is valid, ``expires_in`` as a [`Duration`].
+    pub fn lifetime(&self) -> Duration {
+        Duration::from_secs(u64::from(self.expires_in))
+    }
+
+    /// The expiry timestamp as a [`SystemTime`].
+    ///
+    /// Returns ``None`` if the token has no ``expires_at`` timestamp.
+    pub fn expires_at_system_time(&self) -> Option<SystemTime> {
+        let expires_at = self.expires_at?;
+        let offset = Duration::from_secs(expires_at.unsigned_abs());
+
+        if expires_at >= 0 {
+            UNIX_EPOCH.checked_add(offset)
+        } else {
+            UNIX_EPOCH.checked_sub(offset)
+        }
+    }
+
+    /// The expiry timestamp as a [`DateTime`].
+    ///
+    /// Together with [`SpotifyToken::scope_set`] this covers the fields of Web API clients which
+    /// store the expiry as a date and the scopes as a set of strings. The ``rspotify`` feature
+    /// converts to and from rspotify's ``Token`` directly.
+    ///
+    /// Returns ``None`` if the token has no ``expires_at`` timestamp.
+    pub fn expires_at_datetime(&self) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(self.expires_at?, 0).single()
+    }
+
+    /// The granted scopes as a set of their Spotify names, e.g. ``user-read-email``.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::SpotifyToken;
+    /// let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "a", "token_type": "Bearer", "scope": "streaming user-read-email", "expires_in": 3600, "refresh_token": "r"}"#).unwrap();
+    /// # assert!(token.scope_set().contains("user-read-email"));
+    /// ```
+    pub fn scope_set(&self) -> HashSet<String> {
+        self.scope.iter().map(ToString::to_string).collect()
+    }
+
+    /// Fail unless exactly the ``requested`` scopes were granted, in any order.
+    ///
+    /// Users may deselect scopes in the consent dialog, strict applications reject such tokens
+    /// instead of failing later in a Web API call.
+    pub fn ensure_scopes(&self, requested: &[SpotifyScope]) -> SpotifyResult<()> {
+        if !scopes_eq(&self.scope, requested) {
+            return Err(SpotifyError::TokenFailure {
+                context: "The granted scopes differ from the requested scopes.",
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Whether the access token has expired according to the system clock.
+    ///
+    /// Tokens without an ``expires_at`` timestamp are considered expired.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_with(&SystemClock)
+    }
+
+    /// Whether the access token has expired according to the given clock.
+    ///
+    /// Tokens without an ``expires_at`` timestamp are considered expired. A
+    /// [`TokenManager`](crate::TokenManager) also checks the tokens it manages against the
+    /// monotonic clock.
+    pub fn is_expired_with(&self, clock: &dyn Clock) -> bool {
+        self.expires_within_with(Duration::ZERO, clock)
+    }
+
+    /// Whether the access token expires within ``margin`` according to the system clock.
+    ///
+    /// Refreshing tokens which expire within e.g. a minute keeps requests from failing because
+    /// the token expired on the way to the Web API.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_within_with(margin, &SystemClock)
+    }
+
+    /// Whether the access token expires within ``margin`` according to the given clock.
+    ///
+    /// Expired tokens and tokens without an ``expires_at`` timestamp always do.
+    pub fn expires_within_with(&self, margin: Duration, clock: &dyn Clock) -> bool {
+        self.remaining_with(clock).is_none_or(|x| x <= margin)
+    }
+
+    /// The remaining lifetime of the access token in a human readable form, e.g. ``58m``.
+    ///
+    /// Returns ``None`` if the token has no ``expires_at`` timestamp.
+    pub fn expires_in_human(&self) -> Option<String> {
+        self.expires_in_human_with(&SystemClock)
+    }
+
+    /// The remaining lifetime of the access token according to the given clock in a human readable form.
+    ///
+    /// Lifetimes of a minute or longer are truncated to full minutes, expired tokens yield ``0s``.
+    /// Returns ``None`` if the token has no ``expires_at`` timestamp.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{MockClock, SpotifyToken};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut token: SpotifyToken = serde_json::from_str(r#"{"access_token": "a", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "r"}"#).unwrap();
+    /// let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+    /// token.update_expires_at(&clock);
+    /// # assert_eq!(token.expires_in_human_with(&clock).unwrap(), "1h");
+    /// ```
+    pub fn expires_in_human_with(&self, clock: &dyn Clock) -> Option<String> {
+        let remaining = self.remaining_with(clock)?.as_secs();
+        let truncated = if remaining >= 60 {
+            remaining - remaining % 60
+        } else {
+            remaining
+        };
+
+        Some(humantime::format_duration(Duration::from_secs(truncated)).to_string())
+    }
+
+    /// The remaining lifetime of the access token according to the wall clock, zero once it has
+    /// expired.
+    pub(crate) fn remaining_with(&self, clock: &dyn Clock) -> Option<Duration> {
+        let remaining = self.expires_at? - clock.now().timestamp();
+        Some(Duration::from_secs(remaining.max(0) as u64))
+    }
+}
+
+/// Tokens are equal if all fields are, the scopes are compared as sets, see [`scopes_eq`].
+impl PartialEq for SpotifyToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.access_token == other.access_token
+            && self.token_type == other.token_type
+            && scopes_eq(&self.scope, &other.scope)
+            && self.expires_in == other.expires_in
+            && self.expires_at == other.expires_at
+            && self.refresh_token == other.refresh_token
+            && self.extra == other.extra
+    }
+}
+
+/// A summary safe for logs and examples, e.g. ``Bearer token, 2 scopes, expires in 58m``.
+///
+/// Only the token type, the number of scopes and the expiry are shown, never the access or
+/// refresh token.
+impl fmt::Display for SpotifyToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} token, {} scope{}, ",
+            self.token_type,
+            self.scope.len(),
+            if self.scope.len() == 1 { "" } else { "s" }
+        )?;
+
+        match self.expires_in_human() {
+            None => write!(f, "expiry unknown"),
+            Some(_) if self.is_expired() => write!(f, "expired"),
+            Some(x) => write!(f, "expires in {}", x),
+        }
+    }
+}
+
+/// The type of an access token, i.e. how it has to be sent to the Web API.
+///
+/// Parsing is case-insensitive, Spotify only issues ``Bearer`` tokens.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::TokenType;
+/// # use std::str::FromStr;
+/// assert_eq!(TokenType::from_str("bearer").unwrap(), TokenType::Bearer);
+/// assert_eq!(TokenType::Bearer.to_string(), "Bearer");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TokenType {
+    /// A bearer token, sent as ``Authorization: Bearer <token>``.
+    Bearer,
+    /// Any other token type, as returned by the server.
+    Other(String),
+}
+
+impl FromStr for TokenType {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("bearer") {
+            Ok(TokenType::Bearer)
+        } else {
+            Ok(TokenType::Other(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenType::Bearer => f.write_str("Bearer"),
+            TokenType::Other(x) => f.write_str(x),
+        }
+    }
+}
+
+impl Serialize for TokenType {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenType {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(de)?;
+        Ok(TokenType::from_str(&value).unwrap_or_else(|x| match x {}))
+    }
+}
+
+/// A token in a form suited for caches inside configuration files (TOML, YAML, JSON).
+///
+/// The values needed to use and refresh the token are kept under stable field names: the scopes
+/// are stored space separated like Spotify returns them and the expiry as unix timestamp. Fields
+/// of the token response unknown to this library are kept next to them.
+///
+/// Caches are written with ``version = 2`` (see [`CACHE_VERSION`]). Caches without a version, as
+/// written by earlier releases, are migrated when they are read, serialized ``SpotifyToken`` values
+/// included. Caches written by a newer release fail to parse instead of losing fields. The expiry
+/// may also be an RFC 3339 date, e.g. when the cache was edited by hand or written with
+/// [`CachedToken::with_rfc3339_expiry`].
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{CachedToken, SpotifyToken, TokenType};
+/// let cached = CachedToken {
+///     access_token: "NgCXRK".into(),
+///     token_type: TokenType::Bearer,
+///     scope: vec![spotify_oauth::SpotifyScope::Streaming],
+///     expires_at: Some(1_600_003_600),
+///     refresh_token: Some("NgAagA".into()),
+///     extra: Default::default(),
+/// };
+/// let token: SpotifyToken = cached.into();
+/// # assert!(token.is_expired());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(into = "CacheV2", try_from = "Value")]
+pub struct CachedToken {
+    /// The access token.
+    pub access_token: String,
+    /// How the access token may be used.
+    pub token_type: TokenType,
+    /// The granted scopes.
+    pub scope: Vec<SpotifyScope>,
+    /// The timestamp for which the token will expire at.
+    pub expires_at: Option<i64>,
+    /// The refresh token.
+    pub refresh_token: Option<String>,
+    /// Fields not known to this library, see [`SpotifyToken::extra`].
+    pub extra: Map<String, Value>,
+}
+
+/// Cached tokens are equal if all fields are, the scopes are compared as sets.
+impl PartialEq for CachedToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.access_token == other.access_token
+            && self.token_type == other.token_type
+            && scopes_eq(&self.scope, &other.scope)
+            && self.expires_at == other.expires_at
+            && self.refresh_token == other.refresh_token
+            && self.extra == other.extra
+    }
+}
+
+impl CachedToken {
+    /// The cache with ``expires_at`` written as RFC 3339 date instead of a unix timestamp, e.g.
+    /// ``2020-09-13T12:26:40Z``, for caches read by humans and external tools.
+    ///
+    /// Caches accept both forms when they are read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use spotify_oauth::{CachedToken, SpotifyToken};
+    /// # let token: SpotifyToken = serde_json::from_str(r#"{"access_token": "a", "token_type": "Bearer", "scope": "", "expires_in": 3600, "expires_at": 1600000000}"#).unwrap();
+    /// let cached = CachedToken::from(token);
+    /// let text = toml::to_string(&cached.with_rfc3339_expiry()).unwrap();
+    /// # assert!(text.contains(r#"expires_at = "2020-09-13T12:26:40Z""#), "{}", text);
+    /// # assert_eq!(toml::from_str::<CachedToken>(&text).unwrap(), cached);
+    /// ```
+    pub fn with_rfc3339_expiry(&self) -> impl Serialize {
+        let mut cache = CacheV2::from(self.clone());
+        cache.expires_at.dated = true;
+        cache
+    }
+}
+
+/// The version of the cache layout written by [`CachedToken`].
+pub const CACHE_VERSION: u64 = 2;
+
+/// The cache layout of version 2.
+#[derive(Serialize, Deserialize)]
+struct CacheV2 {
+    version: u64,
+    access_token: String,
+    token_type: TokenType,
+    #[serde(
+        serialize_with = "serialize_scope_field",
+        deserialize_with = "deserialize_scope_field"
+    )]
+    scope: Vec<SpotifyScope>,
+    #[serde(default, skip_serializing_if = "CacheExpiry::is_none")]
+    expires_at: CacheExpiry,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// The expiry of a cache, a unix timestamp or an RFC 3339 date when read, see [`crate::rfc3339`].
+#[derive(Default)]
+struct CacheExpiry {
+    at: Option<i64>,
+    /// Write the expiry as RFC 3339 date instead of a unix timestamp.
+    dated: bool,
+}
+
+impl CacheExpiry {
+    fn is_none(&self) -> bool {
+        self.at.is_none()
+    }
+}
+
+impl Serialize for CacheExpiry {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        if self.dated {
+            crate::rfc3339::serialize(&self.at, ser)
+        } else {
+            self.at.serialize(ser)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CacheExpiry {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            at: crate::rfc3339::deserialize(de)?,
+            dated: false,
+        })
+    }
+}
+
+impl From<CachedToken> for CacheV2 {
+    fn from(token: CachedToken) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            access_token: token.access_token,
+            token_type: token.token_type,
+            scope: token.scope,
+            expires_at: CacheExpiry {
+                at: token.expires_at,
+                dated: false,
+            },
+            refresh_token: token.refresh_token,
+            extra: token.extra,
+        }
+    }
+}
+
+/// Reads a cache of any known version, migrating it to the current layout.
+impl TryFrom<Value> for CachedToken {
+    type Error = String;
+
+    fn try_from(mut cache: Value) -> Result<Self, Self::Error> {
+        let version = match cache.get("version") {
+            None => 1,
+            Some(x) => x
+                .as_u64()
+                .ok_or_else(|| format!("invalid token cache version {}", x))?,
+        };
+
+        if version > CACHE_VERSION {
+            return Err(format!(
+                "token cache version {} was written by a newer release, version {} is supported",
+                version, CACHE_VERSION
+            ));
+        }
+        if version < 2 {
+            migrate_v1(&mut cache);
+        }
+
+        let cache: CacheV2 = serde_json::from_value(cache).map_err(|x| x.to_string())?;
+        Ok(Self {
+            access_token: cache.access_token,
+            token_type: cache.token_type,
+            scope: cache.scope,
+            expires_at: cache.expires_at.at,
+            refresh_token: cache.refresh_token,
+            extra: cache.extra,
+        })
+    }
+}
+
+/// Migrate a cache without version to version 2.
+///
+/// Version 1 has the fields of version 2 and is read as is: scope lists are accepted by the scope
+/// parser and the unknown fields of serialized ``SpotifyToken`` values are kept. Their
+/// ``expires_in`` is dropped, it is derived from ``expires_at`` again.
+fn migrate_v1(cache: &mut Value) {
+    if let Value::Object(fields) = cache {
+        fields.remove("expires_in");
+        fields.insert("version".into(), Value::from(2));
+    }
+}
+
+impl From<SpotifyToken> for CachedToken {
+    fn from(token: SpotifyToken) -> Self {
+        Self {
+            access_token: token.access_token,
+            token_type: token.token_type,
+            scope: token.scope,
+            expires_at: token.expires_at,
+            refresh_token: token.refresh_token,
+            extra: token.extra,
+        }
+    }
+}
+
+/// Restores the token, ``expires_in`` is set to the lifetime remaining according to the system clock.
+impl From<CachedToken> for SpotifyToken {
+    fn from(cached: CachedToken) -> Self {
+        let remaining = cached
+            .expires_at
+            .map(|x| x - SystemClock.now().timestamp())
+            .unwrap_or_default();
+
+        Self {
+            access_token: cached.access_token,
+            token_type: cached.token_type,
+            scope: cached.scope,
+            expires_in: remaining.clamp(0, i64::from(u32::MAX)) as u32,
+            expires_at: cached.expires_at,
+            refresh_token: cached.refresh_token,
+            extra: cached.extra,
+        }
+    }
+}
+
+/// Parse space separated scopes, the ones unknown to this library become [`SpotifyScope::Other`].
+pub(crate) fn parse_scopes(scope: &str) -> Vec<SpotifyScope> {
+    scope
+        .split_whitespace()
+        .map(|x| SpotifyScope::from_str(x).unwrap_or_else(|_| SpotifyScope::Other(x.into())))
+        .collect()
+}
+
+/// Serialize the scopes space separated, the format Spotify uses.
+fn serialize_scope_field<S>(scope: &[SpotifyScope], ser: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let joined = scope
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    ser.serialize_str(&joined)
 }
 
 /// Custom parsing function for converting a vector of string scopes into SpotifyScope Enums using Serde.
-/// If scope is empty it will return an empty vector.
-fn deserialize_scope_field<'de, D>(de: D) -> Result<Vec<SpotifyScope>, D::Error>
+/// If scope is empty it will return an empty vector, scopes unknown to this library are kept as
+/// [`SpotifyScope::Other`].
+///
+/// Lists of scopes, as written by earlier versions of this crate, are accepted as well.
+pub(crate) fn deserialize_scope_field<'de, D>(de: D) -> Result<Vec<SpotifyScope>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let result: Value = Deserialize::deserialize(de)?;
     match result {
-        Value::String(ref s) => {
-            let split: Vec<&str> = s.split_whitespace().collect();
-            let mut parsed: Vec<SpotifyScope> = Vec::new();
+        Value::String(ref s) => Ok(parse_scopes(s)),
+        Value::Array(items) => Ok(items
+            .into_iter()
+            .filter_map(|x| match x {
+                // Variant names, e.g. ``Streaming``, come first: every name parses as a scope.
+                Value::String(ref s) => serde_json::from_value(x.clone())
+                    .ok()
+                    .or_else(|| SpotifyScope::from_str(s).ok()),
+                _ => serde_json::from_value(x).ok(),
+            })
+            .collect()),
+        _ => Ok(vec![]),
+    }
+}
 
-            for x in split {
-                parsed.push(SpotifyScope::from_str(x).unwrap());
-            }
+/// Custom parsing function for the lifetime of a token in seconds.
+///
+/// Some proxies and mocks send ``expires_in`` as a string or a float, e.g. ``"3600"`` or
+/// ``3600.0``, both are accepted. Fractions of a second are dropped.
+pub(crate) fn deserialize_expires_in<'de, D>(de: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Value = Deserialize::deserialize(de)?;
+    let seconds = match value {
+        Value::Number(ref x) => x.as_f64(),
+        Value::String(ref x) => x.trim().parse::<f64>().ok(),
+        _ => None,
+    };
 
-            Ok(parsed)
-        }
-        _ => Ok(vec![]),
+    match seconds {
+        Some(x) if x.is_finite() && x >= 0.0 => Ok(x.min(f64::from(u32::MAX)) as u32),
+        _ => Err(serde::de::Error::custom(format!(
+            "invalid expires_in {}, expected a number of seconds",
+            value
+        ))),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::datetime_to_timestamp;
+    use crate::{datetime_to_timestamp, MockClock};
+    use chrono::{Duration, TimeZone, Utc};
 
     #[test]
     fn test_token_parse() {
@@ -85,13 +629,277 @@ mod tests {
         assert_eq!(
             SpotifyToken {
                 access_token: "NgCXRKDjGUSKlfJODUjvnSUhcOMzYjw".to_string(),
-                token_type: "Bearer".to_string(),
+                token_type: TokenType::Bearer,
                 scope: vec![SpotifyScope::UserReadPrivate, SpotifyScope::UserReadEmail],
                 expires_in: 3600,
                 expires_at: Some(timestamp),
-                refresh_token: "NgAagAHfVxDkSvCUm_SHo".to_string()
+                refresh_token: Some("NgAagAHfVxDkSvCUm_SHo".to_string()),
+                extra: Map::new(),
             },
             token
         );
     }
+
+    #[test]
+    fn test_token_toml_round_trip() {
+        let token = SpotifyToken::fixture()
+            .scope(vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail])
+            .expires_at(1_600_003_600)
+            .build();
+
+        let text = toml::to_string(&token).unwrap();
+        assert!(text.contains(r#"scope = "streaming user-read-email""#));
+        assert_eq!(toml::from_str::<SpotifyToken>(&text).unwrap(), token);
+
+        let cached = CachedToken::from(token);
+        let text = toml::to_string(&cached).unwrap();
+        assert_eq!(toml::from_str::<CachedToken>(&text).unwrap(), cached);
+    }
+
+    #[test]
+    fn test_cached_token_versions() {
+        let cached = CachedToken::from(
+            SpotifyToken::fixture()
+                .access_token("NgCXRK")
+                .refresh_token("NgAagA")
+                .scope(vec![SpotifyScope::Streaming])
+                .expires_at(1_600_003_600)
+                .build(),
+        );
+        let text = serde_json::to_string(&cached).unwrap();
+        assert!(text.starts_with(r#"{"version":2,"#), "{}", text);
+
+        for legacy in [
+            r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": "streaming", "expires_at": 1600003600, "refresh_token": "NgAagA"}"#,
+            r#"{"access_token": "NgCXRK", "token_type": "Bearer", "scope": ["Streaming"], "expires_in": 3600, "expires_at": 1600003600, "refresh_token": "NgAagA", "market": "DE"}"#,
+        ] {
+            let mut token: CachedToken = serde_json::from_str(legacy).unwrap();
+            let market = token.extra.remove("market");
+            assert_eq!(market.is_some(), legacy.contains("market"), "{}", legacy);
+            assert_eq!(token, cached, "{}", legacy);
+        }
+
+        let dated = text.replace("1600003600", r#""2020-09-13T13:26:40Z""#);
+        assert_eq!(serde_json::from_str::<CachedToken>(&dated).unwrap(), cached);
+        assert_eq!(
+            serde_json::to_string(&cached.with_rfc3339_expiry()).unwrap(),
+            dated
+        );
+
+        let newer = text.replace(r#""version":2"#, r#""version":3"#);
+        let err = serde_json::from_str::<CachedToken>(&newer).unwrap_err();
+        assert!(err.to_string().contains("newer release"), "{}", err);
+    }
+
+    #[test]
+    fn test_token_yaml_round_trip() {
+        let token = SpotifyToken::fixture()
+            .scope(vec![SpotifyScope::Streaming])
+            .build();
+
+        let text = serde_yaml::to_string(&token).unwrap();
+        assert_eq!(serde_yaml::from_str::<SpotifyToken>(&text).unwrap(), token);
+
+        let mut cached = CachedToken::from(token);
+        cached.expires_at = None;
+        let text = serde_yaml::to_string(&cached).unwrap();
+        assert!(!text.contains("expires_at"));
+        assert_eq!(serde_yaml::from_str::<CachedToken>(&text).unwrap(), cached);
+    }
+
+    #[test]
+    fn test_token_equality_ignores_scope_order() {
+        let token = SpotifyToken::fixture()
+            .scope(vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail])
+            .build();
+        let mut reordered = token.clone();
+        reordered.scope.reverse();
+        assert_eq!(token, reordered);
+        assert_eq!(CachedToken::from(token.clone()), reordered.clone().into());
+
+        reordered.scope.pop();
+        assert_ne!(token, reordered);
+        assert!(token
+            .ensure_scopes(&[SpotifyScope::UserReadEmail, SpotifyScope::Streaming])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_token_legacy_scope_list() {
+        let token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "Bearer", "scope": ["Streaming", "user-read-email"], "expires_in": 3600, "refresh_token": "r"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            token.scope,
+            vec![SpotifyScope::Streaming, SpotifyScope::UserReadEmail]
+        );
+    }
+
+    #[test]
+    fn test_token_unknown_scopes() {
+        for scope in [
+            r#""ugc-image-upload streaming app-remote-future""#,
+            r#"["ugc-image-upload", "Streaming", "app-remote-future", 42, null]"#,
+            r#"["UgcImageUpload", "streaming", {"Other": "app-remote-future"}]"#,
+        ] {
+            let token: SpotifyToken = serde_json::from_str(&format!(
+                r#"{{"access_token": "a", "token_type": "Bearer", "scope": {}, "expires_in": 3600}}"#,
+                scope
+            ))
+            .unwrap();
+            let expected = vec![
+                SpotifyScope::UgcImageUpload,
+                SpotifyScope::Streaming,
+                SpotifyScope::Other("app-remote-future".into()),
+            ];
+            assert_eq!(token.scope, expected, "{}", scope);
+
+            let json = serde_json::to_value(&token).unwrap();
+            assert_eq!(
+                json["scope"],
+                "ugc-image-upload streaming app-remote-future"
+            );
+            let cached = serde_json::to_value(CachedToken::from(token)).unwrap();
+            let cached: CachedToken = serde_json::from_value(cached).unwrap();
+            assert_eq!(cached.scope, expected);
+        }
+    }
+
+    #[test]
+    fn test_token_parse_minimal_response() {
+        let token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "Bearer", "expires_in": 3600}"#,
+        )
+        .unwrap();
+        assert!(token.scope.is_empty());
+        assert_eq!(token.refresh_token, None);
+
+        let token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "Bearer", "scope": "streaming ugc-image-upload", "expires_in": 3600}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            token.scope,
+            vec![SpotifyScope::Streaming, SpotifyScope::UgcImageUpload]
+        );
+    }
+
+    #[test]
+    fn test_token_keeps_unknown_fields() {
+        let token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "Bearer", "expires_in": 3600, "id_token": "eyJhbGci", "grant": {"version": 2}}"#,
+        )
+        .unwrap();
+        assert_eq!(token.extra["id_token"], "eyJhbGci");
+
+        let json = serde_json::to_value(&token).unwrap();
+        assert_eq!(json["grant"]["version"], 2);
+        assert_eq!(serde_json::from_value::<SpotifyToken>(json).unwrap(), token);
+
+        let cache = toml::to_string(&CachedToken::from(token.clone())).unwrap();
+        let cached: CachedToken = toml::from_str(&cache).unwrap();
+        assert_eq!(SpotifyToken::from(cached).extra, token.extra);
+    }
+
+    #[test]
+    fn test_token_expires_in_string_or_float() {
+        for expires_in in [r#""3600""#, "3600.0", "3600.7", r#"" 3600.0 ""#] {
+            let token: SpotifyToken = serde_json::from_str(&format!(
+                r#"{{"access_token": "a", "token_type": "Bearer", "expires_in": {}}}"#,
+                expires_in
+            ))
+            .unwrap();
+            assert_eq!(token.expires_in, 3600, "{}", expires_in);
+        }
+
+        for expires_in in [r#""soon""#, "-1", "null"] {
+            let result = serde_json::from_str::<SpotifyToken>(&format!(
+                r#"{{"access_token": "a", "token_type": "Bearer", "expires_in": {}}}"#,
+                expires_in
+            ));
+            assert!(result.is_err(), "{}", expires_in);
+        }
+    }
+
+    #[test]
+    fn test_token_type_case_insensitive() {
+        let token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "bearer", "expires_in": 3600}"#,
+        )
+        .unwrap();
+        assert_eq!(token.token_type, TokenType::Bearer);
+        assert_eq!(
+            serde_json::to_value(&token).unwrap()["token_type"],
+            "Bearer"
+        );
+
+        let token_type: TokenType = serde_json::from_str(r#""MAC""#).unwrap();
+        assert_eq!(token_type, TokenType::Other("MAC".to_string()));
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let mut token = SpotifyToken::fixture().build_with(&clock);
+        assert_eq!(token.expires_at, Some(1_600_003_600));
+        assert_eq!(token.lifetime(), std::time::Duration::from_secs(3600));
+        assert!(!token.is_expired_with(&clock));
+
+        let minute = std::time::Duration::from_secs(60);
+        clock.advance(Duration::seconds(3500));
+        assert!(!token.expires_within_with(minute, &clock));
+        clock.advance(Duration::seconds(40));
+        assert!(token.expires_within_with(minute, &clock));
+        assert!(!token.is_expired_with(&clock));
+
+        clock.advance(Duration::seconds(60));
+        assert!(token.is_expired_with(&clock));
+
+        token.expires_at = None;
+        assert!(token.is_expired_with(&clock));
+    }
+
+    #[test]
+    fn test_token_display() {
+        let mut token = SpotifyToken::fixture()
+            .scope(vec![SpotifyScope::Streaming])
+            .build();
+        token.expires_at = None;
+        assert_eq!(token.to_string(), "Bearer token, 1 scope, expiry unknown");
+
+        token.expires_at = Some(datetime_to_timestamp(3500));
+        assert_eq!(token.to_string(), "Bearer token, 1 scope, expires in 58m");
+
+        token.expires_at = Some(datetime_to_timestamp(0) - 1);
+        assert_eq!(token.to_string(), "Bearer token, 1 scope, expired");
+
+        let text = format!("{}", token);
+        assert!(!text.contains(&token.access_token));
+        assert!(!text.contains(token.refresh_token.as_deref().unwrap()));
+    }
+
+    #[test]
+    fn test_token_expiry_timestamps() {
+        let clock = MockClock::new(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let mut token: SpotifyToken = serde_json::from_str(
+            r#"{"access_token": "a", "token_type": "Bearer", "scope": "", "expires_in": 3600, "refresh_token": "r"}"#,
+        )
+        .unwrap();
+        token.update_expires_at(&clock);
+
+        // The token itself only knows the wall clock, see the TokenManager for the
+        // monotonic clock.
+        clock.set(Utc.timestamp_opt(1_600_003_600, 0).unwrap());
+        assert!(token.is_expired_with(&clock));
+        assert_eq!(
+            token.expires_at_system_time(),
+            Some(UNIX_EPOCH + std::time::Duration::from_secs(1_600_003_600))
+        );
+        assert_eq!(
+            token.expires_at_datetime(),
+            Some(Utc.timestamp_opt(1_600_003_600, 0).unwrap())
+        );
+    }
 }
@@ -1,12 +1,18 @@
-use crate::{error::*, SpotifyCallback, SpotifyToken};
-use chrono::{DateTime, Utc};
+use crate::{
+    error::*, AppClient, Clock, Endpoints, HttpClient, HttpResponse, SpotifyCallback, SpotifyToken,
+    SystemClock, TokenRequest,
+};
+#[cfg(feature = "rand")]
 use rand::{self, Rng};
-use snafu::ResultExt;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
 use url::Url;
 
-const SPOTIFY_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
-
 /// Convert date and time to a unix timestamp.
 ///
 /// # Example
@@ -17,8 +23,7 @@ const SPOTIFY_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
 /// let timestamp = datetime_to_timestamp(3600);
 /// ```
 pub fn datetime_to_timestamp(elapsed: u32) -> i64 {
-    let utc: DateTime<Utc> = Utc::now();
-    utc.timestamp() + i64::from(elapsed)
+    SystemClock.timestamp_in(elapsed)
 }
 
 /// Generate a random alphanumeric string with a given length.
@@ -30,62 +35,511 @@ pub fn datetime_to_timestamp(elapsed: u32) -> i64 {
 /// # use spotify_oauth::generate_random_string;
 /// let timestamp = generate_random_string(20);
 /// ```
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+#[cfg(feature = "rand")]
 pub fn generate_random_string(length: usize) -> String {
+    generate_random_string_with(&mut rand::thread_rng(), length)
+}
+
+/// Generate a random alphanumeric string with a given length using the given random number generator.
+///
+/// This allows deterministic strings in tests, or forcing a specific generator such as ``OsRng``.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::generate_random_string_with;
+/// use rand::{rngs::StdRng, SeedableRng};
+///
+/// let state = generate_random_string_with(&mut StdRng::seed_from_u64(42), 20);
+/// # assert_eq!(state, generate_random_string_with(&mut StdRng::seed_from_u64(42), 20));
+/// ```
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+#[cfg(feature = "rand")]
+pub fn generate_random_string_with<R: Rng + ?Sized>(rng: &mut R, length: usize) -> String {
     String::from_utf8_lossy(
-        &rand::thread_rng()
-            .sample_iter(&rand::distributions::Alphanumeric)
+        &rng.sample_iter(&rand::distributions::Alphanumeric)
             .take(length)
             .collect::<Vec<_>>(),
     )
     .to_string()
 }
 
+/// Characters allowed in a PKCE code verifier (RFC 7636, section 4.1).
+#[cfg(feature = "rand")]
+const CODE_VERIFIER_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+
+/// Generate a PKCE code verifier with a given length.
+///
+/// # Panics
+///
+/// Panics if ``length`` is not within the 43 to 128 characters allowed by RFC 7636.
+///
+/// # Example
+///
+/// ```
+/// # use spotify_oauth::{code_challenge_s256, generate_code_verifier};
+/// let verifier = generate_code_verifier(64);
+/// let challenge = code_challenge_s256(&verifier);
+/// # assert_eq!(verifier.len(), 64);
+/// ```
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+#[cfg(feature = "rand")]
+pub fn generate_code_verifier(length: usize) -> String {
+    generate_code_verifier_with(&mut rand::thread_rng(), length)
+}
+
+/// Generate a PKCE code verifier with a given length using the given random number generator.
+///
+/// # Panics
+///
+/// Panics if ``length`` is not within the 43 to 128 characters allowed by RFC 7636.
+///
+/// Only available with the ``rand`` feature, which is enabled by default.
+#[cfg(feature = "rand")]
+pub fn generate_code_verifier_with<R: Rng + ?Sized>(rng: &mut R, length: usize) -> String {
+    assert!(
+        (43..=128).contains(&length),
+        "PKCE code verifiers must be between 43 and 128 characters long"
+    );
+
+    (0..length)
+        .map(|_| CODE_VERIFIER_CHARSET[rng.gen_range(0..CODE_VERIFIER_CHARSET.len())] as char)
+        .collect()
+}
+
+/// Derive the ``S256`` PKCE code challenge of a code verifier.
+///
+/// The challenge is the unpadded base64url encoded SHA-256 digest of the verifier.
+pub fn code_challenge_s256(verifier: &str) -> String {
+    base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+/// Write a file holding tokens, e.g. a token cache or a ``.env`` file.
+///
+/// The contents go to a temporary file next to ``path`` which then replaces it, so a crash never
+/// leaves a partly written file behind. On Unix the file is only readable by its owner (mode
+/// 0600), also when it replaces a file which was readable by others.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::write_private_file;
+/// write_private_file("token.json", r#"{"version": 2, "access_token": "NgCXRK"}"#).unwrap();
+/// ```
+pub fn write_private_file(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let path = path.as_ref();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = PathBuf::from(temp);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let result = options
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
 /// Converts the Spotify Callback object into a Spotify Token object.
+///
+/// Only available with the ``surf`` feature, which is enabled by default.
+#[cfg(feature = "surf")]
 pub async fn convert_callback_into_token(
     callback: SpotifyCallback,
     client_id: String,
     client_secret: String,
     redirect_uri: Url,
 ) -> SpotifyResult<SpotifyToken> {
-    let mut payload: HashMap<String, String> = HashMap::new();
-    payload.insert("grant_type".to_owned(), "authorization_code".to_owned());
-    payload.insert(
-        "code".to_owned(),
-        match callback.code {
-            None => {
-                return Err(SpotifyError::TokenFailure {
-                    context: "Spotify callback code failed to parse.",
-                })
-            }
-            Some(x) => x,
-        },
+    convert_callback_into_token_with(
+        &crate::SurfClient::default(),
+        &Endpoints::default(),
+        callback,
+        client_id,
+        client_secret,
+        redirect_uri,
+    )
+    .await
+}
+
+/// Converts the Spotify Callback object into a Spotify Token object using the given HTTP client and endpoints.
+///
+/// See [`AppClient::exchange_callback`] for a variant which does not take ownership of the configuration.
+pub async fn convert_callback_into_token_with<C: HttpClient + ?Sized>(
+    http: &C,
+    endpoints: &Endpoints,
+    callback: SpotifyCallback,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: Url,
+) -> SpotifyResult<SpotifyToken> {
+    AppClient::new(client_id, client_secret)
+        .with_endpoints(endpoints.clone())
+        .exchange_callback(http, &callback, &redirect_uri)
+        .await
+}
+
+/// Request a new access token using the refresh token of a previous authorization.
+///
+/// Spotify may omit the refresh token from the response, in that case the given refresh token is kept.
+///
+/// If Spotify rejects the refresh token (it was revoked or the user removed the application)
+/// [`SpotifyError::NeedsReauthorization`] is returned, the cached token should then be discarded
+/// and the authorization flow restarted.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::refresh_access_token;
+/// # #[async_std::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// let token = refresh_access_token("NgAagAHfVxDkSvCUm_SHo".into(), "00000000000".into(), "secret".into()).await?;
+/// # Ok(()) }
+/// ```
+///
+/// Only available with the ``surf`` feature, which is enabled by default.
+#[cfg(feature = "surf")]
+pub async fn refresh_access_token(
+    refresh_token: String,
+    client_id: String,
+    client_secret: String,
+) -> SpotifyResult<SpotifyToken> {
+    refresh_access_token_with(
+        &crate::SurfClient::default(),
+        &Endpoints::default(),
+        refresh_token,
+        client_id,
+        client_secret,
+    )
+    .await
+}
+
+/// Request a new access token using the refresh token of a previous authorization, the given
+/// HTTP client and endpoints.
+///
+/// See [`refresh_access_token`] for details and [`AppClient::refresh`] for a variant which does
+/// not take ownership of the configuration.
+pub async fn refresh_access_token_with<C: HttpClient + ?Sized>(
+    http: &C,
+    endpoints: &Endpoints,
+    refresh_token: String,
+    client_id: String,
+    client_secret: String,
+) -> SpotifyResult<SpotifyToken> {
+    AppClient::new(client_id, client_secret)
+        .with_endpoints(endpoints.clone())
+        .refresh(http, &refresh_token)
+        .await
+}
+
+/// Send a token request and interpret the response with ``finish``, see [`crate::protocol`].
+pub(crate) async fn execute<C, T, F>(
+    http: &C,
+    mut request: TokenRequest,
+    finish: F,
+) -> SpotifyResult<T>
+where
+    C: HttpClient + ?Sized,
+    F: FnOnce(&HttpResponse) -> SpotifyResult<T>,
+{
+    #[cfg(feature = "log")]
+    log::debug!(
+        "requesting token from {} (grant_type={})",
+        request.url,
+        request.form_field("grant_type").unwrap_or_default()
     );
-    payload.insert("redirect_uri".to_owned(), redirect_uri.to_string());
 
-    // Form authorisation header.
-    let auth_value = base64::encode(&format!("{}:{}", client_id, client_secret));
+    #[cfg(feature = "otel")]
+    let cx = crate::otel::start(&request);
 
     // POST the request.
-    let mut response = surf::post(SPOTIFY_TOKEN_URL)
-        .header("Authorization", format!("Basic {}", auth_value))
-        .body(surf::Body::from_form(&payload).unwrap())
-        .send()
+    let response = http.fetch_token(&mut request);
+    #[cfg(feature = "otel")]
+    let response = opentelemetry::trace::FutureExt::with_context(response, cx.clone());
+    let response = response.await;
+
+    #[cfg(feature = "otel")]
+    let status = response.as_ref().ok().map(|x| x.status);
+
+    let result = match response {
+        Ok(response) => {
+            #[cfg(feature = "log")]
+            log::debug!("token endpoint responded with status {}", response.status);
+
+            finish(&response)
+        }
+        Err(err) => Err(SpotifyError::Transport {
+            context: err.to_string(),
+        }),
+    };
+
+    #[cfg(feature = "log")]
+    if let Err(ref err) = result {
+        log_failure(err);
+    }
+
+    #[cfg(feature = "otel")]
+    crate::otel::finish(&cx, status, &result);
+
+    result
+}
+
+/// Whether retrying the failed token request may succeed: transport errors, rate limits and server
+/// errors of the accounts service.
+#[cfg(any(feature = "log", feature = "backoff"))]
+pub(crate) fn is_retryable(err: &SpotifyError) -> bool {
+    match err {
+        SpotifyError::Transport { .. } | SpotifyError::Timeout { .. } => true,
+        SpotifyError::Protocol {
+            status: Some(status),
+            ..
+        }
+        | SpotifyError::UnexpectedResponse { status, .. } => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
+
+/// Log a failed token request, at warn level if retrying it may succeed and at error level otherwise.
+#[cfg(feature = "log")]
+fn log_failure(err: &SpotifyError) {
+    let level = if is_retryable(err) {
+        log::Level::Warn
+    } else {
+        log::Level::Error
+    };
+
+    log::log!(level, "token request failed: {}", redact(&err.to_string()));
+}
+
+/// Query, form and JSON keys whose values must never end up in logs.
+const SECRET_KEYS: [&str; 5] = [
+    "code",
+    "access_token",
+    "refresh_token",
+    "client_secret",
+    "code_verifier",
+];
+
+/// Mask credentials contained in free-form text such as error messages or logged requests.
+///
+/// Values of ``code``, ``access_token``, ``refresh_token``, ``client_secret`` and ``code_verifier``
+/// in query strings, form bodies and JSON as well as ``Basic``/``Bearer`` credentials are replaced by ``***``.
+pub(crate) fn redact(text: &str) -> String {
+    let mut out = text.to_string();
+
+    for key in SECRET_KEYS {
+        out = mask_after(&out, &format!("{}=", key));
+        out = mask_after(&out, &format!("\"{}\":\"", key));
+        out = mask_after(&out, &format!("\"{}\": \"", key));
+    }
+
+    for scheme in ["Basic ", "Bearer "] {
+        out = mask_after(&out, scheme);
+    }
+
+    out
+}
+
+/// Replace the value following every standalone occurrence of ``marker`` with ``***``.
+fn mask_after(text: &str, marker: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(pos) = rest.find(marker) {
+        let standalone = marker.starts_with('"')
+            || !rest[..pos]
+                .chars()
+                .next_back()
+                .is_some_and(|x| x.is_alphanumeric() || x == '_');
+        let (head, tail) = rest.split_at(pos + marker.len());
+        out.push_str(head);
+
+        let end = tail
+            .find(|x: char| x.is_whitespace() || "&\"',;)}".contains(x))
+            .unwrap_or(tail.len());
+
+        if standalone && end > 0 {
+            out.push_str("***");
+            rest = &tail[end..];
+        } else {
+            rest = tail;
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHttpClient, MockResponse};
+
+    fn callback() -> SpotifyCallback {
+        SpotifyCallback::new(Some("AQD0yXvFEOvw".to_string()), None, "sN".to_string())
+    }
+
+    #[async_std::test]
+    async fn test_convert_callback_into_token() {
+        let http =
+            MockHttpClient::new().with(MockResponse::token("NgCXRK", Some("NgAagA"), "streaming"));
+        let redirect_uri = Url::parse("http://localhost:8888/callback").unwrap();
+
+        let token = convert_callback_into_token_with(
+            &http,
+            &Endpoints::default(),
+            callback(),
+            "id".into(),
+            "secret".into(),
+            redirect_uri,
+        )
         .await
-        .map_err(|err| SpotifyError::SurfError {
-            context: format!("{err:?}"),
-        })?;
+        .unwrap();
+        assert_eq!(token.access_token, "NgCXRK");
+        assert!(!token.is_expired());
 
-    // Read the response body.
-    let buf = response.body_string().await.unwrap();
+        let request = &http.requests()[0];
+        assert_eq!(request.header("authorization"), Some("Basic aWQ6c2VjcmV0"));
+        assert_eq!(request.form_field("code"), Some("AQD0yXvFEOvw"));
+        assert_eq!(
+            request.form_field("redirect_uri"),
+            Some("http://localhost:8888/callback")
+        );
+    }
 
-    if response.status().is_success() {
-        let mut token: SpotifyToken = serde_json::from_str(&buf).context(SerdeError)?;
-        token.expires_at = Some(datetime_to_timestamp(token.expires_in));
+    #[async_std::test]
+    async fn test_convert_callback_protocol_error() {
+        let http = MockHttpClient::new().with(MockResponse::oauth_error(
+            "invalid_client",
+            "Invalid client secret",
+        ));
+        let redirect_uri = Url::parse("http://localhost:8888/callback").unwrap();
 
-        return Ok(token);
+        let err = convert_callback_into_token_with(
+            &http,
+            &Endpoints::default(),
+            callback(),
+            "id".into(),
+            "secret".into(),
+            redirect_uri,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Spotify authorization failure: invalid_client (Invalid client secret)"
+        );
     }
 
-    Err(SpotifyError::TokenFailure {
-        context: "Failed to convert callback into token",
-    })
+    #[async_std::test]
+    async fn test_refresh_keeps_refresh_token() {
+        let http = MockHttpClient::new().with(MockResponse::token("NgCXRK", None, "streaming"));
+
+        let token = refresh_access_token_with(
+            &http,
+            &Endpoints::default(),
+            "NgAagA".into(),
+            "id".into(),
+            "secret".into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("NgAagA"));
+    }
+
+    #[async_std::test]
+    async fn test_refresh_needs_reauthorization() {
+        let http = MockHttpClient::new().with(MockResponse::oauth_error(
+            "invalid_grant",
+            "Refresh token revoked",
+        ));
+
+        let err = refresh_access_token_with(
+            &http,
+            &Endpoints::default(),
+            "NgAagA".into(),
+            "id".into(),
+            "secret".into(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SpotifyError::NeedsReauthorization { .. }));
+    }
+
+    #[async_std::test]
+    async fn test_refresh_transport_error() {
+        let http = MockHttpClient::new().with(MockResponse::transport_error("dns failure"));
+
+        let err = refresh_access_token_with(
+            &http,
+            &Endpoints::default(),
+            "NgAagA".into(),
+            "id".into(),
+            "secret".into(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.category(), crate::ErrorCategory::Transport);
+    }
+
+    #[test]
+    fn test_code_challenge_s256() {
+        // Test vector from RFC 7636, appendix B.
+        assert_eq!(
+            code_challenge_s256("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_code_verifier_charset() {
+        let verifier = generate_code_verifier(128);
+
+        assert_eq!(verifier.len(), 128);
+        assert!(verifier.bytes().all(|x| CODE_VERIFIER_CHARSET.contains(&x)));
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    #[should_panic]
+    fn test_code_verifier_too_short() {
+        generate_code_verifier(42);
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let text = r#"POST grant_type=authorization_code&code=AQD0yXvF&redirect_uri=x Authorization: Basic YWJjOmRlZg== {"access_token": "NgCXRK","token_type":"Bearer"}"#;
+
+        assert_eq!(
+            redact(text),
+            r#"POST grant_type=authorization_code&code=***&redirect_uri=x Authorization: Basic *** {"access_token": "***","token_type":"Bearer"}"#
+        );
+    }
+
+    #[test]
+    fn test_redact_ignores_suffixed_keys() {
+        assert_eq!(redact("error_code=400"), "error_code=400");
+    }
 }
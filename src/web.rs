@@ -0,0 +1,655 @@
+//! Running the redirect flow inside the browser, only available with the ``wasm`` feature.
+//!
+//! Single page apps send the user to Spotify with [`redirect_to_authorize`] and, once the browser
+//! returns to the app, read the result with [`callback_from_location`]. The functions use the
+//! ``window`` of the page and fail outside of a browser.
+//!
+//! The state of the [`SpotifyAuth`] has to survive the page reload, e.g. in the session storage,
+//! to be compared with the state of the callback. Never ship the client secret to the browser,
+//! exchange the code in a backend, or use the PKCE flow of [`PkceFlow`] which needs no secret.
+//! [`redirect_to_authorize_with_pkce`] and [`PendingAuthorization`] keep the state and the code
+//! verifier of custom flows across the redirect.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use spotify_oauth::{web, SpotifyAuth};
+//! # fn run(auth: SpotifyAuth) -> spotify_oauth::SpotifyResult<()> {
+//! match web::callback_from_location()? {
+//!     // Back from Spotify: verify the state and hand the code to the backend.
+//!     Some(callback) => {
+//!         web::clear_callback_from_location()?;
+//!     }
+//!     None => web::redirect_to_authorize(&auth)?,
+//! }
+//! # Ok(()) }
+//! ```
+
+use crate::{
+    code_challenge_s256, error::*, generate_code_verifier, generate_random_string, protocol,
+    store::is_usable, CachedToken, Clock, Endpoints, HttpResponse, RandomStateGenerator,
+    SpotifyAuth, SpotifyCallback, SpotifyScope, SpotifyToken, SystemClock, TokenRequest,
+    TokenStore,
+};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::{fmt, io, str::FromStr, sync::Arc};
+use url::{form_urlencoded, Url};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Location, Storage};
+
+mod indexed_db;
+#[cfg(feature = "leptos")]
+pub mod leptos;
+#[cfg(feature = "yew")]
+pub mod yew;
+
+pub use indexed_db::IndexedDbTokenStore;
+
+/// Map a JavaScript exception to an error.
+fn js_failure(err: JsValue) -> SpotifyError {
+    SpotifyError::BrowserFailure {
+        source: io::Error::other(format!("{:?}", err)),
+    }
+}
+
+fn window() -> SpotifyResult<web_sys::Window> {
+    web_sys::window().ok_or_else(|| js_failure(JsValue::from_str("no window available")))
+}
+
+fn location() -> SpotifyResult<Location> {
+    Ok(window()?.location())
+}
+
+/// Navigate the current page to the authorization URL.
+pub fn redirect_to_authorize(auth: &SpotifyAuth) -> SpotifyResult<()> {
+    location()?
+        .set_href(&auth.authorize_url()?)
+        .map_err(js_failure)
+}
+
+/// Navigate the current page to the PKCE authorization URL, with a fresh state and code verifier.
+///
+/// The state and the code verifier are kept in the session storage under ``key``, consume them
+/// with [`PendingAuthorization::consume`] when the browser returns. The state of ``auth`` is replaced.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{web, SpotifyAuth};
+/// # fn run(auth: SpotifyAuth) -> spotify_oauth::SpotifyResult<()> {
+/// match web::callback_from_location()? {
+///     Some(callback) => {
+///         web::clear_callback_from_location()?;
+///         let pending = web::PendingAuthorization::consume("login", &callback)?;
+///         // Exchange ``callback.code()`` with ``pending.code_verifier()``.
+///     }
+///     None => web::redirect_to_authorize_with_pkce(&auth, "login")?,
+/// }
+/// # Ok(()) }
+/// ```
+pub fn redirect_to_authorize_with_pkce(auth: &SpotifyAuth, key: &str) -> SpotifyResult<()> {
+    let pending = PendingAuthorization::generate();
+    let url = pending.authorize_url(auth)?;
+    pending.save(key)?;
+
+    location()?.set_href(&url).map_err(js_failure)
+}
+
+/// The callback contained in the URL of the current page, from its query or its fragment.
+///
+/// Yields ``None`` if the page was not loaded by a redirect from Spotify.
+pub fn callback_from_location() -> SpotifyResult<Option<SpotifyCallback>> {
+    let href = location()?.href().map_err(js_failure)?;
+
+    callback_from_href(&href)
+}
+
+/// Remove the callback parameters from the address bar, without reloading the page.
+///
+/// This keeps the single use authorization code out of the history and of bookmarks. Query
+/// parameters of the redirect URI itself, e.g. ``?env=dev``, stay.
+pub fn clear_callback_from_location() -> SpotifyResult<()> {
+    let window = window()?;
+    let href = window.location().href().map_err(js_failure)?;
+    let url = without_callback(&Url::parse(&href).context(UrlError)?);
+
+    window
+        .history()
+        .map_err(js_failure)?
+        .replace_state_with_url(&JsValue::NULL, "", Some(url.as_str()))
+        .map_err(js_failure)
+}
+
+/// Send a request to the token endpoint with the ``fetch`` API of the browser.
+///
+/// The token endpoint of Spotify allows cross origin requests of the PKCE flow.
+pub async fn fetch(request: &TokenRequest) -> SpotifyResult<HttpResponse> {
+    let transport = |err: JsValue| SpotifyError::Transport {
+        context: format!("{:?}", err),
+    };
+
+    let init = web_sys::RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&JsValue::from_str(request.body()));
+    let js_request =
+        web_sys::Request::new_with_str_and_init(&request.url, &init).map_err(transport)?;
+    let headers = js_request.headers();
+    headers
+        .set("Content-Type", TokenRequest::CONTENT_TYPE)
+        .map_err(transport)?;
+    for header in &request.headers {
+        headers
+            .set(header.name(), header.value())
+            .map_err(transport)?;
+    }
+
+    let response: web_sys::Response = JsFuture::from(window()?.fetch_with_request(&js_request))
+        .await
+        .map_err(transport)?
+        .dyn_into()
+        .map_err(transport)?;
+    let body = JsFuture::from(response.text().map_err(transport)?)
+        .await
+        .map_err(transport)?;
+
+    Ok(HttpResponse {
+        status: response.status(),
+        body: body.as_string().unwrap_or_default(),
+    })
+}
+
+/// The state of the login of a [`PkceFlow`], as shown by the UI.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthStatus {
+    /// No token is stored.
+    SignedOut,
+    /// The browser is redirected to Spotify or the code is being exchanged.
+    Pending,
+    /// A token is available.
+    SignedIn(SpotifyToken),
+    /// The last login failed.
+    Failed(String),
+}
+
+/// The state and code verifier of an authorization in progress, kept in the session storage across the redirect.
+///
+/// Single page apps lose their memory when the browser navigates to Spotify, so the code verifier
+/// has to be stored before the redirect. Each pending authorization is consumed once, together with
+/// the check of the state of the callback.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingAuthorization {
+    state: String,
+    code_verifier: String,
+}
+
+impl PendingAuthorization {
+    /// A fresh random state and code verifier.
+    pub fn generate() -> Self {
+        Self {
+            state: generate_random_string(20),
+            code_verifier: generate_code_verifier(64),
+        }
+    }
+
+    /// The state sent to Spotify.
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// The code verifier for the code exchange.
+    pub fn code_verifier(&self) -> &str {
+        &self.code_verifier
+    }
+
+    /// The PKCE authorization URL of ``auth`` with this state and the challenge of this verifier.
+    pub fn authorize_url(&self, auth: &SpotifyAuth) -> SpotifyResult<String> {
+        let auth = SpotifyAuth {
+            client_id: auth.client_id.clone(),
+            client_secret: auth.client_secret.clone(),
+            response_type: auth.response_type.clone(),
+            redirect_uri: auth.redirect_uri.clone(),
+            redirect_uris: auth.redirect_uris.clone(),
+            state: self.state.clone(),
+            state_generator: auth.state_generator.clone(),
+            scope: auth.scope.clone(),
+            show_dialog: auth.show_dialog,
+            endpoints: auth.endpoints.clone(),
+        };
+
+        auth.authorize_url_with_pkce(&code_challenge_s256(&self.code_verifier))
+    }
+
+    /// Keep the authorization in the session storage under ``key`` until the browser returns.
+    pub fn save(&self, key: &str) -> SpotifyResult<()> {
+        let value = serde_json::to_string(self).context(Deserialization)?;
+
+        session_storage()?.set_item(key, &value).map_err(js_failure)
+    }
+
+    /// Take the authorization stored under ``key`` and check it against the state of the callback.
+    ///
+    /// The stored authorization is removed in any case, so a callback cannot be replayed. Fails if
+    /// nothing is stored, e.g. because the login was started in another tab, or if the state differs.
+    pub fn consume(key: &str, callback: &SpotifyCallback) -> SpotifyResult<Self> {
+        let storage = session_storage()?;
+        let value = storage.get_item(key).map_err(js_failure)?;
+        storage.remove_item(key).map_err(js_failure)?;
+
+        let pending = value
+            .and_then(|x| serde_json::from_str::<Self>(&x).ok())
+            .ok_or(SpotifyError::CallbackFailure {
+                context: "No authorization is pending in this browser tab.",
+            })?;
+        if pending.state != callback.state() {
+            return Err(SpotifyError::CallbackFailure {
+                context: "The state does not match the pending authorization.",
+            });
+        }
+
+        Ok(pending)
+    }
+}
+
+/// The PKCE authorization flow of a single page app, the building block of the framework hooks.
+///
+/// [`PkceFlow::login`] keeps a fresh state and code verifier in the session storage and navigates
+/// to Spotify. When the browser returns, [`PkceFlow::resume`] verifies the callback, exchanges the
+/// code and keeps the token in the local storage, so it survives reloads.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{web::PkceFlow, SpotifyScope};
+/// # use url::Url;
+/// # async fn run() -> spotify_oauth::SpotifyResult<()> {
+/// let flow = PkceFlow::new("00000000000", Url::parse("https://app.example/").unwrap(), vec![SpotifyScope::Streaming]);
+///
+/// // On every page load.
+/// match flow.resume().await? {
+///     Some(token) => println!("Signed in: {}", token.access_token),
+///     None => flow.login()?,
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct PkceFlow {
+    client_id: String,
+    redirect_uri: Url,
+    scope: Vec<SpotifyScope>,
+    endpoints: Endpoints,
+    storage_key: String,
+    clock: Arc<dyn Clock>,
+}
+
+/// Flows are compared without their clocks.
+impl PartialEq for PkceFlow {
+    fn eq(&self, other: &Self) -> bool {
+        self.client_id == other.client_id
+            && self.redirect_uri == other.redirect_uri
+            && self.scope == other.scope
+            && self.endpoints == other.endpoints
+            && self.storage_key == other.storage_key
+    }
+}
+
+impl fmt::Debug for PkceFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PkceFlow")
+            .field("client_id", &self.client_id)
+            .field("redirect_uri", &self.redirect_uri)
+            .field("scope", &self.scope)
+            .field("endpoints", &self.endpoints)
+            .field("storage_key", &self.storage_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PkceFlow {
+    /// Create a flow for the given application, redirecting back to ``redirect_uri``.
+    pub fn new(client_id: impl Into<String>, redirect_uri: Url, scope: Vec<SpotifyScope>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            redirect_uri,
+            scope,
+            endpoints: Endpoints::default(),
+            storage_key: "spotify-oauth".to_string(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Stamp the ``expires_at`` of the token with ``clock`` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Use the given endpoints instead of the real Spotify Accounts service.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Prefix of the storage keys, to keep several flows on one origin apart.
+    ///
+    /// Defaults to ``spotify-oauth``.
+    pub fn with_storage_key(mut self, key: impl Into<String>) -> Self {
+        self.storage_key = key.into();
+        self
+    }
+
+    /// Remember a new state and code verifier and navigate to the authorization URL.
+    pub fn login(&self) -> SpotifyResult<()> {
+        let auth = SpotifyAuth {
+            client_id: self.client_id.clone(),
+            client_secret: String::new(),
+            response_type: "code".to_string(),
+            redirect_uri: self.redirect_uri.clone(),
+            redirect_uris: Vec::new(),
+            state: String::new(),
+            state_generator: Arc::new(RandomStateGenerator),
+            scope: self.scope.clone(),
+            show_dialog: false,
+            endpoints: self.endpoints.clone(),
+        };
+
+        redirect_to_authorize_with_pkce(&auth, &self.pending_key())
+    }
+
+    /// Finish a login if the page was loaded by the redirect from Spotify, otherwise return the stored token.
+    ///
+    /// The callback parameters are removed from the address bar. A callback whose state does not
+    /// match the pending login is rejected.
+    pub async fn resume(&self) -> SpotifyResult<Option<SpotifyToken>> {
+        let callback = match callback_from_location()? {
+            None => return Ok(self.token()),
+            Some(x) => x,
+        };
+        clear_callback_from_location()?;
+
+        let pending = PendingAuthorization::consume(&self.pending_key(), &callback)?;
+
+        let code = match (callback.code(), callback.error()) {
+            (Some(x), _) => x,
+            (None, error) => {
+                return Err(SpotifyError::Protocol {
+                    status: None,
+                    error: error.unwrap_or("access_denied").to_string(),
+                    description: None,
+                })
+            }
+        };
+
+        let request = protocol::pkce_exchange_request(
+            &self.endpoints,
+            &self.client_id,
+            code,
+            &self.redirect_uri,
+            pending.code_verifier(),
+        );
+        let token = protocol::finish_exchange(&fetch(&request).await?, &*self.clock)?;
+
+        self.store().save("token", &token)?;
+
+        Ok(Some(token))
+    }
+
+    /// The stored token, if it is still usable.
+    pub fn token(&self) -> Option<SpotifyToken> {
+        self.store().load("token").ok().flatten()
+    }
+
+    /// Forget the stored token.
+    pub fn logout(&self) -> SpotifyResult<()> {
+        self.store().remove("token")
+    }
+
+    fn pending_key(&self) -> String {
+        format!("{}.pending", self.storage_key)
+    }
+
+    /// The tokens are kept in the local storage, so they survive reloads and browser restarts.
+    fn store(&self) -> WebStorageTokenStore {
+        WebStorageTokenStore::local().with_prefix(&self.storage_key)
+    }
+}
+
+/// The transitions shared by the framework hooks.
+#[cfg(any(feature = "leptos", feature = "yew"))]
+impl PkceFlow {
+    async fn resume_status(&self) -> AuthStatus {
+        match self.resume().await {
+            Ok(Some(x)) => AuthStatus::SignedIn(x),
+            Ok(None) => AuthStatus::SignedOut,
+            Err(err) => AuthStatus::Failed(err.to_string()),
+        }
+    }
+
+    fn login_status(&self) -> AuthStatus {
+        match self.login() {
+            Ok(()) => AuthStatus::Pending,
+            Err(err) => AuthStatus::Failed(err.to_string()),
+        }
+    }
+
+    fn logout_status(&self) -> AuthStatus {
+        match self.logout() {
+            Ok(()) => AuthStatus::SignedOut,
+            Err(err) => AuthStatus::Failed(err.to_string()),
+        }
+    }
+}
+
+fn session_storage() -> SpotifyResult<Storage> {
+    WebStorage::Session.open()
+}
+
+/// A storage area of the Web Storage API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebStorage {
+    /// ``localStorage``, kept across browser restarts and shared by all tabs of the origin.
+    Local,
+    /// ``sessionStorage``, kept across reloads of a single tab.
+    Session,
+}
+
+impl WebStorage {
+    fn open(self) -> SpotifyResult<Storage> {
+        let storage = match self {
+            WebStorage::Local => window()?.local_storage(),
+            WebStorage::Session => window()?.session_storage(),
+        };
+
+        storage
+            .map_err(js_failure)?
+            .ok_or_else(|| js_failure(JsValue::from_str("web storage unavailable")))
+    }
+}
+
+/// A [`TokenStore`] persisting tokens as JSON in the Web Storage of the browser.
+///
+/// The expiry is stored as a timestamp, so the remaining lifetime is correct after a reload.
+/// Tokens which expired and cannot be refreshed, as well as entries which cannot be parsed, are
+/// removed when they are loaded.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{web::WebStorageTokenStore, TokenStore};
+/// let store = WebStorageTokenStore::local().with_prefix("my-app");
+/// if let Some(token) = store.load("alice").unwrap() {
+///     println!("{}", token.access_token);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebStorageTokenStore {
+    storage: WebStorage,
+    prefix: String,
+}
+
+impl WebStorageTokenStore {
+    /// A store over the given storage area, with the key prefix ``spotify-oauth``.
+    pub fn new(storage: WebStorage) -> Self {
+        Self {
+            storage,
+            prefix: "spotify-oauth".to_string(),
+        }
+    }
+
+    /// A store over ``localStorage``.
+    pub fn local() -> Self {
+        Self::new(WebStorage::Local)
+    }
+
+    /// A store over ``sessionStorage``.
+    pub fn session() -> Self {
+        Self::new(WebStorage::Session)
+    }
+
+    /// Prefix of the storage keys, the token of ``key`` is stored as ``<prefix>.<key>``.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn item_key(&self, key: &str) -> String {
+        format!("{}.{}", self.prefix, key)
+    }
+}
+
+impl TokenStore for WebStorageTokenStore {
+    fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        let storage = self.storage.open()?;
+        let item_key = self.item_key(key);
+        let value = match storage.get_item(&item_key).map_err(js_failure)? {
+            None => return Ok(None),
+            Some(x) => x,
+        };
+
+        match serde_json::from_str::<CachedToken>(&value).map(SpotifyToken::from) {
+            Ok(token) if is_usable(&token) => Ok(Some(token)),
+            _ => {
+                storage.remove_item(&item_key).map_err(js_failure)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        let value =
+            serde_json::to_string(&CachedToken::from(token.clone())).context(Deserialization)?;
+
+        self.storage
+            .open()?
+            .set_item(&self.item_key(key), &value)
+            .map_err(js_failure)
+    }
+
+    fn remove(&self, key: &str) -> SpotifyResult<()> {
+        self.storage
+            .open()?
+            .remove_item(&self.item_key(key))
+            .map_err(js_failure)
+    }
+}
+
+/// The page URL without the callback parameters in its query and fragment.
+fn without_callback(url: &Url) -> Url {
+    let is_callback = |key: &str| matches!(key, "code" | "error" | "state");
+    let mut out = url.clone();
+
+    let kept: Vec<_> = url.query_pairs().filter(|x| !is_callback(&x.0)).collect();
+    if kept.len() != url.query_pairs().count() {
+        out.set_query(None);
+        if !kept.is_empty() {
+            out.query_pairs_mut().extend_pairs(kept);
+        }
+    }
+
+    if let Some(fragment) = url.fragment() {
+        if form_urlencoded::parse(fragment.as_bytes()).any(|x| is_callback(&x.0)) {
+            out.set_fragment(None);
+        }
+    }
+
+    out
+}
+
+/// Parse a page URL into a callback, ``None`` if it contains no callback parameters.
+pub(crate) fn callback_from_href(href: &str) -> SpotifyResult<Option<SpotifyCallback>> {
+    let url = Url::parse(href).context(UrlError)?;
+    let fragment = url.fragment().unwrap_or_default();
+    let has_callback = url
+        .query_pairs()
+        .chain(form_urlencoded::parse(fragment.as_bytes()))
+        .any(|x| matches!(x.0.as_ref(), "code" | "error" | "state"));
+
+    if !has_callback {
+        return Ok(None);
+    }
+
+    SpotifyCallback::from_str(href).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_from_href() {
+        assert_eq!(callback_from_href("https://app.example/").unwrap(), None);
+
+        let callback = callback_from_href("https://app.example/#error=access_denied&state=sN")
+            .unwrap()
+            .unwrap();
+        assert_eq!(callback.error(), Some("access_denied"));
+        assert_eq!(callback.state(), "sN");
+    }
+
+    #[test]
+    fn test_without_callback_keeps_redirect_query() {
+        for (href, expected) in [
+            (
+                "https://app.example/cb?env=dev&code=AQD0&state=sN",
+                "https://app.example/cb?env=dev",
+            ),
+            (
+                "https://app.example/cb?code=AQD0&state=sN",
+                "https://app.example/cb",
+            ),
+            (
+                "https://app.example/#error=access_denied&state=sN",
+                "https://app.example/",
+            ),
+            (
+                "https://app.example/cb?env=dev#top",
+                "https://app.example/cb?env=dev#top",
+            ),
+        ] {
+            let url = without_callback(&Url::parse(href).unwrap());
+            assert_eq!(url.as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_pending_authorization_url() {
+        let auth = SpotifyAuth::new(
+            "00000000000".into(),
+            String::new(),
+            "code".into(),
+            "https://app.example/".into(),
+            vec![SpotifyScope::Streaming],
+            false,
+        );
+        let pending = PendingAuthorization::generate();
+        let url = Url::parse(&pending.authorize_url(&auth).unwrap()).unwrap();
+        let query: Vec<_> = url.query_pairs().into_owned().collect();
+
+        assert!(query.contains(&("state".into(), pending.state().into())));
+        assert!(query.contains(&(
+            "code_challenge".into(),
+            code_challenge_s256(pending.code_verifier())
+        )));
+    }
+}
@@ -0,0 +1,167 @@
+use super::{js_failure, window};
+use crate::{error::*, store::is_usable, AsyncTokenStore, CachedToken, SpotifyToken};
+use async_trait::async_trait;
+use js_sys::{Array, Function, Promise};
+use snafu::ResultExt;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+/// An [`AsyncTokenStore`] persisting tokens in an IndexedDB database of the browser.
+///
+/// Unlike the Web Storage of [`WebStorageTokenStore`](super::WebStorageTokenStore), IndexedDB does
+/// not block the page and has no small size limit, so apps can keep the tokens of many accounts.
+/// The tokens are stored as JSON under their key. Tokens which expired and cannot be refreshed,
+/// as well as entries which cannot be parsed, are removed when they are loaded.
+///
+/// # Example
+///
+/// ```no_run
+/// # use spotify_oauth::{web::IndexedDbTokenStore, AsyncTokenStore, SpotifyResult};
+/// # async fn run() -> SpotifyResult<()> {
+/// let store = IndexedDbTokenStore::default();
+/// for account in store.keys().await? {
+///     if let Some(token) = store.load(&account).await? {
+///         println!("{}: {}", account, token.access_token);
+///     }
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedDbTokenStore {
+    database: String,
+    object_store: String,
+}
+
+impl Default for IndexedDbTokenStore {
+    fn default() -> Self {
+        Self::new("spotify-oauth")
+    }
+}
+
+impl IndexedDbTokenStore {
+    /// A store over the given database, which is created on first use.
+    pub fn new(database: impl Into<String>) -> Self {
+        Self {
+            database: database.into(),
+            object_store: "tokens".to_string(),
+        }
+    }
+
+    /// The keys of all stored tokens, e.g. to list the accounts of the app.
+    pub async fn keys(&self) -> SpotifyResult<Vec<String>> {
+        let keys = self
+            .request(IdbTransactionMode::Readonly, |store| store.get_all_keys())
+            .await?;
+
+        Ok(Array::from(&keys)
+            .iter()
+            .filter_map(|key| key.as_string())
+            .collect())
+    }
+
+    async fn open(&self) -> SpotifyResult<IdbDatabase> {
+        let factory = window()?
+            .indexed_db()
+            .map_err(js_failure)?
+            .ok_or_else(|| js_failure(JsValue::from_str("IndexedDB unavailable")))?;
+        let open = factory
+            .open_with_u32(&self.database, 1)
+            .map_err(js_failure)?;
+
+        // Runs once when the database is created.
+        let object_store = self.object_store.clone();
+        let request = open.clone();
+        let upgrade = Closure::once_into_js(move || {
+            if let Ok(db) = request.result() {
+                let _ = db
+                    .unchecked_into::<IdbDatabase>()
+                    .create_object_store(&object_store);
+            }
+        });
+        open.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+
+        Ok(complete(&open).await?.unchecked_into())
+    }
+
+    async fn request(
+        &self,
+        mode: IdbTransactionMode,
+        f: impl FnOnce(&IdbObjectStore) -> Result<IdbRequest, JsValue>,
+    ) -> SpotifyResult<JsValue> {
+        let db = self.open().await?;
+        let store = db
+            .transaction_with_str_and_mode(&self.object_store, mode)
+            .and_then(|tx| tx.object_store(&self.object_store))
+            .map_err(js_failure)?;
+        let request = f(&store).map_err(js_failure)?;
+        let result = complete(&request).await;
+        db.close();
+
+        result
+    }
+}
+
+/// Wait for the success or error event of the request.
+async fn complete(request: &IdbRequest) -> SpotifyResult<JsValue> {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let succeeded = request.clone();
+        let on_success = Closure::once_into_js(move || {
+            let _ = resolve.call1(&JsValue::NULL, &succeeded.result().unwrap_or_default());
+        });
+        let failed = request.clone();
+        let on_error = Closure::once_into_js(move || {
+            let error = failed.error().ok().flatten().map(JsValue::from);
+            let _ = reject.call1(&JsValue::NULL, &error.unwrap_or_default());
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+
+    JsFuture::from(promise).await.map_err(js_failure)
+}
+
+#[async_trait(?Send)]
+impl AsyncTokenStore for IndexedDbTokenStore {
+    async fn load(&self, key: &str) -> SpotifyResult<Option<SpotifyToken>> {
+        let key = JsValue::from_str(key);
+        let value = self
+            .request(IdbTransactionMode::Readonly, |store| store.get(&key))
+            .await?;
+        let value = match value.as_string() {
+            None => return Ok(None),
+            Some(x) => x,
+        };
+
+        match serde_json::from_str::<CachedToken>(&value).map(SpotifyToken::from) {
+            Ok(token) if is_usable(&token) => Ok(Some(token)),
+            _ => {
+                self.request(IdbTransactionMode::Readwrite, |store| store.delete(&key))
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn save(&self, key: &str, token: &SpotifyToken) -> SpotifyResult<()> {
+        let value =
+            serde_json::to_string(&CachedToken::from(token.clone())).context(Deserialization)?;
+        let (key, value) = (JsValue::from_str(key), JsValue::from_str(&value));
+
+        self.request(IdbTransactionMode::Readwrite, |store| {
+            store.put_with_key(&value, &key)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> SpotifyResult<()> {
+        let key = JsValue::from_str(key);
+
+        self.request(IdbTransactionMode::Readwrite, |store| store.delete(&key))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Spotify login for Leptos apps, only available with the ``leptos`` feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use leptos::prelude::*;
+//! use spotify_oauth::{web::{leptos::use_spotify_auth, AuthStatus, PkceFlow}, SpotifyScope};
+//! use url::Url;
+//!
+//! #[component]
+//! fn Login() -> impl IntoView {
+//!     let auth = use_spotify_auth(PkceFlow::new(
+//!         "00000000000",
+//!         Url::parse("https://app.example/").unwrap(),
+//!         vec![SpotifyScope::Streaming],
+//!     ));
+//!
+//!     move || match auth.status().get() {
+//!         AuthStatus::SignedIn(_) => view! { <button on:click=move |_| auth.logout()>"Log out"</button> }.into_any(),
+//!         AuthStatus::Pending => view! { <p>"Signing in ..."</p> }.into_any(),
+//!         _ => view! { <button on:click=move |_| auth.login()>"Log in with Spotify"</button> }.into_any(),
+//!     }
+//! }
+//! ```
+
+use super::{AuthStatus, PkceFlow};
+use ::leptos::{prelude::*, task::spawn_local};
+
+/// The handle returned by [`use_spotify_auth`].
+#[derive(Clone, Copy)]
+pub struct SpotifyAuthHandle {
+    flow: StoredValue<PkceFlow>,
+    status: RwSignal<AuthStatus>,
+}
+
+impl SpotifyAuthHandle {
+    /// The current state of the login.
+    pub fn status(&self) -> ReadSignal<AuthStatus> {
+        self.status.read_only()
+    }
+
+    /// Navigate to Spotify to log in.
+    pub fn login(&self) {
+        self.status
+            .set(self.flow.with_value(PkceFlow::login_status));
+    }
+
+    /// Forget the stored token.
+    pub fn logout(&self) {
+        self.status
+            .set(self.flow.with_value(PkceFlow::logout_status));
+    }
+}
+
+/// Run the PKCE flow of a [`PkceFlow`] in a component.
+///
+/// The flow finishes a login the page was redirected back from, or loads the stored token, and
+/// updates the status signal with the resulting [`AuthStatus`].
+pub fn use_spotify_auth(flow: PkceFlow) -> SpotifyAuthHandle {
+    let status = RwSignal::new(AuthStatus::Pending);
+    let handle = SpotifyAuthHandle {
+        flow: StoredValue::new(flow.clone()),
+        status,
+    };
+
+    spawn_local(async move { status.set(flow.resume_status().await) });
+
+    handle
+}
@@ -0,0 +1,70 @@
+//! Spotify login for Yew apps, only available with the ``yew`` feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use spotify_oauth::{web::{yew::use_spotify_auth, AuthStatus, PkceFlow}, SpotifyScope};
+//! use url::Url;
+//! use yew::prelude::*;
+//!
+//! #[function_component]
+//! fn Login() -> Html {
+//!     let auth = use_spotify_auth(PkceFlow::new(
+//!         "00000000000",
+//!         Url::parse("https://app.example/").unwrap(),
+//!         vec![SpotifyScope::Streaming],
+//!     ));
+//!
+//!     match auth.status() {
+//!         AuthStatus::SignedIn(_) => html! { <button onclick={move |_| auth.logout()}>{ "Log out" }</button> },
+//!         AuthStatus::Pending => html! { <p>{ "Signing in ..." }</p> },
+//!         _ => html! { <button onclick={move |_| auth.login()}>{ "Log in with Spotify" }</button> },
+//!     }
+//! }
+//! ```
+
+use super::{AuthStatus, PkceFlow};
+use ::yew::{hook, platform::spawn_local, use_effect_with, use_state, UseStateHandle};
+
+/// The handle returned by [`use_spotify_auth`].
+#[derive(Clone)]
+pub struct UseSpotifyAuthHandle {
+    flow: PkceFlow,
+    status: UseStateHandle<AuthStatus>,
+}
+
+impl UseSpotifyAuthHandle {
+    /// The current state of the login.
+    pub fn status(&self) -> &AuthStatus {
+        &self.status
+    }
+
+    /// Navigate to Spotify to log in.
+    pub fn login(&self) {
+        self.status.set(self.flow.login_status());
+    }
+
+    /// Forget the stored token.
+    pub fn logout(&self) {
+        self.status.set(self.flow.logout_status());
+    }
+}
+
+/// Run the PKCE flow of a [`PkceFlow`] in a component.
+///
+/// On mount the hook finishes a login the page was redirected back from, or loads the stored
+/// token, and re-renders the component with the resulting [`AuthStatus`].
+#[hook]
+pub fn use_spotify_auth(flow: PkceFlow) -> UseSpotifyAuthHandle {
+    let status = use_state(|| AuthStatus::Pending);
+
+    {
+        let status = status.clone();
+        let flow = flow.clone();
+        use_effect_with((), move |_| {
+            spawn_local(async move { status.set(flow.resume_status().await) });
+        });
+    }
+
+    UseSpotifyAuthHandle { flow, status }
+}
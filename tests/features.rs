@@ -0,0 +1,21 @@
+//! Compile tests guarding that the HTTP backends are independent, additive features and that the
+//! types build without any of them.
+
+#[test]
+fn test_feature_combinations() {
+    let t = trybuild::TestCases::new();
+
+    t.pass("tests/features/no_backend.rs");
+    if cfg!(all(feature = "surf", feature = "reqwest", feature = "ureq")) {
+        t.pass("tests/features/all_backends.rs");
+    }
+    if cfg!(not(feature = "rand")) {
+        t.compile_fail("tests/features/rand_disabled.rs");
+    }
+    if cfg!(not(feature = "reqwest")) {
+        t.compile_fail("tests/features/reqwest_disabled.rs");
+    }
+    if cfg!(not(feature = "ureq")) {
+        t.compile_fail("tests/features/ureq_disabled.rs");
+    }
+}
@@ -0,0 +1,10 @@
+// Every backend can be enabled at the same time.
+use spotify_oauth::{HttpClient, ReqwestClient, SurfClient, UreqClient};
+
+fn backend<C: HttpClient>(_: C) {}
+
+fn main() {
+    backend(SurfClient::new());
+    backend(ReqwestClient::new());
+    backend(UreqClient::new());
+}
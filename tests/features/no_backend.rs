@@ -0,0 +1,34 @@
+// The types and the sans-IO core build with any combination of features, including none.
+use spotify_oauth::{
+    protocol, AppClient, Endpoints, HttpResponse, SpotifyAuth, SpotifyCallback, SpotifyError,
+    SpotifyScope, SpotifyToken, SystemClock,
+};
+use std::{str::FromStr, sync::Arc};
+use url::Url;
+
+fn main() {
+    let redirect_uri = Url::parse("http://localhost:8000/callback").unwrap();
+    let auth = SpotifyAuth {
+        client_id: "id".into(),
+        client_secret: "secret".into(),
+        response_type: "code".into(),
+        redirect_uri: redirect_uri.clone(),
+        redirect_uris: Vec::new(),
+        state: "state".into(),
+        state_generator: Arc::new(|| "state".to_string()),
+        scope: vec![SpotifyScope::Streaming],
+        show_dialog: false,
+        endpoints: Endpoints::default(),
+    };
+    let _url = auth.authorize_url().unwrap();
+    let callback = SpotifyCallback::from_str("http://localhost:8000/callback?code=code&state=state").unwrap();
+
+    let app = AppClient::from(&auth);
+    let _request = protocol::exchange_request(&app, callback.code().unwrap(), &redirect_uri);
+    let response = HttpResponse {
+        status: 400,
+        body: String::new(),
+    };
+    let result: Result<SpotifyToken, SpotifyError> = protocol::finish_exchange(&response, &SystemClock);
+    assert!(result.is_err());
+}
@@ -0,0 +1,5 @@
+use spotify_oauth::generate_random_string;
+
+fn main() {
+    let _state = generate_random_string(20);
+}
@@ -0,0 +1,5 @@
+use spotify_oauth::ReqwestClient;
+
+fn main() {
+    let _client = ReqwestClient::new();
+}
@@ -0,0 +1,5 @@
+use spotify_oauth::UreqClient;
+
+fn main() {
+    let _client = UreqClient::new();
+}